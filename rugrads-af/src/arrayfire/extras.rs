@@ -1,5 +1,6 @@
 use rugrads;
 use rugrads::{Node, VecJacProduct, Expression, LeafVar};
+use rugrads::serial::{Encode, Json};
 
 use libaf;
 use libaf::{Array, Dim4};
@@ -8,12 +9,18 @@ use ::{Context, Container};
 use super::utils::repeat_to_match_dims;
 
 #[derive(Clone)]
-pub struct LogSumExpVJP(Array);
+pub struct LogSumExpVJP(Array, Option<i32>);
 
 impl VecJacProduct<Array> for LogSumExpVJP {
     fn vjp(&self, g: Array, node: &Node<Array>, _: &Node<Array>, _: usize) -> Array {
         let output_dims = self.0.dims();
-        let g_tiled = repeat_to_match_dims(&g, output_dims);
+        // The output is repeated along the reduced dimensions so every
+        // copy contributes to the gradient
+        let g_summed = match self.1 {
+            Some(dim) => libaf::sum(&g, dim),
+            None => libaf::constant(libaf::sum_all(&g).0, Dim4::new(&[1,1,1,1])),
+        };
+        let g_tiled = repeat_to_match_dims(&g_summed, output_dims);
         let node_tiled = repeat_to_match_dims(&node.value(), output_dims);
         return libaf::mul(&g_tiled, &libaf::exp(&(&self.0 - node_tiled)), false)
     }
@@ -43,7 +50,17 @@ impl<X> Expression<Array> for LogSumExp<X>
         let progenitors = Node::get_progenitors(&parents);
         let lse_clone = parents[0].value().clone();
         Node::new(c, out_val,
-                    parents, progenitors, Box::new(LogSumExpVJP(lse_clone)))
+                    parents, progenitors, Box::new(LogSumExpVJP(lse_clone, self.1)))
+    }
+}
+
+impl<C, X: Expression<Array> + Encode<C>> Encode<C> for LogSumExp<X> {
+    fn encode(&self, codec: &C) -> Json {
+        let dim = match self.1 {
+            Some(dim) => Json::Number(dim as f64),
+            None => Json::Null,
+        };
+        Json::op("logsumexp", vec![self.0.encode(codec)]).with("dim", dim)
     }
 }
 
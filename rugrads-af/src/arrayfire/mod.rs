@@ -1,4 +1,5 @@
 use rugrads::{Node, VecJacProduct, Expression};
+use rugrads::serial::{Encode, Json};
 
 use libaf;
 use libaf::Array;
@@ -7,6 +8,8 @@ use ::Context;
 
 pub mod wrappers;
 pub mod extras;
+pub mod serial;
+pub mod property;
 mod utils;
 
 #[derive(Copy, Clone)]
@@ -22,7 +25,7 @@ impl<F> VecJacProduct<Array> for LinVJP<F>
 }
 
 macro_rules! univariate_wrapper {
-    ($name: ident, $af_func: expr, $vjp: expr) => {
+    ($name: ident, $op_name: expr, $af_func: expr, $vjp: expr) => {
 #[derive(Copy, Clone)]
 pub struct $name<X: Expression<Array>>(X);
 
@@ -36,41 +39,47 @@ impl<X: Expression<Array>> Expression<Array> for $name<X> {
                     parents, progenitors, Box::new(LinVJP($vjp)))
     }
 }
+
+impl<C, X: Expression<Array> + Encode<C>> Encode<C> for $name<X> {
+    fn encode(&self, codec: &C) -> Json {
+        Json::op($op_name, vec![self.0.encode(codec)])
+    }
+}
     };
 }
 
-univariate_wrapper!(Sin, libaf::sin, libaf::cos);
-univariate_wrapper!(Cos, libaf::cos, |x| -libaf::sin(x));
-univariate_wrapper!(Tan, libaf::tan, |x| {
+univariate_wrapper!(Sin, "sin", libaf::sin, libaf::cos);
+univariate_wrapper!(Cos, "cos", libaf::cos, |x| -libaf::sin(x));
+univariate_wrapper!(Tan, "tan", libaf::tan, |x| {
     let ones = libaf::constant(1f64, x.dims());
     let cos_x = libaf::cos(x);
     libaf::div(&ones, &libaf::mul(&cos_x, &cos_x, false), false)
 });
-univariate_wrapper!(Sinh, libaf::sinh, libaf::cosh);
-univariate_wrapper!(Cosh, libaf::cosh, libaf::sinh);
-univariate_wrapper!(Tanh, libaf::tanh, |x| {
+univariate_wrapper!(Sinh, "sinh", libaf::sinh, libaf::cosh);
+univariate_wrapper!(Cosh, "cosh", libaf::cosh, libaf::sinh);
+univariate_wrapper!(Tanh, "tanh", libaf::tanh, |x| {
     let ones = libaf::constant(1f64, x.dims());
     let cosh_x = libaf::cosh(x);
     libaf::div(&ones, &libaf::mul(&cosh_x, &cosh_x, false), false)
 });
-univariate_wrapper!(Arcsin, libaf::asin, |x| {
+univariate_wrapper!(Arcsin, "asin", libaf::asin, |x| {
     let ones = libaf::constant(1f64, x.dims());
     let x_sq = libaf::sub(&ones, &libaf::pow(x, &2f64, false), false);
-    libaf::div(&ones, &x_sq, false)
+    libaf::div(&ones, &libaf::sqrt(&x_sq), false)
 });
-univariate_wrapper!(Arccos, libaf::acos, |x| {
+univariate_wrapper!(Arccos, "acos", libaf::acos, |x| {
     let ones = libaf::constant(1f64, x.dims());
     let x_sq = libaf::sub(&ones, &libaf::pow(x, &2f64, false), false);
-    -libaf::div(&ones, &x_sq, false)
+    -libaf::div(&ones, &libaf::sqrt(&x_sq), false)
 });
-univariate_wrapper!(Arctan, libaf::atan, |x| {
+univariate_wrapper!(Arctan, "atan", libaf::atan, |x| {
     let ones = libaf::constant(1f64, x.dims());
     let x_sq = libaf::add(&ones, &libaf::pow(x, &2f64, false), false);
     libaf::div(&ones, &x_sq, false)
 });
-univariate_wrapper!(Exp, libaf::exp, libaf::exp);
-univariate_wrapper!(Log, libaf::log, move |x| libaf::pow(x, &-1f64, false));
-univariate_wrapper!(Sigmoid, libaf::sigmoid, |x| {
+univariate_wrapper!(Exp, "exp", libaf::exp, libaf::exp);
+univariate_wrapper!(Log, "log", libaf::log, move |x| libaf::pow(x, &-1f64, false));
+univariate_wrapper!(Sigmoid, "sigmoid", libaf::sigmoid, |x| {
     let exp = libaf::exp(x);
     let ones = libaf::constant(1f64, x.dims());
 
@@ -92,6 +101,12 @@ impl<X: Expression<Array>> Expression<Array> for Pow<X> {
     }
 }
 
+impl<C, X: Expression<Array> + Encode<C>> Encode<C> for Pow<X> {
+    fn encode(&self, codec: &C) -> Json {
+        Json::op("pow", vec![self.0.encode(codec)]).with("n", Json::float(self.1))
+    }
+}
+
 #[derive(Copy, Clone)]
 pub struct SumAllVJP;
 
@@ -118,14 +133,22 @@ impl<X: Expression<Array>> Expression<Array> for SumAll<X> {
     }
 }
 
+impl<C, X: Expression<Array> + Encode<C>> Encode<C> for SumAll<X> {
+    fn encode(&self, codec: &C) -> Json {
+        Json::op("sum_all", vec![self.0.encode(codec)])
+    }
+}
+
 #[derive(Copy, Clone)]
 pub struct NormVJP(libaf::NormType);
 
 impl VecJacProduct<Array> for NormVJP {
-    fn vjp(&self, g: Array, _: &Node<Array>, x: &Node<Array>, _: usize) -> Array {
+    fn vjp(&self, g: Array, ans: &Node<Array>, x: &Node<Array>, _: usize) -> Array {
         match self.0 {
             libaf::NormType::VECTOR_2 => {
-                libaf::mul(&g, x.value(), true) * 2
+                // d|x|/dx = x / |x|
+                let norm = libaf::sum_all(ans.value()).0;
+                libaf::mul(&g, x.value(), true) / norm
             },
             _ => panic!("Only Frobenius norm is supported currently")
         }
@@ -148,6 +171,15 @@ impl<X: Expression<Array>> Expression<Array> for Norm<X> {
     }
 }
 
+impl<C, X: Expression<Array> + Encode<C>> Encode<C> for Norm<X> {
+    fn encode(&self, codec: &C) -> Json {
+        Json::op("norm", vec![self.0.encode(codec)])
+            .with("type", Json::String(serial::norm_type_name(self.1).to_string()))
+            .with("p", Json::float(self.2))
+            .with("q", Json::float(self.3))
+    }
+}
+
 #[derive(Clone)]
 pub struct DotVJP(Array, Array);
 
@@ -191,36 +223,11 @@ impl<X, Y> Expression<Array> for Dot<X, Y>
     }
 }
 
-#[derive(Clone)]
-pub struct AFMulVJP(Array, Array);
-
-impl VecJacProduct<Array> for AFMulVJP {
-    fn vjp(&self, g: Array, _: &Node<Array>, _: &Node<Array>, argnum: usize) -> Array {
-        match argnum {
-            0 => libaf::mul(&g, &self.1, false),
-            1 => libaf::mul(&g, &self.0, false),
-            _ => panic!("Invalid argnum fed to AFMul VJP")
-        }
-    }
-}
-
-#[derive(Copy, Clone)]
-pub struct AFMul<X: Expression<Array>, Y: Expression<Array>>(X, Y);
-
-impl<X, Y> Expression<Array> for AFMul<X, Y>
-    where X: Expression<Array>, Y: Expression<Array>
+impl<C, X, Y> Encode<C> for Dot<X, Y>
+    where X: Expression<Array> + Encode<C>, Y: Expression<Array> + Encode<C>
 {
-    fn eval(&self, c: &mut Context) -> Node<Array> {
-        let x_eval = self.0.eval(c);
-        let y_eval = self.1.eval(c);
-        let parents = vec![x_eval, y_eval];
-        let progenitors = Node::get_progenitors(&parents);
-
-        let prod = libaf::mul(parents[0].value(), parents[1].value(), false);
-        
-        let lhs_clone = parents[0].value().clone();
-        let rhs_clone = parents[1].value().clone();
-        Node::new(c, prod, parents, progenitors, Box::new(AFMulVJP(lhs_clone, rhs_clone)))
+    fn encode(&self, codec: &C) -> Json {
+        Json::op("dot", vec![self.0.encode(codec), self.1.encode(codec)])
     }
 }
 
@@ -276,6 +283,14 @@ impl<X, Y> Expression<Array> for MatMul<X, Y>
     }
 }
 
+impl<C, X, Y> Encode<C> for MatMul<X, Y>
+    where X: Expression<Array> + Encode<C>, Y: Expression<Array> + Encode<C>
+{
+    fn encode(&self, codec: &C) -> Json {
+        Json::op("matmul", vec![self.0.encode(codec), self.1.encode(codec)])
+    }
+}
+
 
 #[derive(Clone)]
 pub struct MaxOfVJP(Array, Array, bool);
@@ -311,6 +326,15 @@ impl<X,Y> Expression<Array> for MaxOf<X,Y>
     }
 }
 
+impl<C, X, Y> Encode<C> for MaxOf<X, Y>
+    where X: Expression<Array> + Encode<C>, Y: Expression<Array> + Encode<C>
+{
+    fn encode(&self, codec: &C) -> Json {
+        Json::op("max_of", vec![self.0.encode(codec), self.1.encode(codec)])
+            .with("batch", Json::Bool(self.2))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use libaf;
@@ -0,0 +1,158 @@
+//! Randomized gradient checks for arrayfire expressions
+//!
+//! This registers the arrayfire operations with the harness from
+//! `rugrads::property`. Variables are random 2x2 arrays and each
+//! expression is reduced with `sum_all` before it is differentiated.
+
+use rugrads::Variable;
+use rugrads::property::{BoxedExpr, BoxedGradient, Harness, Op};
+use rugrads::rng::Rng;
+
+use libaf;
+use libaf::{Array, Dim4, MatProp};
+
+use ::Container;
+use super::*;
+use super::extras::{logsumexp, relu};
+use super::wrappers::*;
+
+fn min_all(x: &Array) -> f64 {
+    libaf::min_all(x).0
+}
+
+fn max_all(x: &Array) -> f64 {
+    libaf::max_all(x).0
+}
+
+fn max_abs(x: &Array) -> f64 {
+    max_all(&libaf::abs(x))
+}
+
+fn min_abs(x: &Array) -> f64 {
+    min_all(&libaf::abs(x))
+}
+
+/// Returns the elementwise arrayfire operations with domains suitable for checking
+///
+/// Every operation preserves the shape of its arguments.
+pub fn ops() -> Vec<Op<Array>> {
+    vec![
+        Op::binary("add", |x, y| x + y),
+        Op::binary("sub", |x, y| x - y),
+        Op::unary("neg", |x| -x),
+        Op::binary("mul", |x, y| mul(x, y, false)),
+        Op::binary("matmul", |x, y| matmul(x, y, MatProp::NONE, MatProp::NONE)),
+        Op::binary("max_of", |x, y| Container::new(MaxOf(x.into_inner(), y.into_inner(), false)))
+            .domain(|a: &[Array]| min_abs(&(&a[0] - &a[1])) > 0.05),
+        Op::unary("sin", sin),
+        Op::unary("cos", cos),
+        Op::unary("tan", tan).domain(|a: &[Array]| max_abs(&a[0]) < 1.2),
+        Op::unary("sinh", sinh).domain(|a: &[Array]| max_abs(&a[0]) < 3.0),
+        Op::unary("cosh", cosh).domain(|a: &[Array]| max_abs(&a[0]) < 3.0),
+        Op::unary("tanh", tanh),
+        Op::unary("asin", asin).domain(|a: &[Array]| max_abs(&a[0]) < 0.9),
+        Op::unary("acos", acos).domain(|a: &[Array]| max_abs(&a[0]) < 0.9),
+        Op::unary("atan", atan),
+        Op::unary("exp", exp).domain(|a: &[Array]| max_all(&a[0]) < 2.0),
+        Op::unary("log", log).domain(|a: &[Array]| min_all(&a[0]) > 0.25),
+        Op::unary("sigmoid", sigmoid),
+        Op::unary("pow", |x| pow(x, 2.5))
+            .domain(|a: &[Array]| min_all(&a[0]) > 0.25 && max_all(&a[0]) < 4.0),
+        Op::unary("cube", |x| pow(x, 3.0)).domain(|a: &[Array]| max_abs(&a[0]) < 1.5),
+        Op::unary("relu", relu).domain(|a: &[Array]| min_abs(&a[0]) > 0.05),
+        Op::unary("logsumexp", |x| logsumexp(x, None)),
+        Op::unary("logsumexp_1", |x| logsumexp(x, Some(1))),
+    ]
+}
+
+fn scalar(arr: &Array) -> f64 {
+    libaf::sum_all(arr).0
+}
+
+/// Compares array gradients against elementwise central differences
+///
+/// The expression must evaluate to a single element.
+pub fn check_grads(grad: &mut BoxedGradient<Array>, vars: &[Variable]) -> Result<(), String> {
+    let h = 1e-5;
+    let (rtol, atol) = (1e-4, 1e-6);
+
+    for var in vars {
+        let analytic = grad.try_backprop(var, libaf::constant(1f64, Dim4::new(&[1,1,1,1])))
+            .unwrap_or_else(|| libaf::constant(0f64, grad.get(var).dims()));
+
+        let x = grad.get(var).clone();
+        let dims = x.dims();
+        let n = dims.elements() as usize;
+        let mut data = vec![0f64; n];
+        x.host(&mut data);
+        let mut analytic_data = vec![0f64; n];
+        analytic.host(&mut analytic_data);
+
+        for k in 0..n {
+            let mut plus = data.clone();
+            plus[k] += h;
+            grad.context().set_variable_value(var, Array::new(&plus, dims));
+            let f_plus = scalar(&grad.value());
+
+            let mut minus = data.clone();
+            minus[k] -= h;
+            grad.context().set_variable_value(var, Array::new(&minus, dims));
+            let f_minus = scalar(&grad.value());
+
+            grad.context().set_variable_value(var, x.clone());
+
+            let numeric = (f_plus - f_minus) / (2.0 * h);
+            let abs_error = (analytic_data[k] - numeric).abs();
+            if abs_error > atol + rtol * numeric.abs() {
+                return Err(format!("{:?}[{}]: analytic = {}, numeric = {}",
+                                   var, k, analytic_data[k], numeric));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn sample(rng: &mut Rng) -> Array {
+    let data: Vec<f64> = (0..4).map(|_| rng.range(-2.0, 2.0)).collect();
+    Array::new(&data, Dim4::new(&[2,2,1,1]))
+}
+
+/// Creates a harness for the arrayfire operations
+///
+/// Generated expressions are reduced with `sum_all`. Use `Harness::output`
+/// to check other reductions.
+pub fn harness() -> Harness<Array> {
+    Harness::new(sample, check_grads)
+        .with_ops(ops())
+        .output(|e: BoxedExpr<Array>| Box::new(sum_all(Container::new(e)).into_inner()))
+}
+
+#[cfg(test)]
+mod tests {
+    use libaf;
+    use libaf::NormType;
+
+    use super::*;
+
+    #[test]
+    fn test_ops() {
+        libaf::set_backend(libaf::Backend::CPU);
+        if let Err(failure) = harness().cases(200).run() {
+            panic!("Gradient check failed for `{}` (case {}): {}",
+                   failure.expr, failure.case, failure.message);
+        }
+    }
+
+    #[test]
+    fn test_norm() {
+        libaf::set_backend(libaf::Backend::CPU);
+        let result = harness()
+            .output(|e| Box::new(norm(Container::new(e), NormType::VECTOR_2, 0.0, 0.0).into_inner()))
+            .cases(50)
+            .run();
+        if let Err(failure) = result {
+            panic!("Gradient check failed for `{}` (case {}): {}",
+                   failure.expr, failure.case, failure.message);
+        }
+    }
+}
@@ -0,0 +1,297 @@
+//! Serialization of arrayfire expressions
+//!
+//! This reuses the format from `rugrads::serial`, writing constant
+//! arrays as their dimensions and column-major data. Only `f64` arrays
+//! are supported.
+
+use rugrads;
+use rugrads::{Node, Expression, Variable, LeafVar};
+use rugrads::functions::{Add, Sub, Mul, Neg};
+use rugrads::serial::{Codec, Encode, Error, Json};
+use rugrads::serial::{decode_op, decode_variable, document, read_document};
+
+use libaf;
+use libaf::{Array, Dim4, NormType};
+
+use ::{Context, Container};
+use super::*;
+use super::extras::{logsumexp, LogSumExp};
+
+/// The `Codec` for f64 arrays
+#[derive(Clone, Copy, Debug)]
+pub struct ArrayCodec;
+
+impl Codec<Array> for ArrayCodec {
+    fn encode_const(&self, value: &Array) -> Json {
+        let dims = value.dims();
+        let mut data = vec![0f64; dims.elements() as usize];
+        value.host(&mut data);
+
+        Json::object(vec![("dims", Json::Array(dims.get().iter().map(|&d| Json::Number(d as f64)).collect())),
+                          ("data", Json::Array(data.into_iter().map(Json::float).collect()))])
+    }
+
+    fn decode_const(&self, json: &Json) -> Result<Array, Error> {
+        let dims = json.field("dims")?.as_array()?;
+        if dims.len() != 4 {
+            return Err(Error::new("Array dimensions must have four entries"));
+        }
+        let mut dim_arr = [0u64; 4];
+        for (d, j) in dim_arr.iter_mut().zip(dims.iter()) {
+            *d = j.as_u64()?;
+        }
+
+        let data = json.field("data")?.as_array()?.iter()
+                       .map(|j| j.as_f64())
+                       .collect::<Result<Vec<f64>, Error>>()?;
+        let dim4 = Dim4::new(&dim_arr);
+        if data.len() as u64 != dim4.elements() {
+            return Err(Error::new("Array data does not match its dimensions"));
+        }
+        Ok(Array::new(&data, dim4))
+    }
+}
+
+/// Returns the name used to write a `NormType`
+pub fn norm_type_name(ntype: NormType) -> &'static str {
+    match ntype {
+        NormType::VECTOR_1 => "vector_1",
+        NormType::VECTOR_INF => "vector_inf",
+        NormType::VECTOR_2 => "vector_2",
+        NormType::VECTOR_P => "vector_p",
+        NormType::MATRIX_1 => "matrix_1",
+        NormType::MATRIX_INF => "matrix_inf",
+        NormType::MATRIX_2 => "matrix_2",
+        NormType::MATRIX_L_PQ => "matrix_l_pq",
+    }
+}
+
+fn norm_type_from_name(name: &str) -> Result<NormType, Error> {
+    match name {
+        "vector_1" => Ok(NormType::VECTOR_1),
+        "vector_inf" => Ok(NormType::VECTOR_INF),
+        "vector_2" => Ok(NormType::VECTOR_2),
+        "vector_p" => Ok(NormType::VECTOR_P),
+        "matrix_1" => Ok(NormType::MATRIX_1),
+        "matrix_inf" => Ok(NormType::MATRIX_INF),
+        "matrix_2" => Ok(NormType::MATRIX_2),
+        "matrix_l_pq" => Ok(NormType::MATRIX_L_PQ),
+        _ => Err(Error::new(&format!("Unknown norm type `{}`", name))),
+    }
+}
+
+type Arg = Box<AfTerm>;
+
+/// A dynamically structured arrayfire expression
+///
+/// This is the result of reading an expression back from the serial format.
+pub enum AfTerm {
+    /// A variable in the context
+    Var(Variable),
+    /// A constant array
+    Const(LeafVar<Array>),
+    /// Addition
+    Add(Add<Array, Arg, Arg>),
+    /// Subtraction
+    Sub(Sub<Array, Arg, Arg>),
+    /// Negation
+    Neg(Neg<Array, Arg>),
+    /// Elementwise Sine
+    Sin(Sin<Arg>),
+    /// Elementwise Cosine
+    Cos(Cos<Arg>),
+    /// Elementwise Tangent
+    Tan(Tan<Arg>),
+    /// Elementwise Hyperbolic Sine
+    Sinh(Sinh<Arg>),
+    /// Elementwise Hyperbolic Cosine
+    Cosh(Cosh<Arg>),
+    /// Elementwise Hyperbolic Tangent
+    Tanh(Tanh<Arg>),
+    /// Elementwise Arcsin
+    Arcsin(Arcsin<Arg>),
+    /// Elementwise Arccos
+    Arccos(Arccos<Arg>),
+    /// Elementwise Arctan
+    Arctan(Arctan<Arg>),
+    /// Elementwise Exponent
+    Exp(Exp<Arg>),
+    /// Elementwise Natural Logarithm
+    Log(Log<Arg>),
+    /// Elementwise Sigmoid
+    Sigmoid(Sigmoid<Arg>),
+    /// Sum of all elements
+    SumAll(SumAll<Arg>),
+    /// Elementwise Power Raising
+    Pow(Pow<Arg>),
+    /// Norm
+    Norm(Norm<Arg>),
+    /// Dot product
+    Dot(Dot<Arg, Arg>),
+    /// Elementwise multiplication
+    Mul(Mul<Array, Arg, Arg>),
+    /// Matrix product
+    MatMul(MatMul<Arg, Arg>),
+    /// Elementwise maximum
+    MaxOf(MaxOf<Arg, Arg>),
+    /// Log-sum-exp
+    LogSumExp(LogSumExp<Arg>),
+}
+
+macro_rules! for_each_term {
+    ($term: expr, $e: ident => $body: expr) => {
+        match *$term {
+            AfTerm::Var(ref $e) => $body,
+            AfTerm::Const(ref $e) => $body,
+            AfTerm::Add(ref $e) => $body,
+            AfTerm::Sub(ref $e) => $body,
+            AfTerm::Neg(ref $e) => $body,
+            AfTerm::Sin(ref $e) => $body,
+            AfTerm::Cos(ref $e) => $body,
+            AfTerm::Tan(ref $e) => $body,
+            AfTerm::Sinh(ref $e) => $body,
+            AfTerm::Cosh(ref $e) => $body,
+            AfTerm::Tanh(ref $e) => $body,
+            AfTerm::Arcsin(ref $e) => $body,
+            AfTerm::Arccos(ref $e) => $body,
+            AfTerm::Arctan(ref $e) => $body,
+            AfTerm::Exp(ref $e) => $body,
+            AfTerm::Log(ref $e) => $body,
+            AfTerm::Sigmoid(ref $e) => $body,
+            AfTerm::SumAll(ref $e) => $body,
+            AfTerm::Pow(ref $e) => $body,
+            AfTerm::Norm(ref $e) => $body,
+            AfTerm::Dot(ref $e) => $body,
+            AfTerm::Mul(ref $e) => $body,
+            AfTerm::MatMul(ref $e) => $body,
+            AfTerm::MaxOf(ref $e) => $body,
+            AfTerm::LogSumExp(ref $e) => $body,
+        }
+    };
+}
+
+impl Expression<Array> for AfTerm {
+    fn eval(&self, c: &mut Context) -> Node<Array> {
+        for_each_term!(self, e => e.eval(c))
+    }
+}
+
+impl<C: Codec<Array>> Encode<C> for AfTerm {
+    fn encode(&self, codec: &C) -> Json {
+        for_each_term!(self, e => e.encode(codec))
+    }
+}
+
+fn arity(name: &str) -> usize {
+    match name {
+        "add" | "sub" | "dot" | "mul" | "matmul" | "max_of" => 2,
+        _ => 1,
+    }
+}
+
+/// Reads an `AfTerm` from its JSON description
+pub fn decode(json: &Json) -> Result<AfTerm, Error> {
+    if let Some(v) = json.get("var") {
+        return decode_variable(v).map(AfTerm::Var);
+    }
+    if let Some(v) = json.get("const") {
+        return ArrayCodec.decode_const(v).map(|arr| AfTerm::Const(LeafVar(arr)));
+    }
+
+    let name = json.field("op")?.as_str()?;
+    let (_, args) = decode_op(json, arity(name))?;
+    let arg = |i: usize| decode(&args[i]).map(Box::new);
+    let cont = |i: usize| arg(i).map(rugrads::Container::new);
+
+    let term = match name {
+        "add" => AfTerm::Add((cont(0)? + cont(1)?).into_inner()),
+        "sub" => AfTerm::Sub((cont(0)? - cont(1)?).into_inner()),
+        "neg" => AfTerm::Neg((-cont(0)?).into_inner()),
+        "sin" => AfTerm::Sin(Sin(arg(0)?)),
+        "cos" => AfTerm::Cos(Cos(arg(0)?)),
+        "tan" => AfTerm::Tan(Tan(arg(0)?)),
+        "sinh" => AfTerm::Sinh(Sinh(arg(0)?)),
+        "cosh" => AfTerm::Cosh(Cosh(arg(0)?)),
+        "tanh" => AfTerm::Tanh(Tanh(arg(0)?)),
+        "asin" => AfTerm::Arcsin(Arcsin(arg(0)?)),
+        "acos" => AfTerm::Arccos(Arccos(arg(0)?)),
+        "atan" => AfTerm::Arctan(Arctan(arg(0)?)),
+        "exp" => AfTerm::Exp(Exp(arg(0)?)),
+        "log" => AfTerm::Log(Log(arg(0)?)),
+        "sigmoid" => AfTerm::Sigmoid(Sigmoid(arg(0)?)),
+        "sum_all" => AfTerm::SumAll(SumAll(arg(0)?)),
+        "pow" => AfTerm::Pow(Pow(arg(0)?, json.field("n")?.as_f64()?)),
+        "norm" => {
+            let ntype = norm_type_from_name(json.field("type")?.as_str()?)?;
+            let p = json.field("p")?.as_f64()?;
+            let q = json.field("q")?.as_f64()?;
+            AfTerm::Norm(Norm(arg(0)?, ntype, p, q))
+        },
+        "dot" => AfTerm::Dot(Dot(arg(0)?, arg(1)?)),
+        "mul" => AfTerm::Mul((cont(0)? * cont(1)?).into_inner()),
+        "matmul" => AfTerm::MatMul(MatMul(arg(0)?, arg(1)?)),
+        "max_of" => {
+            let batch = match *json.field("batch")? {
+                Json::Bool(b) => b,
+                _ => return Err(Error::new("Expected a boolean")),
+            };
+            AfTerm::MaxOf(MaxOf(arg(0)?, arg(1)?, batch))
+        },
+        "logsumexp" => {
+            let dim = match *json.field("dim")? {
+                Json::Null => None,
+                ref j => Some(j.as_f64()? as i32),
+            };
+            AfTerm::LogSumExp(logsumexp(Container::new(arg(0)?), dim).into_inner())
+        },
+        _ => return Err(Error::new(&format!("Unknown operation `{}`", name))),
+    };
+    Ok(term)
+}
+
+/// Writes an arrayfire expression as a string
+pub fn to_string<E: Expression<Array> + Encode<ArrayCodec>>(expr: &Container<E>) -> String {
+    document(expr.encode(&ArrayCodec)).to_string()
+}
+
+/// Reads an arrayfire expression from a string
+pub fn from_str(s: &str) -> Result<Container<AfTerm>, Error> {
+    let doc = Json::parse(s)?;
+    decode(read_document(&doc)?).map(Container::new)
+}
+
+#[cfg(test)]
+mod tests {
+    use libaf;
+    use libaf::Dim4;
+
+    use ::{Context, Gradient};
+    use ::testsupport::array_eq;
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        libaf::set_backend(libaf::Backend::CPU);
+        let mut context = Context::new();
+
+        let dims = Dim4::new(&[2,2,1,1]);
+        let arr = Array::new(&[0.5, -0.5, 0.25, 0.75], dims);
+        let x = context.create_variable(arr);
+        let f = ::sum_all(::relu(::sigmoid(x) - ::pow(::tanh(x), 2.0)));
+
+        let text = to_string(&f);
+        let g = from_str(&text).unwrap();
+        assert_eq!(to_string(&g), text);
+
+        let expected = f.eval(&mut context).value().clone();
+        assert!(array_eq(g.eval(&mut context).value(), &expected, 1e-12));
+
+        let mut grad_f = Gradient::of(f, context);
+        let grad_expected = grad_f.grad(&x);
+
+        let mut context = Context::new();
+        context.create_variable(Array::new(&[0.5, -0.5, 0.25, 0.75], dims));
+        let mut grad_g = Gradient::of(g, context);
+        assert!(array_eq(&grad_g.grad(&x), &grad_expected, 1e-12));
+    }
+}
@@ -1,6 +1,7 @@
 use libaf::Array;
 
 use rugrads::Expression;
+use rugrads::functions::Mul;
 use ::Container;
 
 use super::*;
@@ -55,10 +56,12 @@ pub fn dot<E1, E2>(lhs: Container<E1>, rhs: Container<E2>, _: ::MatProp, _: ::Ma
 }
 
 /// Computes the elementwise multiplication of two arrays
-pub fn mul<E1, E2>(lhs: Container<E1>, rhs: Container<E2>, _: bool) -> Container<AFMul<E1, E2>>
+///
+/// This is the same as `lhs * rhs`.
+pub fn mul<E1, E2>(lhs: Container<E1>, rhs: Container<E2>, _: bool) -> Container<Mul<Array, E1, E2>>
     where E1: Expression<Array>, E2: Expression<Array>
 {
-    Container::new(AFMul(lhs.into_inner(), rhs.into_inner()))
+    lhs * rhs
 }
 
 /// Computes the matrix product of two arrays
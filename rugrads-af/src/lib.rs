@@ -23,6 +23,12 @@ pub use libaf::{set_backend, constant};
 pub use arrayfire::wrappers::*;
 pub use arrayfire::extras::{logsumexp, logsoftmax, relu};
 
+// Reexport expression serialization
+pub use arrayfire::serial;
+
+// Reexport randomized gradient checks
+pub use arrayfire::property;
+
 /// A struct for two dimensions
 pub struct Dim2(pub [u64; 2]);
 
@@ -44,10 +50,12 @@ impl<E: Expression<Array>> Gradient<E> {
 
     pub fn grad(&mut self, wrt: &rugrads::Variable) -> Array {
         let input_type = wrt.value(&self.0.context()).get_type();
+        let dims = wrt.value(&self.0.context()).dims();
 
         match input_type {
             DType::F64 => {
-                self.0.backprop(wrt, libaf::constant(1f64, Dim4::new(&[1,1,1,1])))
+                self.0.try_backprop(wrt, libaf::constant(1f64, Dim4::new(&[1,1,1,1])))
+                    .unwrap_or_else(|| libaf::constant(0f64, dims))
             },
             _ => panic!("Currently only f64 array types are supported")
         }
@@ -0,0 +1,456 @@
+//! Check module
+//!
+//! Compares the gradients computed by back propagation against
+//! numerical estimates. This is useful for testing new operations.
+//!
+//! # Example
+//!
+//! ```
+//! use rugrads::{Context, Gradient};
+//! use rugrads::functions::*;
+//! use rugrads::check::GradCheck;
+//!
+//! let mut context = Context::new();
+//! let x = context.create_variable(0.5);
+//! let y = context.create_variable(0.3);
+//! let mut grad = Gradient::of(y * sin(x) + cos(y), context);
+//!
+//! let report = GradCheck::new().check(&mut grad);
+//! assert!(report.passed(), "{}", report);
+//! ```
+
+use std::fmt;
+
+use num::{Complex, Float};
+
+use ::{Expression, Gradient, Variable};
+use ::term::{Func, Func2, Term, ToTerm};
+
+/// The finite difference scheme used to estimate gradients
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Difference {
+    /// One-sided differences, `(f(x + h) - f(x)) / h`
+    Forward,
+    /// Central differences, `(f(x + h) - f(x - h)) / 2h`
+    Central,
+    /// Richardson extrapolation of central differences with steps `h` and `h / 2`
+    Richardson,
+}
+
+/// The result of checking the gradient for a single `Variable`
+#[derive(Clone, Copy, Debug)]
+pub struct Entry<T> {
+    /// The variable which was checked
+    pub variable: Variable,
+    /// The gradient computed by back propagation
+    pub analytic: T,
+    /// The numerical estimate of the gradient
+    pub numeric: T,
+    /// The absolute difference between the gradients
+    pub abs_error: T,
+    /// The absolute difference relative to the numerical gradient
+    pub rel_error: T,
+    /// Whether the gradients agree within the tolerances
+    pub passed: bool,
+}
+
+/// The result of a gradient check
+#[derive(Clone, Debug)]
+pub struct Report<T> {
+    /// One entry for each variable checked
+    pub entries: Vec<Entry<T>>,
+}
+
+impl<T: Float> Report<T> {
+    /// Returns true if every variable passed the check
+    pub fn passed(&self) -> bool {
+        self.entries.iter().all(|e| e.passed)
+    }
+
+    /// Returns the entries which failed the check
+    pub fn failures(&self) -> Vec<&Entry<T>> {
+        self.entries.iter().filter(|e| !e.passed).collect()
+    }
+
+    /// Returns the largest absolute error
+    pub fn max_abs_error(&self) -> T {
+        self.entries.iter().fold(T::zero(), |m, e| m.max(e.abs_error))
+    }
+
+    /// Returns the largest relative error
+    pub fn max_rel_error(&self) -> T {
+        self.entries.iter().fold(T::zero(), |m, e| m.max(e.rel_error))
+    }
+}
+
+impl<T: Float + fmt::Display> fmt::Display for Report<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for e in self.entries.iter() {
+            writeln!(f, "x{}: analytic = {}, numeric = {}, abs error = {}, rel error = {} [{}]",
+                     e.variable.0, e.analytic, e.numeric, e.abs_error, e.rel_error,
+                     if e.passed { "ok" } else { "FAILED" })?;
+        }
+        Ok(())
+    }
+}
+
+/// Settings for a gradient check
+///
+/// A gradient passes the check if
+/// `|analytic - numeric| <= atol + rtol * |numeric|`.
+#[derive(Clone, Copy, Debug)]
+pub struct GradCheck<T> {
+    difference: Difference,
+    step: T,
+    rtol: T,
+    atol: T,
+}
+
+impl<T: Float> GradCheck<T> {
+    /// Creates a check using central differences
+    ///
+    /// The default step is the cube root of machine epsilon and the
+    /// default tolerances are `rtol = 1e-5` and `atol = 1e-8`.
+    pub fn new() -> Self {
+        GradCheck {
+            difference: Difference::Central,
+            step: T::epsilon().cbrt(),
+            rtol: T::from(1e-5).unwrap(),
+            atol: T::from(1e-8).unwrap(),
+        }
+    }
+
+    /// Sets the finite difference scheme
+    pub fn difference(mut self, difference: Difference) -> Self {
+        self.difference = difference;
+        self
+    }
+
+    /// Sets the finite difference step
+    ///
+    /// The step is scaled by the magnitude of each variable when
+    /// this is larger than one.
+    pub fn step(mut self, step: T) -> Self {
+        self.step = step;
+        self
+    }
+
+    /// Sets the relative and absolute tolerances
+    pub fn tolerances(mut self, rtol: T, atol: T) -> Self {
+        self.rtol = rtol;
+        self.atol = atol;
+        self
+    }
+
+    fn entry(&self, variable: Variable, analytic: T, numeric: T) -> Entry<T> {
+        let abs_error = (analytic - numeric).abs();
+        let rel_error = if numeric == T::zero() {
+            abs_error
+        } else {
+            abs_error / numeric.abs()
+        };
+        Entry {
+            variable: variable,
+            analytic: analytic,
+            numeric: numeric,
+            abs_error: abs_error,
+            rel_error: rel_error,
+            passed: abs_error <= self.atol + self.rtol * numeric.abs(),
+        }
+    }
+
+    /// Checks the gradient with respect to every variable in the context
+    pub fn check<E: Expression<T>>(&self, grad: &mut Gradient<T, E>) -> Report<T> {
+        let vars = grad.context().variables();
+        self.check_variables(grad, &vars)
+    }
+
+    /// Checks the gradient with respect to the given variables
+    ///
+    /// The variable values are left unchanged.
+    pub fn check_variables<E: Expression<T>>(&self, grad: &mut Gradient<T, E>, vars: &[Variable]) -> Report<T> {
+        let entries = vars.iter().map(|var| {
+            let analytic = grad.grad(var);
+            let numeric = self.finite_diff(grad, var);
+            self.entry(*var, analytic, numeric)
+        }).collect();
+
+        Report { entries: entries }
+    }
+
+    fn eval_at<E: Expression<T>>(grad: &mut Gradient<T, E>, var: &Variable, x: T) -> T {
+        let x0 = *grad.get(var);
+        grad.context().set_variable_value(var, x);
+        let value = grad.value();
+        grad.context().set_variable_value(var, x0);
+        value
+    }
+
+    fn central<E: Expression<T>>(grad: &mut Gradient<T, E>, var: &Variable, x: T, h: T) -> T {
+        let two = T::one() + T::one();
+        (Self::eval_at(grad, var, x + h) - Self::eval_at(grad, var, x - h)) / (two * h)
+    }
+
+    fn finite_diff<E: Expression<T>>(&self, grad: &mut Gradient<T, E>, var: &Variable) -> T {
+        let x = *grad.get(var);
+        let h = self.step * x.abs().max(T::one());
+
+        match self.difference {
+            Difference::Forward => {
+                let f0 = grad.value();
+                (Self::eval_at(grad, var, x + h) - f0) / h
+            },
+            Difference::Central => Self::central(grad, var, x, h),
+            Difference::Richardson => {
+                let two = T::one() + T::one();
+                let d1 = Self::central(grad, var, x, h);
+                let d2 = Self::central(grad, var, x, h / two);
+                (two * two * d2 - d1) / (two + T::one())
+            },
+        }
+    }
+
+    /// Checks gradients against the complex-step method
+    ///
+    /// The complex-step estimate `Im(f(x + ih)) / h` does not suffer from
+    /// cancellation and so is accurate to machine precision. This requires
+    /// the expression to be convertible to a `Term` and only uses the
+    /// tolerances from this check.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the expression uses a function which
+    /// has no complex extension.
+    pub fn check_complex_step<E>(&self, grad: &mut Gradient<T, E>) -> Report<T>
+        where E: Expression<T> + ToTerm<T>
+    {
+        let term = grad.expr().to_term();
+        let h = T::epsilon() * T::epsilon();
+
+        let entries = grad.context().variables().into_iter().map(|var| {
+            let analytic = grad.grad(&var);
+            let numeric = eval_complex(&term, grad, &var, h).im / h;
+            self.entry(var, analytic, numeric)
+        }).collect();
+
+        Report { entries: entries }
+    }
+}
+
+impl<T: Float> Default for GradCheck<T> {
+    fn default() -> Self {
+        GradCheck::new()
+    }
+}
+
+fn apply_complex<T: Float>(f: Func, z: Complex<T>) -> Complex<T> {
+    match f {
+        Func::Sin => z.sin(),
+        Func::Cos => z.cos(),
+        Func::Exp => z.exp(),
+        Func::Ln => z.ln(),
+        Func::Tan => z.tan(),
+        Func::Asin => atan_complex(z / (Complex::new(T::one(), T::zero()) - z * z).sqrt()),
+        Func::Acos => {
+            let asin = atan_complex(z / (Complex::new(T::one(), T::zero()) - z * z).sqrt());
+            Complex::new(T::from(::std::f64::consts::FRAC_PI_2).unwrap(), T::zero()) - asin
+        },
+        Func::Atan => atan_complex(z),
+        Func::Sinh => z.sinh(),
+        Func::Cosh => z.cosh(),
+        Func::Tanh => z.tanh(),
+        Func::Asinh => z.asinh(),
+        Func::Acosh => z.acosh(),
+        Func::Atanh => z.atanh(),
+        Func::Sqrt => z.sqrt(),
+        Func::Cbrt => {
+            // The real cube root rather than the principal branch
+            let third = T::from(3).unwrap().recip();
+            if z.re < T::zero() { -(-z).powf(third) } else { z.powf(third) }
+        },
+        Func::Log2 => z.ln() / T::from(::std::f64::consts::LN_2).unwrap(),
+        Func::Log10 => z.ln() / T::from(::std::f64::consts::LN_10).unwrap(),
+        Func::Exp2 => (z * T::from(::std::f64::consts::LN_2).unwrap()).exp(),
+        Func::ExpM1 => z.exp() - T::one(),
+        Func::Ln1p => (z + T::one()).ln(),
+        Func::Erf | Func::Erfc | Func::Gamma | Func::Lgamma |
+        Func::Digamma | Func::Trigamma | Func::Polygamma(_) => {
+            panic!("`{}` has no complex extension", f.name())
+        },
+        // Piecewise functions follow the branch of the real part, and
+        // average the branches at a kink
+        Func::Abs => z * f.deriv(z.re),
+        Func::Relu => z * f.deriv(z.re),
+        Func::Sign | Func::Floor | Func::Ceil | Func::Round => Complex::new(f.apply(z.re), T::zero()),
+        Func::Powi(n) => {
+            let mut result = Complex::new(T::one(), T::zero());
+            for _ in 0..n.unsigned_abs() {
+                result = result * z;
+            }
+            if n < 0 { result.inv() } else { result }
+        },
+    }
+}
+
+// The inverse trigonometric functions in `num::Complex` go through a
+// logarithm of numbers close to one and lose the tiny imaginary part of a
+// complex step. This uses `atan(z) = atan(a) + atan((z - a) / (1 + a z))`
+// with `a = Re(z)` instead, the second term is small enough that it equals
+// its argument to machine precision.
+fn atan_complex<T: Float>(z: Complex<T>) -> Complex<T> {
+    let a = Complex::new(z.re, T::zero());
+    let w = (z - a) / (Complex::new(T::one(), T::zero()) + a * z);
+    Complex::new(z.re.atan(), T::zero()) + w
+}
+
+fn apply2_complex<T: Float>(f: Func2, z: Complex<T>, w: Complex<T>) -> Complex<T> {
+    match f {
+        Func2::Atan2 => {
+            // `atan(z / w)` is analytic, shift it onto the quadrant of the real parts
+            let offset = z.re.atan2(w.re) - (z.re / w.re).atan();
+            atan_complex(z / w) + offset
+        },
+        Func2::Hypot => (z * z + w * w).sqrt(),
+        Func2::Log => z.ln() / w.ln(),
+        Func2::Lbeta => panic!("`{}` has no complex extension", f.name()),
+        Func2::Pow => (w * z.ln()).exp(),
+        Func2::Min | Func2::Max => {
+            let two = T::one() + T::one();
+            if z.re == w.re {
+                (z + w) / two
+            } else if (z.re < w.re) == (f == Func2::Min) {
+                z
+            } else {
+                w
+            }
+        },
+    }
+}
+
+fn eval_complex<T: Float, E>(term: &Term<T>, grad: &Gradient<T, E>, wrt: &Variable, h: T) -> Complex<T>
+    where E: Expression<T>
+{
+    let eval = |t: &Term<T>| eval_complex(t, grad, wrt, h);
+    match *term {
+        Term::Var(ref v) if v == wrt => Complex::new(*grad.get(v), h),
+        Term::Var(ref v) => Complex::new(*grad.get(v), T::zero()),
+        Term::Const(v) => Complex::new(v, T::zero()),
+        Term::Add(ref x, ref y) => eval(x) + eval(y),
+        Term::Sub(ref x, ref y) => eval(x) - eval(y),
+        Term::Mul(ref x, ref y) => eval(x) * eval(y),
+        Term::Div(ref x, ref y) => eval(x) / eval(y),
+        Term::Neg(ref x) => -eval(x),
+        Term::Powf(ref x, n) => eval(x).powf(n),
+        Term::Apply(f, ref x) => apply_complex(f, eval(x)),
+        Term::Apply2(f, ref x, ref y) => apply2_complex(f, eval(x), eval(y)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::marker::PhantomData;
+
+    use ::{Container, Context, Expression, Gradient, Node, VecJacProduct};
+    use ::functions::*;
+
+    #[test]
+    fn test_all_differences() {
+        for &d in &[Difference::Forward, Difference::Central, Difference::Richardson] {
+            let mut c = Context::new();
+            let x = c.create_variable(0.5);
+            let y = c.create_variable(3.0);
+            let mut g = Gradient::of(x * y + cos(y) * sin(x) / exp(x), c);
+
+            let check = GradCheck::new().difference(d);
+            let check = if d == Difference::Forward { check.tolerances(1e-4, 1e-6) } else { check };
+            let report = check.check(&mut g);
+            assert!(report.passed(), "{:?}\n{}", d, report);
+            assert_eq!(report.entries.len(), 2);
+
+            // Variable values are restored
+            assert_eq!(*g.get(&x), 0.5);
+            assert_eq!(*g.get(&y), 3.0);
+        }
+    }
+
+    #[test]
+    fn test_richardson_more_accurate() {
+        let mut c = Context::new();
+        let x = c.create_variable(0.5);
+        let mut g = Gradient::of(exp(sin(x)), c);
+
+        let central = GradCheck::new().step(1e-2).check(&mut g);
+        let richardson = GradCheck::new().step(1e-2).difference(Difference::Richardson).check(&mut g);
+        assert!(richardson.max_abs_error() < central.max_abs_error());
+    }
+
+    #[test]
+    fn test_complex_step() {
+        let mut c = Context::new();
+        let x = c.create_variable(0.5);
+        let y = c.create_variable(1.5);
+        let mut g = Gradient::of(powf(y, 2.5) * sin(x) - ln(y) / cos(x), c);
+
+        let report = GradCheck::new().tolerances(1e-14, 1e-14).check_complex_step(&mut g);
+        assert!(report.passed(), "{}", report);
+    }
+
+    #[test]
+    fn test_complex_step_inverse() {
+        let mut c = Context::new();
+        let x = c.create_variable(-0.5);
+        let y = c.create_variable(0.7);
+        let mut g = Gradient::of(atan2(y, x) * acos(y) + tanh(x) * asinh(y) - asin(x) * atan(y), c);
+
+        let report = GradCheck::new().tolerances(1e-12, 1e-12).check_complex_step(&mut g);
+        assert!(report.passed(), "{}", report);
+    }
+
+    #[test]
+    fn test_complex_step_roots_and_logs() {
+        let mut c = Context::new();
+        let x = c.create_variable(-0.5);
+        let y = c.create_variable(1.7);
+        let f = sqrt(y) * cbrt(x) + hypot(x, y) * log(y, exp2(y)) - log2(y) / log10(y)
+            + exp_m1(x) * ln_1p(x) + max(x, y) * abs(x) - relu(y) * round(y)
+            + pow(y, x) * powi(x, -3);
+        let mut g = Gradient::of(f, c);
+
+        let report = GradCheck::new().tolerances(1e-12, 1e-12).check_complex_step(&mut g);
+        assert!(report.passed(), "{}", report);
+    }
+
+    // A sine operation with the wrong derivative
+    struct BadSin<X>(X);
+
+    struct BadSinVJP(PhantomData<f64>);
+
+    impl VecJacProduct<f64> for BadSinVJP {
+        fn vjp(&self, g: f64, _: &Node<f64>, x: &Node<f64>, _: usize) -> f64 {
+            g * f64::sin(*x.value())
+        }
+    }
+
+    impl<X: Expression<f64>> Expression<f64> for BadSin<X> {
+        fn eval(&self, c: &mut Context<f64>) -> Node<f64> {
+            let parents = vec![self.0.eval(c)];
+            let progenitors = Node::get_progenitors(&parents);
+            let value = f64::sin(*parents[0].value());
+            Node::new(c, value, parents, progenitors, Box::new(BadSinVJP(PhantomData)))
+        }
+    }
+
+    #[test]
+    fn test_detects_bad_vjp() {
+        let mut c = Context::new();
+        let x = c.create_variable(0.5);
+        let y = c.create_variable(0.3);
+        let mut g = Gradient::of(Container::new(BadSin(x.into_inner())) + y, c);
+
+        let report = GradCheck::new().check(&mut g);
+        assert!(!report.passed());
+        assert_eq!(report.failures().len(), 1);
+        assert_eq!(report.failures()[0].variable, *x);
+    }
+}
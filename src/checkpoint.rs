@@ -0,0 +1,237 @@
+//! Checkpoint module
+//!
+//! Saving and restoring the variable values held by a `Context`.
+//!
+//! Variables are identified by their index in the context, so the
+//! `Variable` handles created before a checkpoint remain valid for
+//! the restored values.
+//!
+//! # Example
+//!
+//! ```
+//! use rugrads::Context;
+//!
+//! let mut context = Context::new();
+//! let x = context.create_variable(0.5);
+//!
+//! let snapshot = context.snapshot();
+//! context.set_variable_value(&x, 3.0);
+//!
+//! // Roll back to the snapshot
+//! context.restore(&snapshot);
+//! assert_eq!(context.get_variable_value(&x), 0.5);
+//! ```
+
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use num::Float;
+
+use ::Context;
+use ::serial::{Codec, Error, Json, Scalars, VERSION};
+
+/// The variable values of a `Context` at some point in time
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Snapshot<T> {
+    vars: Vec<T>,
+}
+
+impl<T> Snapshot<T> {
+    /// Returns the variable values held by the snapshot
+    pub fn values(&self) -> &[T] {
+        &self.vars
+    }
+}
+
+impl<T: Clone> Context<T> {
+    /// Takes a snapshot of the current variable values
+    pub fn snapshot(&self) -> Snapshot<T> {
+        Snapshot {
+            vars: self.vars.clone(),
+        }
+    }
+
+    /// Restores the variable values from a snapshot
+    ///
+    /// Variables created after the snapshot was taken keep their
+    /// current values.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the snapshot holds more variables
+    /// than this context.
+    pub fn restore(&mut self, snapshot: &Snapshot<T>) {
+        assert!(snapshot.vars.len() <= self.vars.len(),
+                "Snapshot holds more variables than the context");
+        self.vars[..snapshot.vars.len()].clone_from_slice(&snapshot.vars);
+    }
+}
+
+fn invalid_data(err: Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+}
+
+impl<T: Float> Context<T> {
+    /// Writes the variable values to a JSON document
+    pub fn to_json(&self) -> Json {
+        Json::object(vec![("format", Json::String("rugrads-context".to_string())),
+                          ("version", Json::Number(VERSION as f64)),
+                          ("vars", Json::Array(self.vars.iter().map(|v| Scalars.encode_const(v)).collect()))])
+    }
+
+    /// Reads a context from a JSON document written by `to_json`
+    pub fn from_json(json: &Json) -> Result<Context<T>, Error> {
+        if json.field("format")?.as_str()? != "rugrads-context" {
+            return Err(Error::new("Not a rugrads context"));
+        }
+        let version = json.field("version")?.as_u64()?;
+        if version > VERSION {
+            return Err(Error::new(&format!("Unsupported format version {}", version)));
+        }
+
+        let mut context = Context::new();
+        for v in json.field("vars")?.as_array()? {
+            context.vars.push(Scalars.decode_const(v)?);
+        }
+        Ok(context)
+    }
+
+    /// Saves the variable values to a file
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use rugrads::Context;
+    ///
+    /// let mut c = Context::new();
+    /// let x = c.create_variable(2.5);
+    /// c.save("checkpoint.json").unwrap();
+    ///
+    /// let c: Context<f64> = Context::load("checkpoint.json").unwrap();
+    /// assert_eq!(c.get_variable_value(&x), 2.5);
+    /// ```
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut file = File::create(path)?;
+        file.write_all(self.to_json().to_string().as_bytes())
+    }
+
+    /// Loads variable values saved by `save` into a new context
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Context<T>> {
+        let mut text = String::new();
+        File::open(path)?.read_to_string(&mut text)?;
+        let json = Json::parse(&text).map_err(invalid_data)?;
+        Context::from_json(&json).map_err(invalid_data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process;
+
+    use ::{Context, Gradient};
+    use ::functions::*;
+
+    #[test]
+    fn test_snapshot_restore() {
+        let mut c = Context::new();
+        let x = c.create_variable(0.5);
+        let snapshot = c.snapshot();
+
+        c.set_variable_value(&x, 2.0);
+        let y = c.create_variable(1.0);
+        c.restore(&snapshot);
+
+        assert_eq!(c.get_variable_value(&x), 0.5);
+        assert_eq!(c.get_variable_value(&y), 1.0);
+        assert_eq!(snapshot.values(), &[0.5]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_restore_too_many_vars() {
+        let mut c = Context::new();
+        c.create_variable(0.5);
+        let snapshot = c.snapshot();
+
+        let mut c2 = Context::new();
+        c2.restore(&snapshot);
+    }
+
+    #[test]
+    fn test_snapshot_in_gradient() {
+        let mut c = Context::new();
+        let x = c.create_variable(0.5);
+        let mut g = Gradient::of(sin(x), c);
+
+        let snapshot = g.context().snapshot();
+        g.context().set_variable_value(&x, 1.5);
+        g.context().restore(&snapshot);
+        assert!((g.grad(&x) - f64::cos(0.5)).abs() < 1e-10);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        use serde_json;
+        use ::{Expression, Variable};
+
+        let mut c = Context::new();
+        let x = c.create_variable(0.5);
+        let y = c.create_variable(-2.0);
+        (x * y).eval(&mut c);
+        assert!(c.node_count > 0);
+
+        // The node count is scratch space for evaluation and is not saved
+        let text = serde_json::to_string(&c).unwrap();
+        assert!(!text.contains("node_count"));
+        let mut loaded: Context<f64> = serde_json::from_str(&text).unwrap();
+        assert_eq!(loaded.node_count, 0);
+        assert_eq!(loaded.variables(), vec![*x, *y]);
+        assert_eq!(loaded.get_variable_value(&x), 0.5);
+        assert_eq!(loaded.get_variable_value(&y), -2.0);
+        assert_eq!(loaded.create_variable(1.0).inner(), &Variable(2));
+
+        let var: Variable = serde_json::from_str(&serde_json::to_string(&*y).unwrap()).unwrap();
+        assert_eq!(var, *y);
+
+        let snapshot = c.snapshot();
+        let text = serde_json::to_string(&snapshot).unwrap();
+        assert_eq!(serde_json::from_str::<::Snapshot<f64>>(&text).unwrap(), snapshot);
+    }
+
+    // A path which other test processes will not use
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("rugrads_{}_{}.json", name, process::id()))
+    }
+
+    #[test]
+    fn test_save_load() {
+        let mut c = Context::new();
+        let x = c.create_variable(0.1);
+        let y = c.create_variable(-3.25e-12);
+
+        let path = temp_path("save_load");
+        c.save(&path).unwrap();
+        let loaded: Context<f64> = Context::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.get_variable_value(&x), 0.1);
+        assert_eq!(loaded.get_variable_value(&y), -3.25e-12);
+    }
+
+    #[test]
+    fn test_load_invalid() {
+        let path = temp_path("load_invalid");
+        fs::File::create(&path).unwrap();
+        let loaded: Result<Context<f64>, _> = Context::load(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert!(loaded.is_err());
+    }
+}
@@ -0,0 +1,181 @@
+//! Complex functions
+//!
+//! Gradients with complex variables use the Wirtinger calculus. Every
+//! node `w` carries the adjoint `G_w = 2 ∂Re(L)/∂w`, where `L` is the
+//! output and `∂/∂w = (∂/∂u - i ∂/∂v) / 2` for `w = u + iv`. The output
+//! is seeded with one. For a holomorphic `L` this reduces to `L'(w)`.
+//!
+//! With this convention the holomorphic operations (`*`, `/`, `exp`, `ln`,
+//! `sin`, `cos`, `powf`) propagate `G_z = G_w f'(z)` exactly as for real
+//! numbers. The operations in this module are not holomorphic and are
+//! needed to build real valued losses:
+//!
+//! * `conj(z)` propagates `G_z = conj(G_w)`
+//! * `re(z)` propagates `G_z = Re(G_w)`
+//! * `im(z)` propagates `G_z = -i Re(G_w)`
+//! * `norm_sqr(z)` propagates `G_z = 2 Re(G_w) conj(z)`
+//!
+//! The last three produce real values stored with a zero imaginary part.
+//! For a real valued loss `Gradient::grad` returns `∂L/∂x - i ∂L/∂y` for
+//! each `z = x + iy`, so gradient descent steps along the conjugate.
+//!
+//! # Example
+//!
+//! ```
+//! extern crate num;
+//! extern crate rugrads;
+//!
+//! use num::Complex;
+//! use rugrads::{Context, Gradient};
+//! use rugrads::functions::*;
+//!
+//! # fn main() {
+//! let mut context = Context::new();
+//! let z = context.create_variable(Complex::new(1.0, 2.0));
+//!
+//! // L = |z|^2 = x^2 + y^2
+//! let mut grad = Gradient::of(norm_sqr(z), context);
+//!
+//! // ∂L/∂x - i ∂L/∂y = 2x - 2iy
+//! assert_eq!(grad.grad(&z), Complex::new(2.0, -4.0));
+//! # }
+//! ```
+
+use std::marker::PhantomData;
+
+use num::{Complex, Float};
+
+use ::{Container, Context, Expression, Node, VecJacProduct};
+
+macro_rules! complex_func {
+    ($name: ident, $vjp_name: ident, $f_name: ident, $doc: expr,
+     |$z: ident| $value: expr, |$g: ident, $x: ident| $vjp: expr) => {
+#[derive(Copy, Clone)]
+struct $vjp_name;
+
+impl<T: Float> VecJacProduct<Complex<T>> for $vjp_name {
+    fn vjp(&self, $g: Complex<T>, _: &Node<Complex<T>>, x: &Node<Complex<T>>, _: usize) -> Complex<T> {
+        let $x = x.value;
+        $vjp
+    }
+}
+
+#[doc = $doc]
+#[derive(Copy, Clone)]
+pub struct $name<T, X> {
+    x: X,
+    _marker: PhantomData<T>,
+}
+
+impl<T: Float, X: Expression<Complex<T>>> Expression<Complex<T>> for $name<T, X> {
+    fn eval(&self, c: &mut Context<Complex<T>>) -> Node<Complex<T>> {
+        let x_eval = self.x.eval(c);
+        let parents = vec![x_eval];
+        let progenitors = Node::get_progenitors(&parents);
+
+        let $z = parents[0].value;
+        Node {
+            index: c.get_index(),
+            value: $value,
+            parents: parents,
+            progenitors: progenitors,
+            _vjp: Box::new($vjp_name),
+        }
+    }
+}
+
+#[doc = $doc]
+pub fn $f_name<T, E>(x: Container<Complex<T>, E>) -> Container<Complex<T>, $name<T, E>>
+    where T: Float, E: Expression<Complex<T>>
+{
+    Container::new($name { x: x.inner, _marker: PhantomData })
+}
+    };
+}
+
+complex_func!(Conj, ConjVJP, conj, "Complex conjugate",
+              |z| z.conj(),
+              |g, _x| g.conj());
+complex_func!(Re, ReVJP, re, "Real part",
+              |z| Complex::new(z.re, T::zero()),
+              |g, _x| Complex::new(g.re, T::zero()));
+complex_func!(Im, ImVJP, im, "Imaginary part",
+              |z| Complex::new(z.im, T::zero()),
+              |g, _x| Complex::new(T::zero(), -g.re));
+complex_func!(NormSqr, NormSqrVJP, norm_sqr, "Squared modulus",
+              |z| Complex::new(z.norm_sqr(), T::zero()),
+              |g, x| x.conj() * (g.re + g.re));
+
+#[cfg(test)]
+mod tests {
+    use num::Complex;
+
+    use ::{Context, Expression, Gradient, Variable};
+    use ::functions::*;
+
+    type C64 = Complex<f64>;
+
+    fn close(a: C64, b: C64) -> bool {
+        (a - b).norm() < 1e-6 * (1.0 + b.norm())
+    }
+
+    // ∂L/∂x - i ∂L/∂y by central differences
+    fn numeric_grad<E: Expression<C64>>(g: &mut Gradient<C64, E>, z: &Variable) -> C64 {
+        let h = 1e-6;
+        let z0 = *g.get(z);
+        let mut diff = |dz: C64| {
+            g.context().set_variable_value(z, z0 + dz);
+            let plus = g.value().re;
+            g.context().set_variable_value(z, z0 - dz);
+            let minus = g.value().re;
+            g.context().set_variable_value(z, z0);
+            (plus - minus) / (2.0 * h)
+        };
+        let dx = diff(Complex::new(h, 0.0));
+        let dy = diff(Complex::new(0.0, h));
+        Complex::new(dx, -dy)
+    }
+
+    #[test]
+    fn test_holomorphic() {
+        let z0 = Complex::new(0.6, -0.4);
+        let mut c = Context::new();
+        let z = c.create_variable(z0);
+        let f = z * sin(z) / exp(z) + powf(ln(z), Complex::new(2.0, 0.0)) - cos(z);
+
+        let mut g = Gradient::of(f, c);
+        let expected = (z0.sin() + z0 * z0.cos() - z0 * z0.sin()) / z0.exp()
+            + 2.0 * z0.ln() / z0 + z0.sin();
+        assert!(close(g.grad(&z), expected));
+    }
+
+    #[test]
+    fn test_real_loss() {
+        let mut c = Context::new();
+        let z = c.create_variable(Complex::new(0.3, 1.2));
+        let w = c.create_variable(Complex::new(-0.7, 0.5));
+        let loss = norm_sqr(z * conj(w) - exp(w)) + re(z * z) - im(w / z);
+
+        let mut g = Gradient::of(loss, c);
+        for var in &[z, w] {
+            let analytic = g.grad(var);
+            let numeric = numeric_grad(&mut g, var);
+            assert!(close(analytic, numeric), "{} != {}", analytic, numeric);
+        }
+    }
+
+    #[test]
+    fn test_descent() {
+        // Minimise |z - (1 + 2i)|^2
+        let mut c = Context::new();
+        let z = c.create_variable(Complex::new(0.0, 0.0));
+        let target = c.create_variable(Complex::new(1.0, 2.0));
+        let mut g = Gradient::of(norm_sqr(z - target), c);
+
+        for _ in 0..100 {
+            let step = g.grad(&z).conj() * 0.1;
+            *g.get_mut(&z) = *g.get(&z) - step;
+        }
+        assert!(close(*g.get(&z), Complex::new(1.0, 2.0)));
+    }
+}
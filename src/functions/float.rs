@@ -1,16 +1,20 @@
 use num::Float;
 
+use ::Scalar;
+
 use std::marker::PhantomData;
 
-use ::{Expression, VecJacProduct, Node};
+use ::{Expression, VecJacProduct, Node, LeafVar};
 use ::{Container, Context};
+use ::term::{Func, Func2, Term, ToTerm};
+use ::serial::{Codec, Encode, Json};
 
-struct LinVJP<T: Float, F: Fn(T) -> T> {
+struct LinVJP<T: Scalar, F: Fn(T) -> T> {
     f: F,
     _marker: PhantomData<T>
 }
 
-impl<T: Float, F: Fn(T) -> T> LinVJP<T, F> {
+impl<T: Scalar, F: Fn(T) -> T> LinVJP<T, F> {
     fn new(f: F) -> Self {
         LinVJP {
             f: f,
@@ -19,19 +23,19 @@ impl<T: Float, F: Fn(T) -> T> LinVJP<T, F> {
     }
 }
 
-impl<T: Float, F: Fn(T) -> T> VecJacProduct<T> for LinVJP<T, F> {
+impl<T: Scalar, F: Fn(T) -> T> VecJacProduct<T> for LinVJP<T, F> {
     fn vjp(&self, g: T, _:&Node<T>, x: &Node<T>, _: usize) -> T {
         g * (self.f)(x.value)
     }
 }
 
 /// Sine operator
-pub struct Sin<T: Float, X: Expression<T>> {
+pub struct Sin<T: Scalar, X: Expression<T>> {
     x: X,
     _marker: PhantomData<T>
 }
 
-impl<T: Float, X: Expression<T>> Sin<T, X> {
+impl<T: Scalar, X: Expression<T>> Sin<T, X> {
     fn new(x: X) -> Self {
         Sin {
             x: x,
@@ -40,7 +44,7 @@ impl<T: Float, X: Expression<T>> Sin<T, X> {
     }
 }
 
-impl<T: Float, X: Expression<T>> Expression<T> for Sin<T, X> {
+impl<T: Scalar, X: Expression<T>> Expression<T> for Sin<T, X> {
     fn eval(&self, c: &mut Context<T>) -> Node<T> {
         let x_eval = self.x.eval(c);
         let parents = vec![x_eval];
@@ -56,20 +60,32 @@ impl<T: Float, X: Expression<T>> Expression<T> for Sin<T, X> {
     }
 }
 
+impl<T: Scalar, X: Expression<T> + ToTerm<T>> ToTerm<T> for Sin<T, X> {
+    fn to_term(&self) -> Term<T> {
+        Term::Apply(Func::Sin, Box::new(self.x.to_term()))
+    }
+}
+
+impl<T: Scalar, C, X: Expression<T> + Encode<C>> Encode<C> for Sin<T, X> {
+    fn encode(&self, codec: &C) -> Json {
+        Json::op("sin", vec![self.x.encode(codec)])
+    }
+}
+
 /// Sine function
 pub fn sin<T, E>(x: Container<T, E>) -> Container<T, Sin<T, E>>
-    where T: Float, E: Expression<T>
+    where T: Scalar, E: Expression<T>
 {
     Container::new(Sin::new(x.inner))
 }
 
 /// Cosine operator
-pub struct Cos<T: Float, X: Expression<T>> {
+pub struct Cos<T: Scalar, X: Expression<T>> {
     x: X,
     _marker: PhantomData<T>
 }
 
-impl<T: Float, X: Expression<T>> Cos<T, X> {
+impl<T: Scalar, X: Expression<T>> Cos<T, X> {
     fn new(x: X) -> Self {
         Cos {
             x: x,
@@ -78,7 +94,7 @@ impl<T: Float, X: Expression<T>> Cos<T, X> {
     }
 }
 
-impl<T: Float, X: Expression<T>> Expression<T> for Cos<T, X> {
+impl<T: Scalar, X: Expression<T>> Expression<T> for Cos<T, X> {
     fn eval(&self, c: &mut Context<T>) -> Node<T> {
         let x_eval = self.x.eval(c);
         let parents = vec![x_eval];
@@ -94,20 +110,32 @@ impl<T: Float, X: Expression<T>> Expression<T> for Cos<T, X> {
     }
 }
 
+impl<T: Scalar, X: Expression<T> + ToTerm<T>> ToTerm<T> for Cos<T, X> {
+    fn to_term(&self) -> Term<T> {
+        Term::Apply(Func::Cos, Box::new(self.x.to_term()))
+    }
+}
+
+impl<T: Scalar, C, X: Expression<T> + Encode<C>> Encode<C> for Cos<T, X> {
+    fn encode(&self, codec: &C) -> Json {
+        Json::op("cos", vec![self.x.encode(codec)])
+    }
+}
+
 /// Cosine function
 pub fn cos<T, E>(x: Container<T, E>) -> Container<T, Cos<T, E>>
-    where T:Float, E: Expression<T>
+    where T: Scalar, E: Expression<T>
 {
     Container::new(Cos::new(x.inner))
 }
 
 /// Exponential operator
-pub struct Exp<T: Float, X: Expression<T>> {
+pub struct Exp<T: Scalar, X: Expression<T>> {
     x: X,
     _marker: PhantomData<T>
 }
 
-impl<T: Float, X: Expression<T>> Exp<T, X> {
+impl<T: Scalar, X: Expression<T>> Exp<T, X> {
     fn new(x: X) -> Self {
         Exp {
             x: x,
@@ -116,7 +144,7 @@ impl<T: Float, X: Expression<T>> Exp<T, X> {
     }
 }
 
-impl<T: Float, X: Expression<T>> Expression<T> for Exp<T, X> {
+impl<T: Scalar, X: Expression<T>> Expression<T> for Exp<T, X> {
     fn eval(&self, c: &mut Context<T>) -> Node<T> {
         let x_eval = self.x.eval(c);
         let parents = vec![x_eval];
@@ -132,20 +160,32 @@ impl<T: Float, X: Expression<T>> Expression<T> for Exp<T, X> {
     }
 }
 
+impl<T: Scalar, X: Expression<T> + ToTerm<T>> ToTerm<T> for Exp<T, X> {
+    fn to_term(&self) -> Term<T> {
+        Term::Apply(Func::Exp, Box::new(self.x.to_term()))
+    }
+}
+
+impl<T: Scalar, C, X: Expression<T> + Encode<C>> Encode<C> for Exp<T, X> {
+    fn encode(&self, codec: &C) -> Json {
+        Json::op("exp", vec![self.x.encode(codec)])
+    }
+}
+
 /// Exponential function
 pub fn exp<T, E>(x: Container<T, E>) -> Container<T, Exp<T, E>>
-    where T:Float, E: Expression<T>
+    where T: Scalar, E: Expression<T>
 {
     Container::new(Exp::new(x.inner))
 }
 
 /// Natural Logarithm operator
-pub struct Ln<T: Float, X: Expression<T>> {
+pub struct Ln<T: Scalar, X: Expression<T>> {
     x: X,
     _marker: PhantomData<T>
 }
 
-impl<T: Float, X: Expression<T>> Ln<T, X> {
+impl<T: Scalar, X: Expression<T>> Ln<T, X> {
     fn new(x: X) -> Self {
         Ln {
             x: x,
@@ -154,7 +194,7 @@ impl<T: Float, X: Expression<T>> Ln<T, X> {
     }
 }
 
-impl<T: Float, X: Expression<T>> Expression<T> for Ln<T, X> {
+impl<T: Scalar, X: Expression<T>> Expression<T> for Ln<T, X> {
     fn eval(&self, c: &mut Context<T>) -> Node<T> {
         let x_eval = self.x.eval(c);
         let parents = vec![x_eval];
@@ -165,25 +205,37 @@ impl<T: Float, X: Expression<T>> Expression<T> for Ln<T, X> {
             value: T::ln(parents[0].value),
             parents: parents,
             progenitors: progenitors,
-            _vjp: Box::new(LinVJP::new(T::recip)),
+            _vjp: Box::new(LinVJP::new(|x| T::one() / x)),
         }
     }
 }
 
+impl<T: Scalar, X: Expression<T> + ToTerm<T>> ToTerm<T> for Ln<T, X> {
+    fn to_term(&self) -> Term<T> {
+        Term::Apply(Func::Ln, Box::new(self.x.to_term()))
+    }
+}
+
+impl<T: Scalar, C, X: Expression<T> + Encode<C>> Encode<C> for Ln<T, X> {
+    fn encode(&self, codec: &C) -> Json {
+        Json::op("ln", vec![self.x.encode(codec)])
+    }
+}
+
 /// Natural Logarithm function
 pub fn ln<T, E>(x: Container<T, E>) -> Container<T, Ln<T, E>>
-    where T:Float, E: Expression<T>
+    where T: Scalar, E: Expression<T>
 {
     Container::new(Ln::new(x.inner))
 }
 
 /// Power raising operator
-pub struct Powf<T: Float, X: Expression<T>> {
+pub struct Powf<T: Scalar, X: Expression<T>> {
     x: X,
     n: T
 }
 
-impl<T: Float, X: Expression<T>> Powf<T, X> {
+impl<T: Scalar, X: Expression<T>> Powf<T, X> {
     fn new(x: X, n: T) -> Self {
         Powf {
             x: x,
@@ -192,7 +244,7 @@ impl<T: Float, X: Expression<T>> Powf<T, X> {
     }
 }
 
-impl<T: Float, X: Expression<T>> Expression<T> for Powf<T, X> {
+impl<T: Scalar, X: Expression<T>> Expression<T> for Powf<T, X> {
     fn eval(&self, c: &mut Context<T>) -> Node<T> {
         let x_eval = self.x.eval(c);
         let parents = vec![x_eval];
@@ -209,26 +261,299 @@ impl<T: Float, X: Expression<T>> Expression<T> for Powf<T, X> {
     }
 }
 
+impl<T: Scalar, X: Expression<T> + ToTerm<T>> ToTerm<T> for Powf<T, X> {
+    fn to_term(&self) -> Term<T> {
+        Term::Powf(Box::new(self.x.to_term()), self.n)
+    }
+}
+
+impl<T, C, X> Encode<C> for Powf<T, X>
+    where T: Scalar,
+          C: Codec<T>,
+          X: Expression<T> + Encode<C>
+{
+    fn encode(&self, codec: &C) -> Json {
+        Json::op("powf", vec![self.x.encode(codec)]).with("n", codec.encode_const(&self.n))
+    }
+}
+
 /// Natural Logarithm function
 pub fn powf<T, E>(x: Container<T, E>, n: T) -> Container<T, Powf<T, E>>
-    where T:Float, E: Expression<T>
+    where T: Scalar, E: Expression<T>
 {
     Container::new(Powf::new(x.inner, n))
 }
 
+struct FuncVJP(Func);
+
+impl<T: Float> VecJacProduct<T> for FuncVJP {
+    fn vjp(&self, g: T, _: &Node<T>, x: &Node<T>, _: usize) -> T {
+        g * self.0.deriv(x.value)
+    }
+}
+
+macro_rules! float_func {
+    ($name: ident, $f_name: ident, $func: expr, $op_doc: expr, $fn_doc: expr) => {
+#[doc = $op_doc]
+pub struct $name<T: Float, X: Expression<T>> {
+    x: X,
+    _marker: PhantomData<T>
+}
+
+impl<T: Float, X: Expression<T>> Expression<T> for $name<T, X> {
+    fn eval(&self, c: &mut Context<T>) -> Node<T> {
+        let x_eval = self.x.eval(c);
+        let parents = vec![x_eval];
+        let progenitors = Node::get_progenitors(&parents);
+
+        Node {
+            index: c.get_index(),
+            value: $func.apply(parents[0].value),
+            parents: parents,
+            progenitors: progenitors,
+            _vjp: Box::new(FuncVJP($func)),
+        }
+    }
+}
+
+impl<T: Float, X: Expression<T> + ToTerm<T>> ToTerm<T> for $name<T, X> {
+    fn to_term(&self) -> Term<T> {
+        Term::Apply($func, Box::new(self.x.to_term()))
+    }
+}
+
+impl<T: Float, C, X: Expression<T> + Encode<C>> Encode<C> for $name<T, X> {
+    fn encode(&self, codec: &C) -> Json {
+        Json::op($func.name(), vec![self.x.encode(codec)])
+    }
+}
+
+#[doc = $fn_doc]
+pub fn $f_name<T, E>(x: Container<T, E>) -> Container<T, $name<T, E>>
+    where T: Float, E: Expression<T>
+{
+    Container::new($name { x: x.inner, _marker: PhantomData })
+}
+    };
+}
+
+float_func!(Tan, tan, Func::Tan, "Tangent operator", "Tangent function");
+float_func!(Asin, asin, Func::Asin, "Inverse Sine operator",
+            "Inverse Sine function, defined for `-1 < x < 1`");
+float_func!(Acos, acos, Func::Acos, "Inverse Cosine operator",
+            "Inverse Cosine function, defined for `-1 < x < 1`");
+float_func!(Atan, atan, Func::Atan, "Inverse Tangent operator", "Inverse Tangent function");
+float_func!(Sinh, sinh, Func::Sinh, "Hyperbolic Sine operator", "Hyperbolic Sine function");
+float_func!(Cosh, cosh, Func::Cosh, "Hyperbolic Cosine operator", "Hyperbolic Cosine function");
+float_func!(Tanh, tanh, Func::Tanh, "Hyperbolic Tangent operator", "Hyperbolic Tangent function");
+float_func!(Asinh, asinh, Func::Asinh, "Inverse Hyperbolic Sine operator",
+            "Inverse Hyperbolic Sine function");
+float_func!(Acosh, acosh, Func::Acosh, "Inverse Hyperbolic Cosine operator",
+            "Inverse Hyperbolic Cosine function, defined for `x > 1`");
+float_func!(Atanh, atanh, Func::Atanh, "Inverse Hyperbolic Tangent operator",
+            "Inverse Hyperbolic Tangent function, defined for `-1 < x < 1`");
+float_func!(Sqrt, sqrt, Func::Sqrt, "Square Root operator",
+            "Square Root function, the gradient is infinite at zero");
+float_func!(Cbrt, cbrt, Func::Cbrt, "Cube Root operator",
+            "Real Cube Root function, the gradient is infinite at zero");
+float_func!(Log2, log2, Func::Log2, "Base 2 Logarithm operator", "Base 2 Logarithm function");
+float_func!(Log10, log10, Func::Log10, "Base 10 Logarithm operator", "Base 10 Logarithm function");
+float_func!(Exp2, exp2, Func::Exp2, "Base 2 Exponential operator", "Base 2 Exponential function");
+float_func!(ExpM1, exp_m1, Func::ExpM1, "Exponential minus one operator",
+            "Computes `exp(x) - 1` accurately for `x` near zero");
+float_func!(Ln1p, ln_1p, Func::Ln1p, "Logarithm of one plus operator",
+            "Computes `ln(1 + x)` accurately for `x` near zero");
+float_func!(Erf, erf, Func::Erf, "Error Function operator", "Error Function");
+float_func!(Erfc, erfc, Func::Erfc, "Complementary Error Function operator",
+            "Complementary Error Function, accurate for large `x`");
+float_func!(Gamma, gamma, Func::Gamma, "Gamma Function operator", "Gamma Function");
+float_func!(Lgamma, lgamma, Func::Lgamma, "Log Gamma operator",
+            "Logarithm of the absolute value of the Gamma Function");
+float_func!(Digamma, digamma, Func::Digamma, "Digamma Function operator",
+            "Digamma Function, the derivative of `lgamma`");
+float_func!(Trigamma, trigamma, Func::Trigamma, "Trigamma Function operator",
+            "Trigamma Function, the derivative of `digamma`");
+float_func!(Abs, abs, Func::Abs, "Absolute Value operator",
+            "Absolute Value function, the gradient at zero is zero");
+float_func!(Sign, sign, Func::Sign, "Sign operator",
+            "Sign function, which is zero at zero and has a zero gradient");
+float_func!(Floor, floor, Func::Floor, "Floor operator", "Floor function, with a zero gradient");
+float_func!(Ceil, ceil, Func::Ceil, "Ceiling operator", "Ceiling function, with a zero gradient");
+float_func!(Round, round, Func::Round, "Rounding operator",
+            "Rounds half way cases away from zero, with a zero gradient");
+float_func!(Relu, relu, Func::Relu, "Rectified Linear Unit operator",
+            "Rectified Linear Unit, `max(x, 0)`, the gradient at zero is one half");
+
+struct Func2VJP<T>(Func2, T, T);
+
+impl<T: Float> VecJacProduct<T> for Func2VJP<T> {
+    fn vjp(&self, g: T, _: &Node<T>, _: &Node<T>, idx: usize) -> T {
+        let (dx, dy) = self.0.deriv(self.1, self.2);
+        if idx == 0 { g * dx } else { g * dy }
+    }
+}
+
+macro_rules! float_func2 {
+    ($name: ident, $f_name: ident, $func: expr, ($x: ident, $y: ident), $op_doc: expr,
+     $(#[$fn_attr: meta])*) => {
+#[doc = $op_doc]
+pub struct $name<T: Float, X: Expression<T>, Y: Expression<T>> {
+    $x: X,
+    $y: Y,
+    _marker: PhantomData<T>
+}
+
+impl<T: Float, X: Expression<T>, Y: Expression<T>> Expression<T> for $name<T, X, Y> {
+    fn eval(&self, c: &mut Context<T>) -> Node<T> {
+        let x_eval = self.$x.eval(c);
+        let y_eval = self.$y.eval(c);
+        let parents = vec![x_eval, y_eval];
+        let progenitors = Node::get_progenitors(&parents);
+
+        let (x, y) = (parents[0].value, parents[1].value);
+        Node {
+            index: c.get_index(),
+            value: $func.apply(x, y),
+            parents: parents,
+            progenitors: progenitors,
+            _vjp: Box::new(Func2VJP($func, x, y)),
+        }
+    }
+}
+
+impl<T, X, Y> ToTerm<T> for $name<T, X, Y>
+    where T: Float, X: Expression<T> + ToTerm<T>, Y: Expression<T> + ToTerm<T>
+{
+    fn to_term(&self) -> Term<T> {
+        Term::Apply2($func, Box::new(self.$x.to_term()), Box::new(self.$y.to_term()))
+    }
+}
+
+impl<T, C, X, Y> Encode<C> for $name<T, X, Y>
+    where T: Float, X: Expression<T> + Encode<C>, Y: Expression<T> + Encode<C>
+{
+    fn encode(&self, codec: &C) -> Json {
+        Json::op($func.name(), vec![self.$x.encode(codec), self.$y.encode(codec)])
+    }
+}
+
+$(#[$fn_attr])*
+pub fn $f_name<T, E1, E2>($x: Container<T, E1>, $y: Container<T, E2>) -> Container<T, $name<T, E1, E2>>
+    where T: Float, E1: Expression<T>, E2: Expression<T>
+{
+    Container::new($name { $x: $x.inner, $y: $y.inner, _marker: PhantomData })
+}
+    };
+}
+
+float_func2!(Atan2, atan2, Func2::Atan2, (y, x), "Four quadrant inverse tangent operator",
+    /// Four quadrant inverse tangent function
+    ///
+    /// Returns the angle of the point `(x, y)` in `(-pi, pi]`. The gradient
+    /// is undefined at the origin.
+);
+float_func2!(Hypot, hypot, Func2::Hypot, (x, y), "Hypotenuse operator",
+    /// Length of the hypotenuse, `sqrt(x^2 + y^2)`
+    ///
+    /// This avoids overflow for large arguments. The gradient at the origin
+    /// is taken to be zero.
+);
+float_func2!(Log, log, Func2::Log, (x, base), "Logarithm operator",
+    /// Logarithm of `x` to the base `base`
+);
+float_func2!(Lbeta, lbeta, Func2::Lbeta, (a, b), "Log Beta operator",
+    /// Logarithm of the Beta Function, `lgamma(a) + lgamma(b) - lgamma(a + b)`
+);
+float_func2!(Min, min, Func2::Min, (x, y), "Minimum operator",
+    /// The smaller of two values
+    ///
+    /// When the values are equal the gradient is split evenly between them.
+);
+float_func2!(Max, max, Func2::Max, (x, y), "Maximum operator",
+    /// The larger of two values
+    ///
+    /// When the values are equal the gradient is split evenly between them.
+);
+
+float_func2!(Pow, pow, Func2::Pow, (base, exponent), "Power operator",
+    /// Raises `base` to the power `exponent`, where both are expressions
+    ///
+    /// The gradient with respect to the exponent is `base^exponent ln(base)`,
+    /// which is taken to be zero where the result is zero and is `NaN` for
+    /// a negative base.
+);
+
+/// Integer power operator
+pub struct Powi<T: Float, X: Expression<T>> {
+    x: X,
+    n: i32,
+    _marker: PhantomData<T>
+}
+
+impl<T: Float, X: Expression<T>> Expression<T> for Powi<T, X> {
+    fn eval(&self, c: &mut Context<T>) -> Node<T> {
+        let x_eval = self.x.eval(c);
+        let parents = vec![x_eval];
+        let progenitors = Node::get_progenitors(&parents);
+
+        Node {
+            index: c.get_index(),
+            value: parents[0].value.powi(self.n),
+            parents: parents,
+            progenitors: progenitors,
+            _vjp: Box::new(FuncVJP(Func::Powi(self.n))),
+        }
+    }
+}
+
+impl<T: Float, X: Expression<T> + ToTerm<T>> ToTerm<T> for Powi<T, X> {
+    fn to_term(&self) -> Term<T> {
+        Term::Apply(Func::Powi(self.n), Box::new(self.x.to_term()))
+    }
+}
+
+impl<T: Float, C, X: Expression<T> + Encode<C>> Encode<C> for Powi<T, X> {
+    fn encode(&self, codec: &C) -> Json {
+        Json::op("powi", vec![self.x.encode(codec)]).with("n", Json::Number(self.n as f64))
+    }
+}
+
+/// Raises an expression to an integer power
+///
+/// Unlike `powf` this is exact for negative values of `x`.
+pub fn powi<T, E>(x: Container<T, E>, n: i32) -> Container<T, Powi<T, E>>
+    where T: Float, E: Expression<T>
+{
+    Container::new(Powi { x: x.inner, n: n, _marker: PhantomData })
+}
+
+/// Clamps a value to the interval `[low, high]`
+///
+/// This is `min(max(x, low), high)`, so the gradient is one inside the
+/// interval, zero outside it and one half on either bound.
+pub fn clamp<T, E>(x: Container<T, E>, low: T, high: T)
+    -> Container<T, Min<T, Max<T, E, LeafVar<T>>, LeafVar<T>>>
+    where T: Float, E: Expression<T>
+{
+    min(max(x, Container::new(LeafVar(low))), Container::new(LeafVar(high)))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use ::functions::{Add, Mul};
     use ::{Context, Expression};
 
+    use std::f64::consts::PI;
+
     use ::LeafVar;
+    use ::special;
     
 
     #[test]
     fn test_add() {
         let mut c = Context::new();
-        let f = Add::new(LeafVar(1.0), LeafVar(1.0));
+        let f = Add::new(LeafVar(1.0f64), LeafVar(1.0));
         let node = f.eval(&mut c);
         // Just a dummy node
         let x = &node.parents[0];
@@ -239,7 +564,7 @@ mod tests {
     #[test]
     fn test_mul() {
         let mut c = Context::new();
-        let f = Mul::new(LeafVar(0.5), LeafVar(0.3));
+        let f = Mul::new(LeafVar(0.5f64), LeafVar(0.3));
         let node = f.eval(&mut c);
         // Just a dummy node
         let x = &node.parents[0];
@@ -309,4 +634,251 @@ mod tests {
         assert!((node.value - f64::powf(3.0, -1.3)).abs() < 1e-5);
         assert!((node.vjp(1.0, x, 0) + 1.3 * f64::powf(3.0, -2.3)).abs() < 1e-5);
     }
+
+    fn vjp_at<T: Expression<f64>>(f: T) -> (f64, f64) {
+        let mut c = Context::new();
+        let node = f.eval(&mut c);
+        let x = &node.parents[0];
+        (node.value, node.vjp(1.0, x, 0))
+    }
+
+    #[test]
+    fn test_trig() {
+        let x = 0.5f64;
+        let cases = vec![
+            (vjp_at(Tan { x: LeafVar(x), _marker: PhantomData }), x.tan(), 1.0 / x.cos().powi(2)),
+            (vjp_at(Asin { x: LeafVar(x), _marker: PhantomData }), x.asin(), 1.0 / (1.0 - x * x).sqrt()),
+            (vjp_at(Acos { x: LeafVar(x), _marker: PhantomData }), x.acos(), -1.0 / (1.0 - x * x).sqrt()),
+            (vjp_at(Atan { x: LeafVar(x), _marker: PhantomData }), x.atan(), 1.0 / (1.0 + x * x)),
+        ];
+        for ((value, deriv), expected, expected_deriv) in cases {
+            assert!((value - expected).abs() < 1e-12);
+            assert!((deriv - expected_deriv).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_hyperbolic() {
+        let x = 0.5f64;
+        let cases = vec![
+            (vjp_at(Sinh { x: LeafVar(x), _marker: PhantomData }), x.sinh(), x.cosh()),
+            (vjp_at(Cosh { x: LeafVar(x), _marker: PhantomData }), x.cosh(), x.sinh()),
+            (vjp_at(Tanh { x: LeafVar(x), _marker: PhantomData }), x.tanh(), 1.0 / x.cosh().powi(2)),
+            (vjp_at(Asinh { x: LeafVar(x), _marker: PhantomData }), x.asinh(), 1.0 / (x * x + 1.0).sqrt()),
+            (vjp_at(Acosh { x: LeafVar(x + 1.0), _marker: PhantomData }), (x + 1.0).acosh(),
+             1.0 / ((x + 1.0).powi(2) - 1.0).sqrt()),
+            (vjp_at(Atanh { x: LeafVar(x), _marker: PhantomData }), x.atanh(), 1.0 / (1.0 - x * x)),
+        ];
+        for ((value, deriv), expected, expected_deriv) in cases {
+            assert!((value - expected).abs() < 1e-12);
+            assert!((deriv - expected_deriv).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_atan2() {
+        // Every quadrant, including the branch cut side
+        for &(y, x) in &[(1.0f64, 2.0), (1.0, -2.0), (-1.0, -2.0), (-1.0, 2.0)] {
+            let mut c = Context::new();
+            let f = Atan2 { y: LeafVar(y), x: LeafVar(x), _marker: PhantomData };
+            let node = f.eval(&mut c);
+            let r_sq = x * x + y * y;
+            assert!((node.value - y.atan2(x)).abs() < 1e-12);
+            assert!((node.vjp(1.0, &node.parents[0], 0) - x / r_sq).abs() < 1e-12);
+            assert!((node.vjp(1.0, &node.parents[1], 1) + y / r_sq).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_roots_and_logs() {
+        let x = 0.5f64;
+        let cases = vec![
+            (vjp_at(Sqrt { x: LeafVar(x), _marker: PhantomData }), x.sqrt(), 0.5 / x.sqrt()),
+            (vjp_at(Cbrt { x: LeafVar(-x), _marker: PhantomData }), -x.cbrt(), 1.0 / (3.0 * x.cbrt().powi(2))),
+            (vjp_at(Log2 { x: LeafVar(x), _marker: PhantomData }), x.log2(), 1.0 / (x * f64::ln(2.0))),
+            (vjp_at(Log10 { x: LeafVar(x), _marker: PhantomData }), x.log10(), 1.0 / (x * f64::ln(10.0))),
+            (vjp_at(Exp2 { x: LeafVar(x), _marker: PhantomData }), x.exp2(), x.exp2() * f64::ln(2.0)),
+            (vjp_at(ExpM1 { x: LeafVar(x), _marker: PhantomData }), x.exp_m1(), x.exp()),
+            (vjp_at(Ln1p { x: LeafVar(x), _marker: PhantomData }), x.ln_1p(), 1.0 / (1.0 + x)),
+        ];
+        for ((value, deriv), expected, expected_deriv) in cases {
+            assert!((value - expected).abs() < 1e-12);
+            assert!((deriv - expected_deriv).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_near_zero() {
+        // Composing from `exp` and `ln` loses every significant digit here
+        let x = 1e-12f64;
+        let (value, deriv) = vjp_at(ExpM1 { x: LeafVar(x), _marker: PhantomData });
+        assert!((value - x).abs() < 1e-24);
+        assert!((deriv - 1.0).abs() < 1e-11);
+        let (value, deriv) = vjp_at(Ln1p { x: LeafVar(x), _marker: PhantomData });
+        assert!((value - x).abs() < 1e-24);
+        assert!((deriv - 1.0).abs() < 1e-11);
+    }
+
+    #[test]
+    fn test_hypot_and_log() {
+        let mut c = Context::new();
+        let f = Hypot { x: LeafVar(3.0f64), y: LeafVar(4.0), _marker: PhantomData };
+        let node = f.eval(&mut c);
+        assert!((node.value - 5.0).abs() < 1e-12);
+        assert!((node.vjp(1.0, &node.parents[0], 0) - 0.6).abs() < 1e-12);
+        assert!((node.vjp(1.0, &node.parents[1], 1) - 0.8).abs() < 1e-12);
+
+        // Zero subgradient rather than NaN at the origin
+        let f = Hypot { x: LeafVar(0.0f64), y: LeafVar(0.0), _marker: PhantomData };
+        let node = f.eval(&mut c);
+        assert_eq!(node.vjp(1.0, &node.parents[0], 0), 0.0);
+        assert_eq!(node.vjp(1.0, &node.parents[1], 1), 0.0);
+
+        let f = Log { x: LeafVar(8.0f64), base: LeafVar(2.0), _marker: PhantomData };
+        let node = f.eval(&mut c);
+        assert!((node.value - 3.0).abs() < 1e-12);
+        assert!((node.vjp(1.0, &node.parents[0], 0) - 1.0 / (8.0 * f64::ln(2.0))).abs() < 1e-12);
+        assert!((node.vjp(1.0, &node.parents[1], 1) + 3.0 / (2.0 * f64::ln(2.0))).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_special() {
+        let x = 2.5f64;
+        let cases = vec![
+            (vjp_at(Erf { x: LeafVar(0.5), _marker: PhantomData }), 0.5204998778130465,
+             2.0 / PI.sqrt() * (-0.25f64).exp()),
+            (vjp_at(Erfc { x: LeafVar(0.5), _marker: PhantomData }), 1.0 - 0.5204998778130465,
+             -2.0 / PI.sqrt() * (-0.25f64).exp()),
+            (vjp_at(Gamma { x: LeafVar(x), _marker: PhantomData }), special::gamma(x),
+             special::gamma(x) * special::digamma(x)),
+            (vjp_at(Lgamma { x: LeafVar(x), _marker: PhantomData }), special::lgamma(x), special::digamma(x)),
+            (vjp_at(Digamma { x: LeafVar(x), _marker: PhantomData }), special::digamma(x), special::trigamma(x)),
+            (vjp_at(Trigamma { x: LeafVar(x), _marker: PhantomData }), special::trigamma(x),
+             special::polygamma(2, x)),
+        ];
+        for ((value, deriv), expected, expected_deriv) in cases {
+            assert!((value - expected).abs() < 1e-12);
+            assert!((deriv - expected_deriv).abs() < 1e-12);
+        }
+
+        // Digamma is the derivative of lgamma
+        let h = 1e-5;
+        let numeric = (special::lgamma(x + h) - special::lgamma(x - h)) / (2.0 * h);
+        assert!((special::digamma(x) - numeric).abs() < 1e-9);
+
+        let mut c = Context::new();
+        let f = Lbeta { a: LeafVar(2.0f64), b: LeafVar(3.0), _marker: PhantomData };
+        let node = f.eval(&mut c);
+        assert!((node.value - (1.0f64 / 12.0).ln()).abs() < 1e-12);
+        // digamma(2) - digamma(5) = -(1/2 + 1/3 + 1/4)
+        assert!((node.vjp(1.0, &node.parents[0], 0) + 13.0 / 12.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_gamma_log_likelihood() {
+        use ::check::GradCheck;
+        use ::functions::*;
+        use ::Gradient;
+
+        // Log density of a Gamma(shape, rate) distribution at a fixed point
+        let mut c = Context::new();
+        let shape = c.create_variable(2.5f64);
+        let rate = c.create_variable(1.5);
+        let x = Container::new(LeafVar(0.8));
+        let log_pdf = shape * ln(rate) - lgamma(shape) + (shape - Container::new(LeafVar(1.0))) * ln(x)
+            - rate * x;
+
+        let mut g = Gradient::of(log_pdf, c);
+        assert!((g.grad(&shape) - (1.5f64.ln() - special::digamma(2.5) + 0.8f64.ln())).abs() < 1e-12);
+        assert!((g.grad(&rate) - (2.5 / 1.5 - 0.8)).abs() < 1e-12);
+        assert!(GradCheck::new().check(&mut g).passed());
+    }
+
+    #[test]
+    fn test_piecewise() {
+        for &(x, abs_deriv, relu_deriv) in &[(-0.5f64, -1.0, 0.0), (0.0, 0.0, 0.5), (0.5, 1.0, 1.0)] {
+            assert_eq!(vjp_at(Abs { x: LeafVar(x), _marker: PhantomData }), (x.abs(), abs_deriv));
+            assert_eq!(vjp_at(Relu { x: LeafVar(x), _marker: PhantomData }), (x.max(0.0), relu_deriv));
+        }
+        assert_eq!(vjp_at(Sign { x: LeafVar(0.0f64), _marker: PhantomData }), (0.0, 0.0));
+        assert_eq!(vjp_at(Sign { x: LeafVar(-2.0f64), _marker: PhantomData }), (-1.0, 0.0));
+        assert_eq!(vjp_at(Floor { x: LeafVar(-1.5f64), _marker: PhantomData }), (-2.0, 0.0));
+        assert_eq!(vjp_at(Ceil { x: LeafVar(-1.5f64), _marker: PhantomData }), (-1.0, 0.0));
+        assert_eq!(vjp_at(Round { x: LeafVar(-1.5f64), _marker: PhantomData }), (-2.0, 0.0));
+    }
+
+    #[test]
+    fn test_min_max_ties() {
+        let mut c = Context::new();
+        for &(x, y, max_grads) in &[(1.0f64, 2.0, (0.0, 1.0)), (2.0, 1.0, (1.0, 0.0)), (1.5, 1.5, (0.5, 0.5))] {
+            let f = Max { x: LeafVar(x), y: LeafVar(y), _marker: PhantomData };
+            let node = f.eval(&mut c);
+            assert_eq!(node.value, x.max(y));
+            assert_eq!(node.vjp(1.0, &node.parents[0], 0), max_grads.0);
+            assert_eq!(node.vjp(1.0, &node.parents[1], 1), max_grads.1);
+
+            // min takes the other side, except at a tie
+            let f = Min { x: LeafVar(x), y: LeafVar(y), _marker: PhantomData };
+            let node = f.eval(&mut c);
+            assert_eq!(node.value, x.min(y));
+            assert_eq!(node.vjp(1.0, &node.parents[0], 0), max_grads.1);
+            assert_eq!(node.vjp(1.0, &node.parents[1], 1), max_grads.0);
+        }
+    }
+
+    #[test]
+    fn test_clamp_and_hinge() {
+        use ::Gradient;
+
+        for &(x0, expected) in &[(-2.0f64, 0.0), (-1.0, 0.5), (0.3, 1.0), (1.0, 0.5), (4.0, 0.0)] {
+            let mut c = Context::new();
+            let x = c.create_variable(x0);
+            let mut g = Gradient::of(clamp(x, -1.0, 1.0), c);
+            assert_eq!(g.value(), x0.max(-1.0).min(1.0));
+            assert_eq!(g.grad(&x), expected);
+        }
+
+        // Hinge loss max(0, 1 - y w x) for a label y = 1 and input x = 2
+        let mut c = Context::new();
+        let w = c.create_variable(0.2f64);
+        let margin = Container::new(LeafVar(1.0)) - w * Container::new(LeafVar(2.0));
+        let mut g = Gradient::of(relu(margin), c);
+        assert!((g.value() - 0.6).abs() < 1e-12);
+        assert_eq!(g.grad(&w), -2.0);
+    }
+
+    #[test]
+    fn test_powi() {
+        for &n in &[-3, -1, 0, 1, 2, 3] {
+            let (value, deriv) = vjp_at(Powi { x: LeafVar(-1.5f64), n: n, _marker: PhantomData });
+            assert!((value - (-1.5f64).powi(n)).abs() < 1e-12);
+            let expected = if n == 0 { 0.0 } else { n as f64 * (-1.5f64).powi(n - 1) };
+            assert!((deriv - expected).abs() < 1e-12, "n = {}", n);
+        }
+
+        // The derivative exponent does not fit in an `i32`
+        let (_, deriv) = vjp_at(Powi { x: LeafVar(-1.0f64), n: i32::MIN, _marker: PhantomData });
+        assert_eq!(deriv, -(i32::MIN as f64));
+        let d = Func::Powi(i32::MIN).deriv_term(Term::Const(-1.0f64));
+        assert_eq!(d.value(&Context::new()), -(i32::MIN as f64));
+    }
+
+    #[test]
+    fn test_pow() {
+        let mut c = Context::new();
+        let f = Pow { base: LeafVar(2.0f64), exponent: LeafVar(3.0), _marker: PhantomData };
+        let node = f.eval(&mut c);
+        assert_eq!(node.value, 8.0);
+        assert!((node.vjp(1.0, &node.parents[0], 0) - 12.0).abs() < 1e-12);
+        assert!((node.vjp(1.0, &node.parents[1], 1) - 8.0 * f64::ln(2.0)).abs() < 1e-12);
+
+        // No `0 * inf` at a zero base
+        let f = Pow { base: LeafVar(0.0f64), exponent: LeafVar(2.0), _marker: PhantomData };
+        let node = f.eval(&mut c);
+        assert_eq!(node.vjp(1.0, &node.parents[0], 0), 0.0);
+        assert_eq!(node.vjp(1.0, &node.parents[1], 1), 0.0);
+        let f = Pow { base: LeafVar(0.0f64), exponent: LeafVar(0.0), _marker: PhantomData };
+        let node = f.eval(&mut c);
+        assert_eq!(node.vjp(1.0, &node.parents[0], 0), 0.0);
+    }
 }
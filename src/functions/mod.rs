@@ -1,18 +1,40 @@
 //! Functions module
 //!
 //! This module contains differentiable wrapper functions.
+//!
+//! The piecewise functions use the following gradients where they are
+//! not differentiable. A tie in `min` or `max` splits the gradient evenly
+//! between both arguments, as `balanced_eq` does in rugrads-af, so `relu`
+//! and `clamp` pass on one half at their kinks and `abs` passes on zero.
+//! The step functions `sign`, `floor`, `ceil` and `round` have a zero
+//! gradient everywhere.
 
 use std::marker::PhantomData;
 use std::ops;
 
-use num::Float;
 
 use ::{Node, Context, Expression, VecJacProduct, IdentityVJP};
+use ::term::{Term, ToTerm};
+use ::serial::{Encode, Json};
 
 mod op_overrides;
 mod float;
+mod reduce;
+pub mod select;
+pub mod quad;
+pub mod complex;
 
 pub use self::float::{sin, cos, exp, ln, powf};
+pub use self::float::{tan, asin, acos, atan, atan2};
+pub use self::float::{sinh, cosh, tanh, asinh, acosh, atanh};
+pub use self::float::{sqrt, cbrt, hypot, log2, log10, log, exp2, exp_m1, ln_1p};
+pub use self::float::{erf, erfc, gamma, lgamma, digamma, trigamma, lbeta};
+pub use self::float::{abs, sign, floor, ceil, round, relu, min, max, clamp};
+pub use self::float::{pow, powi};
+pub use self::reduce::{logsumexp, softmax, log_softmax};
+pub use self::select::{select, compare, lt, le, gt, ge, eq, ne, Comparison, Condition};
+pub use self::quad::quad;
+pub use self::complex::{conj, re, im, norm_sqr};
 
 /// Addition operation
 #[derive(Copy, Clone)]
@@ -62,10 +84,30 @@ impl<T, X, Y> Expression<T> for Add<T, X, Y>
     }
 }
 
+impl<T, X, Y> ToTerm<T> for Add<T, X, Y>
+    where for<'a, 'b> &'a T: ops::Add<&'b T, Output=T>,
+            X: Expression<T> + ToTerm<T>,
+            Y: Expression<T> + ToTerm<T>
+{
+    fn to_term(&self) -> Term<T> {
+        Term::Add(Box::new(self.x.to_term()), Box::new(self.y.to_term()))
+    }
+}
+
+impl<T, C, X, Y> Encode<C> for Add<T, X, Y>
+    where for<'a, 'b> &'a T: ops::Add<&'b T, Output=T>,
+            X: Expression<T> + Encode<C>,
+            Y: Expression<T> + Encode<C>
+{
+    fn encode(&self, codec: &C) -> Json {
+        Json::op("add", vec![self.x.encode(codec), self.y.encode(codec)])
+    }
+}
+
 /// Multiplication operation
 #[derive(Copy, Clone)]
 pub struct Mul<T, X, Y>
-    where T: Float,
+    where for<'a, 'b> &'a T: ops::Mul<&'b T, Output=T>,
             X: Expression<T>,
             Y: Expression<T>
 {
@@ -75,23 +117,22 @@ pub struct Mul<T, X, Y>
 }
 
 #[derive(Copy, Clone)]
-struct MulVJP<T>(T, T)
-    where T: Float;
+struct MulVJP<T>(T, T);
 
 impl<T> VecJacProduct<T> for MulVJP<T>
-    where T: Float
+    where for<'a, 'b> &'a T: ops::Mul<&'b T, Output=T>
 {
     fn vjp(&self, g: T, _: &Node<T>, _: &Node<T>, argnum: usize) -> T {
         match argnum {
-            0 => g * self.1,
-            1 => g * self.0,
+            0 => &g * &self.1,
+            1 => &g * &self.0,
             _ => panic!("Invalid argnum fed to Mul VJP"),
         }
     }
 }
 
 impl<T, X, Y> Mul<T, X, Y>
-    where T: Float,
+    where for<'a, 'b> &'a T: ops::Mul<&'b T, Output=T>,
             X: Expression<T>,
             Y: Expression<T>
 {
@@ -105,7 +146,8 @@ impl<T, X, Y> Mul<T, X, Y>
 }
 
 impl<T, X, Y> Expression<T> for Mul<T, X, Y>
-    where T: Float,
+    where for<'a, 'b> &'a T: ops::Mul<&'b T, Output=T>,
+            T: Clone,
             X: Expression<T>,
             Y: Expression<T>
 {
@@ -116,12 +158,12 @@ impl<T, X, Y> Expression<T> for Mul<T, X, Y>
         let parents = vec![x_eval, y_eval];
         let progenitors = Node::get_progenitors(&parents);
 
-        let v1 = parents[0].value;
-        let v2 = parents[1].value;
+        let v1 = parents[0].value.clone();
+        let v2 = parents[1].value.clone();
 
         Node {
             index: c.get_index(),
-            value: v1 * v2,
+            value: &v1 * &v2,
             parents: parents,
             progenitors: progenitors,
             _vjp: Box::new(MulVJP(v1, v2))
@@ -129,10 +171,31 @@ impl<T, X, Y> Expression<T> for Mul<T, X, Y>
     }
 }
 
+impl<T, X, Y> ToTerm<T> for Mul<T, X, Y>
+    where for<'a, 'b> &'a T: ops::Mul<&'b T, Output=T>,
+            X: Expression<T> + ToTerm<T>,
+            Y: Expression<T> + ToTerm<T>
+{
+    fn to_term(&self) -> Term<T> {
+        Term::Mul(Box::new(self.x.to_term()), Box::new(self.y.to_term()))
+    }
+}
+
+impl<T, C, X, Y> Encode<C> for Mul<T, X, Y>
+    where for<'a, 'b> &'a T: ops::Mul<&'b T, Output=T>,
+            X: Expression<T> + Encode<C>,
+            Y: Expression<T> + Encode<C>
+{
+    fn encode(&self, codec: &C) -> Json {
+        Json::op("mul", vec![self.x.encode(codec), self.y.encode(codec)])
+    }
+}
+
 /// Division operation
 #[derive(Copy, Clone)]
 pub struct Div<T, X, Y>
-    where T: Float,
+    where for<'a, 'b> &'a T: ops::Div<&'b T, Output=T> + ops::Mul<&'b T, Output=T>,
+            T: ops::Neg<Output=T>,
             X: Expression<T>,
             Y: Expression<T>
 {
@@ -142,7 +205,8 @@ pub struct Div<T, X, Y>
 }
 
 impl<T, X, Y> Div<T, X, Y>
-    where T: Float,
+    where for<'a, 'b> &'a T: ops::Div<&'b T, Output=T> + ops::Mul<&'b T, Output=T>,
+            T: ops::Neg<Output=T>,
             X: Expression<T>,
             Y: Expression<T>
 {
@@ -156,20 +220,24 @@ impl<T, X, Y> Div<T, X, Y>
 }
 
 #[derive(Copy, Clone)]
-struct DivVJP<T: Float>(T, T);
+struct DivVJP<T>(T, T);
 
-impl<T: Float> VecJacProduct<T> for DivVJP<T> {
+impl<T> VecJacProduct<T> for DivVJP<T>
+    where for<'a, 'b> &'a T: ops::Div<&'b T, Output=T> + ops::Mul<&'b T, Output=T>,
+            T: ops::Neg<Output=T>
+{
     fn vjp(&self, g: T, _: &Node<T>, _: &Node<T>, argnum: usize) -> T {
         match argnum {
-            0 => g / self.1,
-            1 => - g * self.0 / (self.1 * self.1),
+            0 => &g / &self.1,
+            1 => -(&(&g * &self.0) / &(&self.1 * &self.1)),
             _ => panic!("Invalid argnum fed to Div VJP"),
         }
     }
 }
 
 impl<T, X, Y> Expression<T> for Div<T, X, Y>
-    where T: Float,
+    where for<'a, 'b> &'a T: ops::Div<&'b T, Output=T> + ops::Mul<&'b T, Output=T>,
+            T: ops::Neg<Output=T> + Clone,
             X: Expression<T>,
             Y: Expression<T>
 {
@@ -179,10 +247,10 @@ impl<T, X, Y> Expression<T> for Div<T, X, Y>
 
         let parents = vec![x_eval, y_eval];
         let progenitors = Node::get_progenitors(&parents);
-        let (v1, v2) = (parents[0].value, parents[1].value);
+        let (v1, v2) = (parents[0].value.clone(), parents[1].value.clone());
         Node {
             index: c.get_index(),
-            value: v1 / v2,
+            value: &v1 / &v2,
             parents: parents,
             progenitors: progenitors,
             _vjp: Box::new(DivVJP(v1, v2))
@@ -190,6 +258,28 @@ impl<T, X, Y> Expression<T> for Div<T, X, Y>
     }
 }
 
+impl<T, X, Y> ToTerm<T> for Div<T, X, Y>
+    where for<'a, 'b> &'a T: ops::Div<&'b T, Output=T> + ops::Mul<&'b T, Output=T>,
+            T: ops::Neg<Output=T>,
+            X: Expression<T> + ToTerm<T>,
+            Y: Expression<T> + ToTerm<T>
+{
+    fn to_term(&self) -> Term<T> {
+        Term::Div(Box::new(self.x.to_term()), Box::new(self.y.to_term()))
+    }
+}
+
+impl<T, C, X, Y> Encode<C> for Div<T, X, Y>
+    where for<'a, 'b> &'a T: ops::Div<&'b T, Output=T> + ops::Mul<&'b T, Output=T>,
+            T: ops::Neg<Output=T>,
+            X: Expression<T> + Encode<C>,
+            Y: Expression<T> + Encode<C>
+{
+    fn encode(&self, codec: &C) -> Json {
+        Json::op("div", vec![self.x.encode(codec), self.y.encode(codec)])
+    }
+}
+
 /// Subtraction operation
 #[derive(Copy, Clone)]
 pub struct Sub<T, X, Y>
@@ -241,6 +331,28 @@ impl<T, X, Y> Expression<T> for Sub<T, X, Y>
     }
 }
 
+impl<T, X, Y> ToTerm<T> for Sub<T, X, Y>
+    where for<'a, 'b> &'a T: ops::Sub<&'b T, Output=T>,
+            T: ops::Neg<Output=T>,
+            X: Expression<T> + ToTerm<T>,
+            Y: Expression<T> + ToTerm<T>
+{
+    fn to_term(&self) -> Term<T> {
+        Term::Sub(Box::new(self.x.to_term()), Box::new(self.y.to_term()))
+    }
+}
+
+impl<T, C, X, Y> Encode<C> for Sub<T, X, Y>
+    where for<'a, 'b> &'a T: ops::Sub<&'b T, Output=T>,
+            T: ops::Neg<Output=T>,
+            X: Expression<T> + Encode<C>,
+            Y: Expression<T> + Encode<C>
+{
+    fn encode(&self, codec: &C) -> Json {
+        Json::op("sub", vec![self.x.encode(codec), self.y.encode(codec)])
+    }
+}
+
 #[derive(Copy, Clone)]
 struct SubVJP<T: ops::Neg<Output=T>>(PhantomData<T>);
 
@@ -289,3 +401,21 @@ impl<T, X> Expression<T> for Neg<T, X>
         }
     }
 }
+
+impl<T, X> ToTerm<T> for Neg<T, X>
+    where T: Clone + ops::Neg<Output=T>,
+          X: Expression<T> + ToTerm<T>
+{
+    fn to_term(&self) -> Term<T> {
+        Term::Neg(Box::new(self.0.to_term()))
+    }
+}
+
+impl<T, C, X> Encode<C> for Neg<T, X>
+    where T: Clone + ops::Neg<Output=T>,
+          X: Expression<T> + Encode<C>
+{
+    fn encode(&self, codec: &C) -> Json {
+        Json::op("neg", vec![self.0.encode(codec)])
+    }
+}
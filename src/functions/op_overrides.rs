@@ -1,7 +1,6 @@
 use std::ops;
 use std::marker::PhantomData;
 
-use num::Float;
 
 use ::{Expression, Container};
 use super::{Add, Mul, Div, Sub, Neg};
@@ -19,7 +18,8 @@ impl<T, E1, E2> ops::Add<Container<T, E2>> for Container<T, E1>
 }
 
 impl<T, E1, E2> ops::Mul<Container<T, E2>> for Container<T, E1>
-    where T: Float,
+    where for<'a, 'b> &'a T: ops::Mul<&'b T, Output=T>,
+        T: Clone,
         E1: Expression<T>,
         E2: Expression<T> 
 {
@@ -31,7 +31,8 @@ impl<T, E1, E2> ops::Mul<Container<T, E2>> for Container<T, E1>
 }
 
 impl<T, E1, E2> ops::Div<Container<T, E2>> for Container<T, E1>
-    where T: Float,
+    where for<'a, 'b> &'a T: ops::Div<&'b T, Output=T> + ops::Mul<&'b T, Output=T>,
+        T: Clone + ops::Neg<Output=T>,
         E1: Expression<T>,
         E2: Expression<T>        
 {
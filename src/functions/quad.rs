@@ -0,0 +1,330 @@
+//! Numerical quadrature
+//!
+//! `quad` integrates an expression over one of its variables. The
+//! integrand is evaluated at each quadrature point by setting the
+//! integration variable, and every evaluation becomes a parent of the
+//! integral, so the gradient with respect to any other variable in the
+//! integrand is the quadrature of the integrand's gradient.
+//!
+//! The bounds may be expressions. Their gradients are the Leibniz terms
+//! `-f(a)` and `f(b)`, the derivatives of the exact integral, rather
+//! than the derivatives of the quadrature rule.
+//!
+//! # Example
+//!
+//! ```
+//! use rugrads::{Context, Gradient};
+//! use rugrads::functions::*;
+//! use rugrads::functions::quad::Rule;
+//!
+//! let mut context = Context::new();
+//! let t = context.create_variable(0.0);
+//! let k = context.create_variable(2.0);
+//! let zero = context.create_variable(0.0);
+//! let b = context.create_variable(1.0);
+//!
+//! // The integral of exp(-k t) from 0 to b is (1 - exp(-k b)) / k
+//! let f = quad(exp(-(k * t)), t, zero, b, Rule::GaussLegendre { points: 10 });
+//! let mut grad = Gradient::of(f, context);
+//!
+//! assert!((grad.value() - (1.0 - f64::exp(-2.0)) / 2.0).abs() < 1e-12);
+//! assert!((grad.grad(&b) - f64::exp(-2.0)).abs() < 1e-12);
+//! ```
+
+use std::f64::consts::PI;
+use std::marker::PhantomData;
+
+use num::Float;
+
+use ::{Container, Context, Expression, Node, VecJacProduct, Variable};
+
+/// A quadrature rule
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Rule<T> {
+    /// Gauss-Legendre quadrature with a fixed number of points
+    ///
+    /// This is exact for polynomials of degree up to `2 points - 1`.
+    GaussLegendre {
+        /// The number of points
+        points: usize,
+    },
+    /// Adaptive Simpson quadrature
+    ///
+    /// Intervals are halved until the Simpson estimates on the interval
+    /// and its halves agree to within the tolerance, which is split
+    /// between the halves.
+    Simpson {
+        /// The absolute tolerance
+        tol: T,
+    },
+}
+
+impl<T: Float> Rule<T> {
+    /// Adaptive Simpson quadrature with a tolerance of `1e-10`
+    pub fn adaptive() -> Self {
+        Rule::Simpson { tol: T::from(1e-10).unwrap() }
+    }
+}
+
+// The deepest an interval is split by the adaptive rule
+const MAX_DEPTH: usize = 40;
+
+fn cast<T: Float>(v: f64) -> T {
+    T::from(v).unwrap()
+}
+
+// The Gauss-Legendre points and weights on [-1, 1], found by Newton's
+// method on the Legendre polynomial from the Chebyshev points
+fn gauss_legendre<T: Float>(n: usize) -> Vec<(T, T)> {
+    let mut rule = Vec::with_capacity(n);
+    for i in 0..n {
+        let mut x = (PI * (i as f64 + 0.75) / (n as f64 + 0.5)).cos();
+        let mut deriv = 1.0;
+        for _ in 0..100 {
+            // P_n(x) and P_n'(x) from the three term recurrence
+            let (mut p, mut p_prev) = (1.0, 0.0);
+            for j in 0..n {
+                let j = j as f64;
+                let next = ((2.0 * j + 1.0) * x * p - j * p_prev) / (j + 1.0);
+                p_prev = p;
+                p = next;
+            }
+            deriv = n as f64 * (x * p - p_prev) / (x * x - 1.0);
+            let step = p / deriv;
+            x -= step;
+            if step.abs() < 1e-16 {
+                break;
+            }
+        }
+        rule.push((cast(x), cast(2.0 / ((1.0 - x * x) * deriv * deriv))));
+    }
+    rule
+}
+
+// Evaluates the integrand at quadrature points and keeps the nodes with
+// their weights
+struct Samples<'a, T: 'a, E: 'a> {
+    integrand: &'a E,
+    t: Variable,
+    nodes: Vec<Node<'a, T>>,
+    weights: Vec<T>,
+}
+
+impl<'a, T: Float, E: Expression<T>> Samples<'a, T, E> {
+    // Returns the index and value of a new sample
+    fn sample(&mut self, c: &mut Context<T>, x: T) -> (usize, T) {
+        c.set_variable_value(&self.t, x);
+        let node = self.integrand.eval(c);
+        let value = node.value;
+        self.nodes.push(node);
+        self.weights.push(T::zero());
+        (self.nodes.len() - 1, value)
+    }
+
+    // Adaptive Simpson on [a, b] given the samples at a, (a + b) / 2 and b
+    fn simpson(&mut self, c: &mut Context<T>, (a, b): (T, T), ends: [(usize, T); 3], tol: T, depth: usize) {
+        let [(ia, fa), (im, fm), (ib, fb)] = ends;
+        let h = b - a;
+        let m = (a + b) / cast(2.0);
+        let (il, fl) = self.sample(c, (a + m) / cast(2.0));
+        let (ir, fr) = self.sample(c, (m + b) / cast(2.0));
+
+        let whole = h / cast(6.0) * (fa + cast::<T>(4.0) * fm + fb);
+        let halves = h / cast(12.0) * (fa + cast::<T>(4.0) * fl + cast::<T>(2.0) * fm + cast::<T>(4.0) * fr + fb);
+        let small = (m - a).abs() <= T::epsilon() * m.abs();
+        if (halves - whole).abs() <= cast::<T>(15.0) * tol || depth == 0 || small {
+            // Richardson extrapolation of the two estimates is Boole's rule
+            for &(i, w) in &[(ia, 7.0), (il, 32.0), (im, 12.0), (ir, 32.0), (ib, 7.0)] {
+                self.weights[i] = self.weights[i] + h * cast(w / 90.0);
+            }
+        } else {
+            let half_tol = tol / cast(2.0);
+            self.simpson(c, (a, m), [(ia, fa), (il, fl), (im, fm)], half_tol, depth - 1);
+            self.simpson(c, (m, b), [(im, fm), (ir, fr), (ib, fb)], half_tol, depth - 1);
+        }
+    }
+}
+
+struct QuadVJP<T> {
+    // The integrand at the lower and upper bounds
+    f_a: T,
+    f_b: T,
+    weights: Vec<T>,
+}
+
+impl<T: Float> VecJacProduct<T> for QuadVJP<T> {
+    fn vjp(&self, g: T, _: &Node<T>, _: &Node<T>, idx: usize) -> T {
+        match idx {
+            0 => -g * self.f_a,
+            1 => g * self.f_b,
+            _ => g * self.weights[idx - 2],
+        }
+    }
+}
+
+/// Definite integral operator
+pub struct Quad<T, E, L, U> {
+    integrand: E,
+    t: Variable,
+    lower: L,
+    upper: U,
+    rule: Rule<T>,
+    // Gauss-Legendre points and weights on [-1, 1]
+    points: Vec<(T, T)>,
+    _marker: PhantomData<T>,
+}
+
+impl<T, E, L, U> Expression<T> for Quad<T, E, L, U>
+    where T: Float, E: Expression<T>, L: Expression<T>, U: Expression<T>
+{
+    fn eval(&self, c: &mut Context<T>) -> Node<T> {
+        let lower = self.lower.eval(c);
+        let upper = self.upper.eval(c);
+        let (a, b) = (lower.value, upper.value);
+        let saved = c.get_variable_value(&self.t);
+
+        let mut samples = Samples { integrand: &self.integrand, t: self.t, nodes: vec![], weights: vec![] };
+        let f_a = samples.sample(c, a).1;
+        let f_b = samples.sample(c, b).1;
+        match self.rule {
+            Rule::GaussLegendre { .. } => {
+                // The bound samples only give the Leibniz terms
+                samples.nodes.clear();
+                samples.weights.clear();
+                let mid = (a + b) / cast(2.0);
+                let half = (b - a) / cast(2.0);
+                for &(x, w) in &self.points {
+                    let (i, _) = samples.sample(c, mid + half * x);
+                    samples.weights[i] = half * w;
+                }
+            }
+            Rule::Simpson { tol } => {
+                let mid = samples.sample(c, (a + b) / cast(2.0));
+                samples.simpson(c, (a, b), [(0, f_a), mid, (1, f_b)], tol, MAX_DEPTH);
+            }
+        }
+        c.set_variable_value(&self.t, saved);
+
+        let value = samples.nodes.iter().zip(&samples.weights).fold(T::zero(), |s, (n, &w)| s + w * n.value);
+        let mut parents = vec![lower, upper];
+        parents.extend(samples.nodes);
+        let progenitors = Node::get_progenitors(&parents);
+
+        Node {
+            index: c.get_index(),
+            value: value,
+            parents: parents,
+            progenitors: progenitors,
+            _vjp: Box::new(QuadVJP { f_a: f_a, f_b: f_b, weights: samples.weights }),
+        }
+    }
+}
+
+/// Integrates `integrand` over `t` from `lower` to `upper`
+///
+/// `t` is set to each quadrature point while the integral is evaluated
+/// and restored afterwards. The gradient with respect to `t` itself is
+/// not meaningful. The integrand may contain other integrals, which gives
+/// iterated integrals.
+///
+/// # Panics
+///
+/// This function will panic if a Gauss-Legendre rule has no points.
+pub fn quad<T, E, L, U>(integrand: Container<T, E>, t: Container<T, Variable>, lower: Container<T, L>,
+                        upper: Container<T, U>, rule: Rule<T>) -> Container<T, Quad<T, E, L, U>>
+    where T: Float, E: Expression<T>, L: Expression<T>, U: Expression<T>
+{
+    let points = match rule {
+        Rule::GaussLegendre { points } => {
+            assert!(points > 0, "Gauss-Legendre quadrature needs at least one point");
+            gauss_legendre(points)
+        }
+        Rule::Simpson { .. } => vec![],
+    };
+    Container::new(Quad {
+        integrand: integrand.into_inner(),
+        t: t.into_inner(),
+        lower: lower.into_inner(),
+        upper: upper.into_inner(),
+        rule: rule,
+        points: points,
+        _marker: PhantomData,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::{Gradient, LeafVar};
+    use ::check::GradCheck;
+    use ::functions::*;
+
+    #[test]
+    fn test_gauss_legendre_exact() {
+        // Five points integrate polynomials of degree nine exactly
+        let mut c = Context::new();
+        let t = c.create_variable(0.0);
+        let f = powi(t, 9) + powi(t, 4);
+        let one = Container::new(LeafVar(1.0));
+        let two = Container::new(LeafVar(2.0));
+        let integral = quad(f, t, one, two, Rule::GaussLegendre { points: 5 });
+        let expected = (1024.0 - 1.0) / 10.0 + (32.0 - 1.0) / 5.0;
+        assert!((integral.eval(&mut c).value - expected).abs() < 1e-12);
+        assert_eq!(c.get_variable_value(&t), 0.0);
+    }
+
+    #[test]
+    fn test_parameter_gradients() {
+        // The integral of sin(w t) from 0 to pi / 2 is (1 - cos(w pi / 2)) / w
+        for &rule in &[Rule::GaussLegendre { points: 20 }, Rule::adaptive()] {
+            let mut c = Context::new();
+            let t = c.create_variable(0.0);
+            let w = c.create_variable(1.5);
+            let zero = Container::new(LeafVar(0.0));
+            let end = Container::new(LeafVar(PI / 2.0));
+            let mut g = Gradient::of(quad(sin(w * t), t, zero, end, rule), c);
+
+            let expected = (1.0 - f64::cos(1.5 * PI / 2.0)) / 1.5;
+            let d_expected = (1.5 * PI / 2.0 * f64::sin(1.5 * PI / 2.0) - 1.0 + f64::cos(1.5 * PI / 2.0)) / 2.25;
+            assert!((g.value() - expected).abs() < 1e-9, "{:?}", rule);
+            assert!((g.grad(&w) - d_expected).abs() < 1e-9, "{:?}", rule);
+        }
+    }
+
+    #[test]
+    fn test_leibniz_terms() {
+        // A cumulative hazard H(b) = int_a^b k t^2 dt with both bounds variable
+        let mut c = Context::new();
+        let t = c.create_variable(0.0);
+        let k = c.create_variable(0.3);
+        let a = c.create_variable(0.5);
+        let b = c.create_variable(2.0);
+        let h = quad(k * t * t, t, a, b, Rule::GaussLegendre { points: 3 });
+        let mut g = Gradient::of(exp(-h), c);
+
+        let report = GradCheck::new().tolerances(1e-6, 1e-9).check(&mut g);
+        let t_entry = report.entries.iter().position(|e| e.variable == *t.inner()).unwrap();
+        let others: Vec<_> = report.entries.iter().enumerate().filter(|&(i, _)| i != t_entry).collect();
+        assert!(others.iter().all(|&(_, e)| e.passed), "{}", report);
+
+        let survival = f64::exp(-0.1 * (8.0 - 0.125));
+        assert!((g.grad(&b) + survival * 0.3 * 4.0).abs() < 1e-12);
+        assert!((g.grad(&a) - survival * 0.3 * 0.25).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_iterated() {
+        // The integral of x y over the triangle 0 <= y <= x <= s is s^4 / 8
+        let mut c = Context::new();
+        let x = c.create_variable(0.0);
+        let y = c.create_variable(0.0);
+        let s = c.create_variable(1.2);
+        let zero = Container::new(LeafVar(0.0));
+        let inner = quad(x * y, y, zero, x, Rule::GaussLegendre { points: 4 });
+        let outer = quad(inner, x, zero, s, Rule::GaussLegendre { points: 4 });
+
+        let mut g = Gradient::of(outer, c);
+        assert!((g.value() - 1.2f64.powi(4) / 8.0).abs() < 1e-12);
+        assert!((g.grad(&s) - 1.2f64.powi(3) / 2.0).abs() < 1e-12);
+    }
+}
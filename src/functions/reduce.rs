@@ -0,0 +1,294 @@
+//! N-ary reductions over collections of expressions
+//!
+//! Each output is a single node whose parents are all of the inputs. The
+//! maximum input is subtracted before exponentiating so large inputs do
+//! not overflow.
+//!
+//! These have no `Term` of their own. `ToTerm` and `Encode` write them
+//! out using `max`, `exp` and `ln`, which has the same value and gradient.
+
+use std::marker::PhantomData;
+
+use num::Float;
+
+use ::{Container, Context, Expression, Node, VecJacProduct};
+use ::term::{Func, Func2, Term, ToTerm};
+use ::serial::{Codec, Encode, Json};
+
+// Returns `logsumexp(values)` and `softmax(values)`
+fn log_sum_exp<T: Float>(values: &[T]) -> (T, Vec<T>) {
+    let m = values.iter().fold(T::neg_infinity(), |m, &v| m.max(v));
+    if m.is_infinite() {
+        // The inputs equal to +inf share the weight equally. If every
+        // input is -inf the result is -inf and the weights are uniform.
+        let k = values.iter().filter(|&&v| v == m).count();
+        let w = T::one() / T::from(k).unwrap();
+        let weights = values.iter().map(|&v| if v == m { w } else { T::zero() }).collect();
+        return (m, weights);
+    }
+
+    let exps: Vec<T> = values.iter().map(|&v| (v - m).exp()).collect();
+    let sum = exps.iter().fold(T::zero(), |s, &e| s + e);
+    (m + sum.ln(), exps.into_iter().map(|e| e / sum).collect())
+}
+
+fn lse_term<T: Float>(terms: Vec<Term<T>>) -> Term<T> {
+    let m = terms.iter().skip(1).fold(terms[0].clone(), |m, t| {
+        Term::Apply2(Func2::Max, Box::new(m), Box::new(t.clone()))
+    });
+    let shifted = |t: Term<T>| {
+        Term::Apply(Func::Exp, Box::new(Term::Sub(Box::new(t), Box::new(m.clone()))))
+    };
+    let mut iter = terms.into_iter();
+    let first = shifted(iter.next().unwrap());
+    let sum = iter.fold(first, |s, t| Term::Add(Box::new(s), Box::new(shifted(t))));
+    Term::Add(Box::new(m.clone()), Box::new(Term::Apply(Func::Ln, Box::new(sum))))
+}
+
+struct LogSumExpVJP<T>(Vec<T>);
+
+impl<T: Float> VecJacProduct<T> for LogSumExpVJP<T> {
+    fn vjp(&self, g: T, _: &Node<T>, _: &Node<T>, idx: usize) -> T {
+        g * self.0[idx]
+    }
+}
+
+/// Log-sum-exp operator
+pub struct LogSumExp<T: Float, E: Expression<T>> {
+    xs: Vec<E>,
+    _marker: PhantomData<T>
+}
+
+impl<T: Float, E: Expression<T>> Expression<T> for LogSumExp<T, E> {
+    fn eval(&self, c: &mut Context<T>) -> Node<T> {
+        let parents: Vec<_> = self.xs.iter().map(|x| x.eval(c)).collect();
+        let progenitors = Node::get_progenitors(&parents);
+
+        let values: Vec<T> = parents.iter().map(|p| p.value).collect();
+        let (lse, weights) = log_sum_exp(&values);
+        Node {
+            index: c.get_index(),
+            value: lse,
+            parents: parents,
+            progenitors: progenitors,
+            _vjp: Box::new(LogSumExpVJP(weights)),
+        }
+    }
+}
+
+impl<T: Float, E: Expression<T> + ToTerm<T>> ToTerm<T> for LogSumExp<T, E> {
+    fn to_term(&self) -> Term<T> {
+        lse_term(self.xs.iter().map(|x| x.to_term()).collect())
+    }
+}
+
+impl<T: Float, C: Codec<T>, E: Expression<T> + ToTerm<T>> Encode<C> for LogSumExp<T, E> {
+    fn encode(&self, codec: &C) -> Json {
+        self.to_term().encode(codec)
+    }
+}
+
+/// Computes `ln(exp(x_1) + ... + exp(x_n))` without overflow
+///
+/// The gradient with respect to each input is its softmax weight.
+///
+/// # Panics
+///
+/// This function will panic if `xs` is empty.
+pub fn logsumexp<T, E>(xs: &[Container<T, E>]) -> Container<T, LogSumExp<T, E>>
+    where T: Float, E: Expression<T> + Clone
+{
+    assert!(!xs.is_empty(), "Cannot take the logsumexp of no expressions");
+    Container::new(LogSumExp {
+        xs: xs.iter().map(|x| x.inner().clone()).collect(),
+        _marker: PhantomData,
+    })
+}
+
+struct SoftmaxVJP<T> {
+    weights: Vec<T>,
+    i: usize,
+    log: bool,
+}
+
+impl<T: Float> VecJacProduct<T> for SoftmaxVJP<T> {
+    fn vjp(&self, g: T, _: &Node<T>, _: &Node<T>, idx: usize) -> T {
+        // d log_softmax_i / dx_j = delta_ij - s_j
+        let delta = if idx == self.i { T::one() } else { T::zero() };
+        let d_log = g * (delta - self.weights[idx]);
+        if self.log { d_log } else { d_log * self.weights[self.i] }
+    }
+}
+
+/// One output of a softmax or log-softmax operator
+pub struct Softmax<T: Float, E: Expression<T>> {
+    xs: Vec<E>,
+    i: usize,
+    log: bool,
+    _marker: PhantomData<T>
+}
+
+impl<T: Float, E: Expression<T>> Expression<T> for Softmax<T, E> {
+    fn eval(&self, c: &mut Context<T>) -> Node<T> {
+        let parents: Vec<_> = self.xs.iter().map(|x| x.eval(c)).collect();
+        let progenitors = Node::get_progenitors(&parents);
+
+        let values: Vec<T> = parents.iter().map(|p| p.value).collect();
+        let (lse, weights) = log_sum_exp(&values);
+        let value = if !self.log {
+            weights[self.i]
+        } else if lse.is_infinite() {
+            // x_i - lse is inf - inf here
+            weights[self.i].ln()
+        } else {
+            values[self.i] - lse
+        };
+        Node {
+            index: c.get_index(),
+            value: value,
+            parents: parents,
+            progenitors: progenitors,
+            _vjp: Box::new(SoftmaxVJP { weights: weights, i: self.i, log: self.log }),
+        }
+    }
+}
+
+impl<T: Float, E: Expression<T> + ToTerm<T>> ToTerm<T> for Softmax<T, E> {
+    fn to_term(&self) -> Term<T> {
+        let terms: Vec<Term<T>> = self.xs.iter().map(|x| x.to_term()).collect();
+        let x_i = terms[self.i].clone();
+        let log_softmax = Term::Sub(Box::new(x_i), Box::new(lse_term(terms)));
+        if self.log {
+            log_softmax
+        } else {
+            Term::Apply(Func::Exp, Box::new(log_softmax))
+        }
+    }
+}
+
+impl<T: Float, C: Codec<T>, E: Expression<T> + ToTerm<T>> Encode<C> for Softmax<T, E> {
+    fn encode(&self, codec: &C) -> Json {
+        self.to_term().encode(codec)
+    }
+}
+
+fn softmax_outputs<T, E>(xs: &[Container<T, E>], log: bool) -> Vec<Container<T, Softmax<T, E>>>
+    where T: Float, E: Expression<T> + Clone
+{
+    let inner: Vec<E> = xs.iter().map(|x| x.inner().clone()).collect();
+    (0..xs.len()).map(|i| {
+        Container::new(Softmax { xs: inner.clone(), i: i, log: log, _marker: PhantomData })
+    }).collect()
+}
+
+/// Computes `exp(x_i) / (exp(x_1) + ... + exp(x_n))` for each input
+///
+/// Each output is a separate expression depending on every input.
+pub fn softmax<T, E>(xs: &[Container<T, E>]) -> Vec<Container<T, Softmax<T, E>>>
+    where T: Float, E: Expression<T> + Clone
+{
+    softmax_outputs(xs, false)
+}
+
+/// Computes `x_i - logsumexp(x)` for each input
+///
+/// This is the logarithm of `softmax`, but does not underflow for
+/// inputs much smaller than the maximum.
+pub fn log_softmax<T, E>(xs: &[Container<T, E>]) -> Vec<Container<T, Softmax<T, E>>>
+    where T: Float, E: Expression<T> + Clone
+{
+    softmax_outputs(xs, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::{Context, Gradient};
+    use ::check::GradCheck;
+    use ::symbolic::derivative;
+
+    #[test]
+    fn test_logsumexp() {
+        let mut c = Context::new();
+        let xs: Vec<_> = [1.0f64, 2.0, 3.0].iter().map(|&v| c.create_variable(v)).collect();
+        let text = ::serial::to_string(&logsumexp(&xs));
+        let mut g = Gradient::of(logsumexp(&xs), c);
+
+        let sum: f64 = [1.0f64, 2.0, 3.0].iter().map(|v| v.exp()).sum();
+        assert!((g.value() - sum.ln()).abs() < 1e-12);
+        for (x, v) in xs.iter().zip(&[1.0f64, 2.0, 3.0]) {
+            assert!((g.grad(x) - v.exp() / sum).abs() < 1e-12);
+        }
+
+        // Written out with `max`, `exp` and `ln`
+        let decoded = ::serial::from_str::<f64>(&text).unwrap();
+        assert!((decoded.eval(g.context()).value - sum.ln()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_no_overflow() {
+        let mut c = Context::new();
+        let xs: Vec<_> = [1000.0f64, 1000.0, -1000.0].iter().map(|&v| c.create_variable(v)).collect();
+        let lse = logsumexp(&xs);
+        let outputs = log_softmax(&xs);
+        assert!((lse.eval(&mut c).value - (1000.0 + 2f64.ln())).abs() < 1e-10);
+        assert!((outputs[0].eval(&mut c).value + 2f64.ln()).abs() < 1e-12);
+        assert!((outputs[2].eval(&mut c).value + 2000.0 + 2f64.ln()).abs() < 1e-10);
+
+        let mut g = Gradient::of(lse, c);
+        assert!((g.grad(&xs[0]) - 0.5).abs() < 1e-12);
+        assert_eq!(g.grad(&xs[2]), 0.0);
+    }
+
+    #[test]
+    fn test_infinite_inputs() {
+        let inf = f64::INFINITY;
+        let mut c = Context::new();
+        let xs: Vec<_> = [inf, 1.0, inf].iter().map(|&v| c.create_variable(v)).collect();
+        let outputs = softmax(&xs);
+        assert_eq!(outputs[0].eval(&mut c).value, 0.5);
+        assert_eq!(outputs[1].eval(&mut c).value, 0.0);
+        assert_eq!(log_softmax(&xs)[2].eval(&mut c).value, -2f64.ln());
+        let mut g = Gradient::of(logsumexp(&xs), c);
+        assert_eq!(g.value(), inf);
+        assert_eq!(g.grads(&xs.iter().map(|x| **x).collect::<Vec<_>>()), vec![0.5, 0.0, 0.5]);
+
+        // All -inf gives -inf with uniform weights
+        let mut c = Context::new();
+        let xs: Vec<_> = (0..4).map(|_| c.create_variable(-inf)).collect();
+        assert_eq!(softmax(&xs)[1].eval(&mut c).value, 0.25);
+        assert_eq!(log_softmax(&xs)[1].eval(&mut c).value, -4f64.ln());
+        let mut g = Gradient::of(logsumexp(&xs), c);
+        assert_eq!(g.value(), -inf);
+        assert_eq!(g.grad(&xs[3]), 0.25);
+    }
+
+    #[test]
+    fn test_softmax_grads() {
+        let values = [0.3f64, -1.2, 2.0, 0.7];
+        for &log in &[false, true] {
+            for i in 0..values.len() {
+                let mut c = Context::new();
+                let xs: Vec<_> = values.iter().map(|&v| c.create_variable(v)).collect();
+                let outputs = if log { log_softmax(&xs) } else { softmax(&xs) };
+                let output = outputs.into_iter().nth(i).unwrap();
+
+                // The expanded term has the same derivative
+                let d = derivative(&output, &xs[1]);
+                let mut g = Gradient::of(output, c);
+                assert!((d.inner().value(g.context()) - g.grad(&xs[1])).abs() < 1e-12);
+
+                let report = GradCheck::new().check(&mut g);
+                assert!(report.passed(), "{}", report);
+            }
+        }
+    }
+
+    #[test]
+    fn test_softmax_sums_to_one() {
+        let mut c = Context::new();
+        let xs: Vec<_> = [0.3f64, -1.2, 2.0].iter().map(|&v| c.create_variable(v)).collect();
+        let total = softmax(&xs).iter().map(|s| s.eval(&mut c).value).fold(0.0, |a, b| a + b);
+        assert!((total - 1.0).abs() < 1e-12);
+    }
+}
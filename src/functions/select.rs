@@ -0,0 +1,211 @@
+//! Conditional expressions
+//!
+//! `select` chooses between two expressions each time it is evaluated,
+//! so a piecewise model does not need to be rebuilt when its variables
+//! change. The condition is a comparison between expressions and is not
+//! differentiated.
+//!
+//! # Example
+//!
+//! ```
+//! use rugrads::{Context, Gradient};
+//! use rugrads::functions::*;
+//!
+//! let mut context = Context::new();
+//! let x = context.create_variable(2.0);
+//! let zero = context.create_variable(0.0);
+//!
+//! // x^2 for positive x and sin(x) otherwise
+//! let f = select(gt(x, zero), x * x, sin(x));
+//! let mut grad = Gradient::of(f, context);
+//! assert_eq!(grad.grad(&x), 4.0);
+//!
+//! *grad.get_mut(&x) = -1.0;
+//! assert_eq!(grad.grad(&x), f64::cos(-1.0));
+//! ```
+
+use std::marker::PhantomData;
+
+use ::{Container, Context, Expression, IdentityVJP, Node};
+
+/// A condition which is tested each time an expression is evaluated
+pub trait Condition<T> {
+    /// Evaluates the condition in the context
+    fn test(&self, c: &mut Context<T>) -> bool;
+}
+
+/// The comparison made by a `Compare` condition
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    /// `x < y`
+    Lt,
+    /// `x <= y`
+    Le,
+    /// `x > y`
+    Gt,
+    /// `x >= y`
+    Ge,
+    /// `x == y`
+    Eq,
+    /// `x != y`
+    Ne,
+}
+
+impl Comparison {
+    /// Compares two values
+    pub fn apply<T: PartialOrd>(&self, x: &T, y: &T) -> bool {
+        match *self {
+            Comparison::Lt => x < y,
+            Comparison::Le => x <= y,
+            Comparison::Gt => x > y,
+            Comparison::Ge => x >= y,
+            Comparison::Eq => x == y,
+            Comparison::Ne => x != y,
+        }
+    }
+}
+
+/// A comparison between the values of two expressions
+#[derive(Clone, Copy)]
+pub struct Compare<T, X, Y> {
+    x: X,
+    y: Y,
+    op: Comparison,
+    _marker: PhantomData<T>,
+}
+
+impl<T: PartialOrd, X: Expression<T>, Y: Expression<T>> Condition<T> for Compare<T, X, Y> {
+    fn test(&self, c: &mut Context<T>) -> bool {
+        let x = self.x.eval(c);
+        let y = self.y.eval(c);
+        self.op.apply(&x.value, &y.value)
+    }
+}
+
+/// Compares the values of two expressions
+pub fn compare<T, X, Y>(x: Container<T, X>, op: Comparison, y: Container<T, Y>) -> Compare<T, X, Y>
+    where T: PartialOrd, X: Expression<T>, Y: Expression<T>
+{
+    Compare { x: x.into_inner(), y: y.into_inner(), op: op, _marker: PhantomData }
+}
+
+macro_rules! comparison_fn {
+    ($f_name: ident, $op: expr, $doc: expr) => {
+#[doc = $doc]
+pub fn $f_name<T, X, Y>(x: Container<T, X>, y: Container<T, Y>) -> Compare<T, X, Y>
+    where T: PartialOrd, X: Expression<T>, Y: Expression<T>
+{
+    compare(x, $op, y)
+}
+    };
+}
+
+comparison_fn!(lt, Comparison::Lt, "Tests whether `x < y`");
+comparison_fn!(le, Comparison::Le, "Tests whether `x <= y`");
+comparison_fn!(gt, Comparison::Gt, "Tests whether `x > y`");
+comparison_fn!(ge, Comparison::Ge, "Tests whether `x >= y`");
+comparison_fn!(eq, Comparison::Eq, "Tests whether `x == y`");
+comparison_fn!(ne, Comparison::Ne, "Tests whether `x != y`");
+
+/// Conditional operator
+pub struct Select<T, C, X, Y> {
+    cond: C,
+    then: X,
+    otherwise: Y,
+    _marker: PhantomData<T>,
+}
+
+impl<T, C, X, Y> Expression<T> for Select<T, C, X, Y>
+    where T: Clone, C: Condition<T>, X: Expression<T>, Y: Expression<T>
+{
+    fn eval(&self, c: &mut Context<T>) -> Node<T> {
+        // Only the chosen branch is evaluated and becomes a parent
+        let branch = if self.cond.test(c) {
+            self.then.eval(c)
+        } else {
+            self.otherwise.eval(c)
+        };
+        let value = branch.value.clone();
+        let parents = vec![branch];
+        let progenitors = Node::get_progenitors(&parents);
+
+        Node {
+            index: c.get_index(),
+            value: value,
+            parents: parents,
+            progenitors: progenitors,
+            _vjp: Box::new(IdentityVJP),
+        }
+    }
+}
+
+/// Evaluates to `then` if the condition holds and to `otherwise` if not
+///
+/// The condition is tested on every evaluation. The gradient flows only
+/// into the chosen branch, so variables which appear only in the other
+/// branch have a zero gradient.
+pub fn select<T, C, X, Y>(cond: C, then: Container<T, X>, otherwise: Container<T, Y>)
+    -> Container<T, Select<T, C, X, Y>>
+    where T: Clone, C: Condition<T>, X: Expression<T>, Y: Expression<T>
+{
+    Container::new(Select {
+        cond: cond,
+        then: then.into_inner(),
+        otherwise: otherwise.into_inner(),
+        _marker: PhantomData,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use ::{Context, Gradient, LeafVar, Container};
+    use ::functions::*;
+
+    #[test]
+    fn test_routes_gradient() {
+        let mut c = Context::new();
+        let x = c.create_variable(1.5);
+        let y = c.create_variable(3.0);
+        let f = select(lt(x, y), x * y, exp(y));
+
+        let mut g = Gradient::of(f, c);
+        assert_eq!(g.value(), 4.5);
+        assert_eq!(g.grad(&x), 3.0);
+        assert_eq!(g.grad(&y), 1.5);
+
+        // Switching branches without rebuilding the expression
+        *g.get_mut(&x) = 4.0;
+        assert_eq!(g.value(), f64::exp(3.0));
+        assert_eq!(g.grad(&x), 0.0);
+        assert_eq!(g.grad(&y), f64::exp(3.0));
+    }
+
+    #[test]
+    fn test_comparisons() {
+        let mut c = Context::new();
+        let one = Container::new(LeafVar(1.0));
+        let two = Container::new(LeafVar(2.0));
+        let cases = [(Comparison::Lt, true), (Comparison::Le, true), (Comparison::Gt, false),
+                     (Comparison::Ge, false), (Comparison::Eq, false), (Comparison::Ne, true)];
+        for &(op, expected) in &cases {
+            assert_eq!(compare(one, op, two).test(&mut c), expected, "{:?}", op);
+        }
+        assert!(eq(one, one).test(&mut c) && le(two, two).test(&mut c) && !ne(two, two).test(&mut c));
+    }
+
+    #[test]
+    fn test_nested() {
+        // A piecewise linear function with three pieces
+        let mut c = Context::new();
+        let x = c.create_variable(0.0);
+        let low = Container::new(LeafVar(-1.0));
+        let high = Container::new(LeafVar(1.0));
+        let f = select(lt(x, low), -x, select(gt(x, high), x * x, Container::new(LeafVar(2.0)) * x));
+
+        let mut g = Gradient::of(f, c);
+        for &(x0, expected) in &[(-2.0, -1.0), (0.5, 2.0), (3.0, 6.0)] {
+            *g.get_mut(&x) = x0;
+            assert_eq!(g.grad(&x), expected);
+        }
+    }
+}
@@ -0,0 +1,490 @@
+//! Implicit differentiation module
+//!
+//! Differentiates through the solution `z` of a system of equations
+//! `g(z, θ) = 0` without differentiating the solver. The solver runs
+//! in the forward pass and the gradient comes from the implicit function
+//! theorem,
+//!
+//! ```text
+//! dz/dθ = -(∂g/∂z)^-1 ∂g/∂θ
+//! ```
+//!
+//! where the Jacobians of `g` are computed by back propagation through
+//! the expressions describing it. The backward pass for each unknown
+//! solves the transposed system `(∂g/∂z)^T λ = e_i` once and returns
+//! `-λ^T ∂g/∂θ`, so the full matrix `dz/dθ` is never formed.
+//!
+//! If the solver fails the unknowns are NaN, and if `∂g/∂z` is singular
+//! at the solution their gradients are NaN, so a failure shows up in the
+//! loss rather than as a plausible but wrong value.
+//!
+//! The equations are built by a closure which is given the unknowns
+//! `z` and the parameters `θ` as variables of a separate context. The
+//! parameters passed to `fixed_point`, `root` or `solve` are expressions
+//! in the outer context and the gradient flows back into them.
+//!
+//! # Example
+//!
+//! ```
+//! use rugrads::{Context, Expression, Gradient};
+//! use rugrads::implicit;
+//!
+//! let mut context = Context::new();
+//! let a = context.create_variable(2.0);
+//!
+//! // The positive root of z^2 - a = 0 is sqrt(a)
+//! let z = implicit::root(|z, p| vec![Box::new(z[0] * z[0] - p[0]) as Box<dyn Expression<f64>>],
+//!                        vec![1.0], &[a]);
+//! let mut grad = Gradient::of(z[0].clone(), context);
+//!
+//! assert!((grad.value() - f64::sqrt(2.0)).abs() < 1e-12);
+//! assert!((grad.grad(&a) - 0.5 / f64::sqrt(2.0)).abs() < 1e-12);
+//! ```
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use num::Float;
+
+use ::{accumulate_all, Container, Context, Expression, Node, VecJacProduct, Variable};
+use ::linalg::Lu;
+
+/// The equations of an implicit problem
+///
+/// This holds the expressions for `g(z, θ)`, or for `f(z, θ)` when
+/// solving the fixed point problem `z = f(z, θ)`.
+pub struct System<T> {
+    context: Context<T>,
+    z: Vec<Variable>,
+    params: Vec<Variable>,
+    exprs: Vec<Box<dyn Expression<T>>>,
+    map: bool,
+}
+
+impl<T: Float> System<T> {
+    fn build<F>(n: usize, m: usize, map: bool, build: F) -> Self
+        where F: FnOnce(&[Container<T, Variable>], &[Container<T, Variable>]) -> Vec<Box<dyn Expression<T>>>
+    {
+        let mut context = Context::new();
+        let z: Vec<_> = (0..n).map(|_| context.create_variable(T::zero())).collect();
+        let params: Vec<_> = (0..m).map(|_| context.create_variable(T::zero())).collect();
+        let exprs = build(&z, &params);
+        assert_eq!(exprs.len(), n, "The system must have one equation per unknown");
+
+        System {
+            context: context,
+            z: z.iter().map(|v| *v.inner()).collect(),
+            params: params.iter().map(|v| *v.inner()).collect(),
+            exprs: exprs,
+            map: map,
+        }
+    }
+
+    /// Creates the system `g(z, θ) = 0` with `n` unknowns and `m` parameters
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `build` does not return `n` equations.
+    pub fn residual<F>(n: usize, m: usize, build: F) -> Self
+        where F: FnOnce(&[Container<T, Variable>], &[Container<T, Variable>]) -> Vec<Box<dyn Expression<T>>>
+    {
+        System::build(n, m, false, build)
+    }
+
+    /// Creates the fixed point system `z = f(z, θ)` with `n` unknowns and `m` parameters
+    ///
+    /// The residual of this system is `f(z, θ) - z`.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `build` does not return `n` equations.
+    pub fn map<F>(n: usize, m: usize, build: F) -> Self
+        where F: FnOnce(&[Container<T, Variable>], &[Container<T, Variable>]) -> Vec<Box<dyn Expression<T>>>
+    {
+        System::build(n, m, true, build)
+    }
+
+    /// The number of unknowns
+    pub fn dim(&self) -> usize {
+        self.z.len()
+    }
+
+    /// The number of parameters
+    pub fn num_params(&self) -> usize {
+        self.params.len()
+    }
+
+    fn set(&mut self, z: &[T], params: &[T]) {
+        for (var, &v) in self.z.iter().zip(z) {
+            self.context.set_variable_value(var, v);
+        }
+        for (var, &v) in self.params.iter().zip(params) {
+            self.context.set_variable_value(var, v);
+        }
+    }
+
+    /// Evaluates the expressions the system was built from
+    ///
+    /// For a fixed point system this is `f(z, θ)`, otherwise it is `g(z, θ)`.
+    pub fn eval(&mut self, z: &[T], params: &[T]) -> Vec<T> {
+        self.set(z, params);
+        let context = &mut self.context;
+        self.exprs.iter().map(|e| {
+            context.node_count = 0;
+            e.eval(context).value
+        }).collect()
+    }
+
+    /// Evaluates the residual `g(z, θ)`
+    pub fn residual_at(&mut self, z: &[T], params: &[T]) -> Vec<T> {
+        let values = self.eval(z, params);
+        if self.map {
+            values.into_iter().zip(z).map(|(f, &z)| f - z).collect()
+        } else {
+            values
+        }
+    }
+
+    /// Returns the Jacobians `∂g/∂z` and `∂g/∂θ` of the residual
+    pub fn jacobians(&mut self, z: &[T], params: &[T]) -> (Vec<Vec<T>>, Vec<Vec<T>>) {
+        self.set(z, params);
+        let n = self.z.len();
+        let vars: Vec<Variable> = self.z.iter().chain(&self.params).cloned().collect();
+        let mut jac_z = vec![];
+        let mut jac_params = vec![];
+        for (i, expr) in self.exprs.iter().enumerate() {
+            let (_, grads) = accumulate_all(expr, &mut self.context, &vars, |_| T::one());
+            let mut row: Vec<T> = grads.into_iter().map(|g| g.unwrap_or(T::zero())).collect();
+            if self.map {
+                row[i] = row[i] - T::one();
+            }
+            jac_params.push(row.split_off(n));
+            jac_z.push(row);
+        }
+        (jac_z, jac_params)
+    }
+}
+
+/// A solver for the forward pass
+///
+/// This is given the system, the initial guess and the parameter values
+/// and returns the solution, or NaN for each unknown if it fails.
+pub type Solver<T> = Box<dyn Fn(&mut System<T>, &[T], &[T]) -> Vec<T>>;
+
+// The tolerance and iteration limit of the built in solvers
+const MAX_ITER: usize = 1000;
+
+fn converged<T: Float>(step: &[T], z: &[T]) -> bool {
+    let tol = T::epsilon().sqrt() * T::epsilon().sqrt().sqrt();
+    step.iter().zip(z).all(|(s, z)| s.abs() <= tol * (T::one() + z.abs()))
+}
+
+/// Iterates `z = f(z, θ)` until it stops changing
+///
+/// Returns NaN for each unknown if this does not converge within 1000
+/// iterations.
+pub fn fixed_point_iteration<T: Float>(system: &mut System<T>, init: &[T], params: &[T]) -> Vec<T> {
+    let mut z = init.to_vec();
+    for _ in 0..MAX_ITER {
+        let next = system.eval(&z, params);
+        let step: Vec<T> = next.iter().zip(&z).map(|(&n, &z)| n - z).collect();
+        z = next;
+        if converged(&step, &z) {
+            return z;
+        }
+    }
+    vec![T::nan(); z.len()]
+}
+
+/// Solves `g(z, θ) = 0` by Newton's method with a backtracking line search
+///
+/// Returns NaN for each unknown if this does not converge within 1000
+/// iterations or the Jacobian becomes singular.
+pub fn newton<T: Float>(system: &mut System<T>, init: &[T], params: &[T]) -> Vec<T> {
+    let norm = |r: &[T]| r.iter().fold(T::zero(), |s, &v| s + v * v);
+
+    let mut z = init.to_vec();
+    let mut r = system.residual_at(&z, params);
+    for _ in 0..MAX_ITER {
+        let (jac_z, _) = system.jacobians(&z, params);
+        let step = match Lu::new(jac_z) {
+            Some(lu) => lu.solve(&r),
+            None => break,
+        };
+
+        // Halve the step until the residual decreases
+        let mut t = T::one();
+        let mut next;
+        let mut next_r;
+        loop {
+            next = z.iter().zip(&step).map(|(&z, &s)| z - t * s).collect::<Vec<T>>();
+            next_r = system.residual_at(&next, params);
+            if norm(&next_r) < norm(&r) || t < T::epsilon() {
+                break;
+            }
+            t = t / (T::one() + T::one());
+        }
+
+        let taken: Vec<T> = step.iter().map(|&s| t * s).collect();
+        z = next;
+        r = next_r;
+        if converged(&taken, &z) || norm(&r) == T::zero() {
+            return z;
+        }
+    }
+    vec![T::nan(); z.len()]
+}
+
+// The solution for the last parameter values with what its gradients need
+struct Solution<T> {
+    params: Vec<T>,
+    z: Vec<T>,
+    // The factorised ∂g/∂z, or None if it is singular or the solve failed
+    lu: Option<Lu<T>>,
+    jac_params: Vec<Vec<T>>,
+    // dz_i/dθ, found when first needed
+    rows: RefCell<Vec<Option<Rc<Vec<T>>>>>,
+}
+
+impl<T: Float> Solution<T> {
+    // Returns dz_i/dθ = -λ^T ∂g/∂θ where (∂g/∂z)^T λ = e_i
+    fn row(&self, i: usize) -> Rc<Vec<T>> {
+        if let Some(ref row) = self.rows.borrow()[i] {
+            return row.clone();
+        }
+        let m = self.params.len();
+        let row = match self.lu {
+            Some(ref lu) => {
+                let mut e = vec![T::zero(); self.z.len()];
+                e[i] = T::one();
+                let lambda = lu.solve_transpose(&e);
+                (0..m).map(|j| {
+                    -lambda.iter().zip(&self.jac_params).fold(T::zero(), |s, (&l, row)| s + l * row[j])
+                }).collect()
+            }
+            None => vec![T::nan(); m],
+        };
+        let row = Rc::new(row);
+        self.rows.borrow_mut()[i] = Some(row.clone());
+        row
+    }
+}
+
+struct Problem<T> {
+    system: RefCell<System<T>>,
+    init: Vec<T>,
+    solver: Solver<T>,
+    last: RefCell<Option<Rc<Solution<T>>>>,
+}
+
+impl<T: Float> Problem<T> {
+    fn solve(&self, params: &[T]) -> Rc<Solution<T>> {
+        if let Some(ref last) = *self.last.borrow() {
+            if last.params.as_slice() == params {
+                return last.clone();
+            }
+        }
+
+        let mut system = self.system.borrow_mut();
+        let z = (self.solver)(&mut system, &self.init, params);
+        let (jac_z, jac_params) = system.jacobians(&z, params);
+        // A failed solve has no gradient even where the Jacobians are finite
+        let lu = if z.iter().all(|z| z.is_finite()) { Lu::new(jac_z) } else { None };
+
+        let solution = Rc::new(Solution {
+            params: params.to_vec(),
+            rows: RefCell::new(vec![None; z.len()]),
+            z: z,
+            lu: lu,
+            jac_params: jac_params,
+        });
+        *self.last.borrow_mut() = Some(solution.clone());
+        solution
+    }
+}
+
+struct ImplicitVJP<T> {
+    solution: Rc<Solution<T>>,
+    i: usize,
+}
+
+impl<T: Float> VecJacProduct<T> for ImplicitVJP<T> {
+    fn vjp(&self, g: T, _: &Node<T>, _: &Node<T>, idx: usize) -> T {
+        g * self.solution.row(self.i)[idx]
+    }
+}
+
+/// One unknown of the solution of an implicit problem
+///
+/// The outputs of one problem share the solve, so evaluating all of them
+/// at the same parameter values runs the solver once.
+pub struct Implicit<T, E> {
+    problem: Rc<Problem<T>>,
+    params: Vec<E>,
+    i: usize,
+}
+
+impl<T, E: Clone> Clone for Implicit<T, E> {
+    fn clone(&self) -> Self {
+        Implicit { problem: self.problem.clone(), params: self.params.clone(), i: self.i }
+    }
+}
+
+impl<T: Float, E: Expression<T>> Expression<T> for Implicit<T, E> {
+    fn eval(&self, c: &mut Context<T>) -> Node<T> {
+        let parents: Vec<_> = self.params.iter().map(|p| p.eval(c)).collect();
+        let progenitors = Node::get_progenitors(&parents);
+
+        let values: Vec<T> = parents.iter().map(|p| p.value).collect();
+        let solution = self.problem.solve(&values);
+        Node {
+            index: c.get_index(),
+            value: solution.z[self.i],
+            parents: parents,
+            progenitors: progenitors,
+            _vjp: Box::new(ImplicitVJP { solution: solution, i: self.i }),
+        }
+    }
+}
+
+/// Solves a system with the given solver and differentiates the solution implicitly
+///
+/// Returns one expression for each unknown.
+///
+/// # Panics
+///
+/// This function will panic if the lengths of `init` and `params` do
+/// not match the system.
+pub fn solve<T, E>(system: System<T>, init: Vec<T>, params: &[Container<T, E>], solver: Solver<T>)
+    -> Vec<Container<T, Implicit<T, E>>>
+    where T: Float, E: Expression<T> + Clone
+{
+    assert_eq!(init.len(), system.dim(), "The initial guess must have one value per unknown");
+    assert_eq!(params.len(), system.num_params(), "The system has a different number of parameters");
+
+    let n = system.dim();
+    let problem = Rc::new(Problem {
+        system: RefCell::new(system),
+        init: init,
+        solver: solver,
+        last: RefCell::new(None),
+    });
+    let params: Vec<E> = params.iter().map(|p| p.inner().clone()).collect();
+    (0..n).map(|i| {
+        Container::new(Implicit { problem: problem.clone(), params: params.clone(), i: i })
+    }).collect()
+}
+
+/// Solves `z = f(z, θ)` by iteration and differentiates the fixed point implicitly
+///
+/// `f` builds the map from the unknowns and the parameters. The iteration
+/// must converge from `init`.
+pub fn fixed_point<T, E, F>(f: F, init: Vec<T>, params: &[Container<T, E>]) -> Vec<Container<T, Implicit<T, E>>>
+    where T: Float + 'static,
+          E: Expression<T> + Clone,
+          F: FnOnce(&[Container<T, Variable>], &[Container<T, Variable>]) -> Vec<Box<dyn Expression<T>>>
+{
+    let system = System::map(init.len(), params.len(), f);
+    solve(system, init, params, Box::new(fixed_point_iteration))
+}
+
+/// Solves `g(z, θ) = 0` by Newton's method and differentiates the root implicitly
+///
+/// `g` builds the residual from the unknowns and the parameters. The
+/// root found is the one Newton's method reaches from `init`.
+pub fn root<T, E, F>(g: F, init: Vec<T>, params: &[Container<T, E>]) -> Vec<Container<T, Implicit<T, E>>>
+    where T: Float + 'static,
+          E: Expression<T> + Clone,
+          F: FnOnce(&[Container<T, Variable>], &[Container<T, Variable>]) -> Vec<Box<dyn Expression<T>>>
+{
+    let system = System::residual(init.len(), params.len(), g);
+    solve(system, init, params, Box::new(newton))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::{Gradient, LeafVar};
+    use ::check::GradCheck;
+    use ::functions::*;
+
+    #[test]
+    fn test_fixed_point() {
+        // z = cos(a z), differentiating z - cos(a z) = 0 gives
+        // dz/da = -z sin(a z) / (1 + a sin(a z))
+        let mut c = Context::new();
+        let a = c.create_variable(0.8);
+        let z = fixed_point(|z, p| vec![Box::new(cos(p[0] * z[0])) as Box<dyn Expression<f64>>],
+                            vec![0.5], &[a]);
+
+        let mut g = Gradient::of(z[0].clone(), c);
+        let z0 = g.value();
+        assert!((z0 - f64::cos(0.8 * z0)).abs() < 1e-10);
+
+        let s = f64::sin(0.8 * z0);
+        assert!((g.grad(&a) + z0 * s / (1.0 + 0.8 * s)).abs() < 1e-8);
+        assert!(GradCheck::new().tolerances(1e-5, 1e-7).check(&mut g).passed());
+    }
+
+    #[test]
+    fn test_root_system() {
+        // The intersection of the circle x^2 + y^2 = r^2 and the line y = k x
+        let mut c = Context::new();
+        let r = c.create_variable(2.0);
+        let k = c.create_variable(0.5);
+        let zs = root(|z, p| vec![
+            Box::new(z[0] * z[0] + z[1] * z[1] - p[0] * p[0]) as Box<dyn Expression<f64>>,
+            Box::new(z[1] - p[1] * z[0]),
+        ], vec![1.0, 1.0], &[r, k]);
+
+        // x = r / sqrt(1 + k^2), so the loss x + y = r (1 + k) / sqrt(1 + k^2)
+        let loss = zs[0].clone() + zs[1].clone();
+        let mut g = Gradient::of(loss, c);
+        let norm = f64::sqrt(1.25);
+        assert!((g.value() - 2.0 * 1.5 / norm).abs() < 1e-10);
+        assert!((g.grad(&r) - 1.5 / norm).abs() < 1e-10);
+        // d/dk (1 + k) / sqrt(1 + k^2) = (1 - k) / (1 + k^2)^1.5
+        assert!((g.grad(&k) - 2.0 * 0.5 / norm.powi(3)).abs() < 1e-10);
+
+        let report = GradCheck::new().tolerances(1e-5, 1e-7).check(&mut g);
+        assert!(report.passed(), "{}", report);
+    }
+
+    #[test]
+    fn test_custom_solver_and_expression_params() {
+        // A user solver which knows the closed form of z^3 = p
+        let mut c = Context::new();
+        let x = c.create_variable(1.5);
+        let system = System::residual(1, 1, |z, p| {
+            vec![Box::new(powi(z[0], 3) - p[0]) as Box<dyn Expression<f64>>]
+        });
+        let solver: Solver<f64> = Box::new(|_, _, p: &[f64]| vec![p[0].cbrt()]);
+        let param = x * x + Container::new(LeafVar(1.0));
+        let z = solve(system, vec![1.0], &[param], solver);
+
+        // z = (x^2 + 1)^(1/3)
+        let mut g = Gradient::of(z[0].clone(), c);
+        let expected = 2.0 * 1.5 / 3.0 * f64::powf(3.25, -2.0 / 3.0);
+        assert!((g.grad(&x) - expected).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_failures_are_nan() {
+        // z = 2 z + a diverges from any start but the fixed point -a
+        let mut c = Context::new();
+        let a = c.create_variable(1.0);
+        let z = fixed_point(|z, p| vec![Box::new(Container::new(LeafVar(2.0)) * z[0] + p[0]) as Box<dyn Expression<f64>>],
+                            vec![0.0], &[a]);
+        let mut g = Gradient::of(z[0].clone(), c);
+        assert!(g.value().is_nan());
+        assert!(g.grad(&a).is_nan());
+
+        // The residual z^2 + a has no real root for a > 0
+        let mut c = Context::new();
+        let a = c.create_variable(1.0);
+        let z = root(|z, p| vec![Box::new(z[0] * z[0] + p[0]) as Box<dyn Expression<f64>>], vec![1.0], &[a]);
+        let mut g = Gradient::of(z[0].clone(), c);
+        assert!(g.value().is_nan());
+        assert!(g.grad(&a).is_nan());
+    }
+}
@@ -2,15 +2,27 @@ use super::Node;
 
 use std::collections::HashMap;
 
-fn relevant_parents<'a, 'b, T>(parents: &'b Vec<Node<'a, T>>, start_idx: usize) -> Vec<&'b Node<'a, T>> {
+// The parents which depend on the start node, or all of them if there
+// is no start node
+fn relevant_parents<'a, 'b, T>(parents: &'b Vec<Node<'a, T>>, start_idx: Option<usize>) -> Vec<&'b Node<'a, T>> {
     parents.iter()
-            .filter(|p| {
-                p.progenitors.contains(&start_idx) || p.index == start_idx
+            .filter(|p| match start_idx {
+                Some(idx) => p.progenitors.contains(&idx) || p.index == idx,
+                None => true,
             })
             .collect()
 }
 
 pub fn reverse_topology<'a, 'b, T: 'a>(end: &'a Node<'b, T>, start_idx: usize) -> RevTopology<'a, 'b, T> {
+    topology(end, Some(start_idx))
+}
+
+/// Every node of the graph, each after all of its children
+pub fn full_reverse_topology<'a, 'b, T: 'a>(end: &'a Node<'b, T>) -> RevTopology<'a, 'b, T> {
+    topology(end, None)
+}
+
+fn topology<'a, 'b, T: 'a>(end: &'a Node<'b, T>, start_idx: Option<usize>) -> RevTopology<'a, 'b, T> {
     let mut child_counts = HashMap::new();
     {
         let mut stack = vec![end];
@@ -31,7 +43,7 @@ pub fn reverse_topology<'a, 'b, T: 'a>(end: &'a Node<'b, T>, start_idx: usize) -
 }
 
 pub struct RevTopology<'a, 'b: 'a, T: 'a> {
-    start: usize,
+    start: Option<usize>,
     child_counts: HashMap<usize, usize>,
     childless_nodes: Vec<&'a Node<'b, T>>
 }
@@ -32,9 +32,30 @@
 #![deny(missing_docs)]
 
 extern crate num;
-
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
+
+pub mod check;
+pub mod checkpoint;
 pub mod functions;
+pub mod implicit;
+pub mod mcmc;
+pub mod ode;
+pub mod optim;
+pub mod property;
+pub mod rng;
+pub mod scalar;
+pub mod serial;
+pub mod simplify;
+pub mod solve;
+pub mod special;
+pub mod symbolic;
+pub mod term;
 mod iter;
+mod linalg;
 mod utils;
 
 #[cfg(test)]
@@ -45,7 +66,10 @@ use std::marker::PhantomData;
 use std::ops::Add;
 use std::ops::Deref;
 
-use iter::reverse_topology;
+use iter::{full_reverse_topology, reverse_topology};
+
+pub use checkpoint::Snapshot;
+pub use scalar::{Scalar, Seed};
 
 /// Container which wraps an expression
 ///
@@ -139,38 +163,34 @@ impl<T, E: Expression<T>> Gradient<T, E> {
     pub fn context(&mut self) -> &mut Context<T> {
         &mut self.context
     }
+
+    /// Returns a reference to the expression being differentiated
+    pub fn expr(&self) -> &E {
+        &self.expr
+    }
+
+    /// Evaluates the expression at the current variable values
+    pub fn value(&mut self) -> T where T: Clone {
+        self.context.node_count = 0;
+        self.expr.eval(&mut self.context).value.clone()
+    }
 }
 
 impl<T: Clone + Add<Output=T>, E: Expression<T>> Gradient<T, E> {
-    /// Back propagates the gradient with some starting seed.
+    /// Back propagates the gradient with some starting seed, returning
+    /// `None` if `wrt` does not appear in the expression
     ///
-    /// This seed should always be set to 1. 
-    pub fn backprop(&mut self, wrt: &Variable, seed: T) -> T {
-        // Reset the context
-        self.context.node_count = 0;
-
-        // Forward prop
-        let end = self.expr.eval(&mut self.context);
-
-        // Backward prop
-        let mut node_in_grads = HashMap::new();
-        node_in_grads.insert(end.index, vec![seed]);
-
-        let mut cur_in_grad = utils::assigning_sum(&node_in_grads[&end.index]);
-        for node in reverse_topology(&end, wrt.0) {
-            if !node_in_grads.contains_key(&node.index) {
-                // This ensures we don't try to sum an empty in_grads vec
-                continue;
-            } else {
-                cur_in_grad = utils::assigning_sum(&node_in_grads[&node.index]);
-                for (argnum, p_node) in node.parents.iter().enumerate() {
-                    let in_grad = node.vjp(cur_in_grad.clone(), p_node, argnum);
-                    node_in_grads.entry(p_node.index).or_insert(vec![]).push(in_grad);
-                }
-            }
-        }
+    /// This is `backprop` for value types without a `Seed` to give the
+    /// zero gradient.
+    pub fn try_backprop(&mut self, wrt: &Variable, seed: T) -> Option<T> {
+        self.accumulate(wrt, |_| seed)
+    }
 
-        return cur_in_grad
+    /// Back propagates a seed computed from the output value and returns
+    /// the gradient accumulated at `wrt`, or `None` if `wrt` is not part
+    /// of the expression.
+    fn accumulate<F: FnOnce(&T) -> T>(&mut self, wrt: &Variable, seed: F) -> Option<T> {
+        accumulate(&self.expr, &mut self.context, wrt, seed)
     }
 
     /// Returns a mutable reference to a variable value in this gradient
@@ -184,12 +204,107 @@ impl<T: Clone + Add<Output=T>, E: Expression<T>> Gradient<T, E> {
     }
 }
 
-impl<T: num::Float, E: Expression<T>> Gradient<T, E> {
+impl<T: Clone + Add<Output=T> + Seed, E: Expression<T>> Gradient<T, E> {
+    /// Back propagates the gradient with some starting seed.
+    ///
+    /// This seed should always be set to 1. The gradient is
+    /// `Seed::zero_grad` if `wrt` does not appear in the expression.
+    pub fn backprop(&mut self, wrt: &Variable, seed: T) -> T {
+        match self.try_backprop(wrt, seed) {
+            Some(grad) => grad,
+            None => T::zero_grad(&self.context.vars[wrt.0]),
+        }
+    }
+
     /// Compute the gradient with respect to the given
     /// `Variable`.
+    ///
+    /// The output is seeded with `Seed::seed` and the gradient is
+    /// `Seed::zero_grad` if `wrt` does not appear in the expression.
+    ///
+    /// For complex scalars the seed is one and the result is
+    /// `2 ∂Re(f)/∂z = ∂Re(f)/∂x - i ∂Re(f)/∂y`, where `z = x + iy` and
+    /// `∂/∂z` is the Wirtinger derivative. For a holomorphic `f` this
+    /// reduces to the complex derivative `f'(z)`. For a real valued `f`
+    /// it is the conjugate of the direction of steepest ascent, so a
+    /// descent step is `z - lr * grad.conj()`. See `functions::complex`
+    /// for details.
     pub fn grad(&mut self, wrt: &Variable) -> T {
-        self.backprop(wrt, T::one())
+        match self.accumulate(wrt, T::seed) {
+            Some(grad) => grad,
+            None => T::zero_grad(&self.context.vars[wrt.0]),
+        }
+    }
+
+    /// Compute the gradient with respect to each of the given variables
+    ///
+    /// This is the same as calling `grad` for each variable, but uses a
+    /// single forward and backward pass.
+    pub fn grads(&mut self, wrt: &[Variable]) -> Vec<T> {
+        let (_, grads) = accumulate_all(&self.expr, &mut self.context, wrt, T::seed);
+        let vars = &self.context.vars;
+        grads.into_iter().zip(wrt).map(|(grad, v)| grad.unwrap_or_else(|| T::zero_grad(&vars[v.0]))).collect()
+    }
+}
+
+// Back propagates a seed computed from the output value of `expr` and
+// returns the gradient accumulated at `wrt`, or `None` if `wrt` is not
+// part of the expression.
+fn accumulate<T, E, F>(expr: &E, context: &mut Context<T>, wrt: &Variable, seed: F) -> Option<T>
+    where T: Clone + Add<Output=T>, E: Expression<T> + ?Sized, F: FnOnce(&T) -> T
+{
+    // Reset the context
+    context.node_count = 0;
+
+    // Forward prop
+    let end = expr.eval(context);
+    let seed = seed(&end.value);
+
+    // Backward prop
+    let mut node_in_grads = HashMap::new();
+    node_in_grads.insert(end.index, vec![seed]);
+
+    for node in reverse_topology(&end, wrt.0) {
+        if !node_in_grads.contains_key(&node.index) {
+            // This ensures we don't try to sum an empty in_grads vec
+            continue;
+        } else {
+            let cur_in_grad = utils::assigning_sum(&node_in_grads[&node.index]);
+            for (argnum, p_node) in node.parents.iter().enumerate() {
+                let in_grad = node.vjp(cur_in_grad.clone(), p_node, argnum);
+                node_in_grads.entry(p_node.index).or_insert(vec![]).push(in_grad);
+            }
+        }
+    }
+
+    node_in_grads.get(&wrt.0).map(|grads| utils::assigning_sum(grads))
+}
+
+// Back propagates a seed computed from the output value of `expr` once
+// through the whole graph and returns the output value with the gradient
+// accumulated at each of `wrt`, which is `None` for the variables that
+// are not part of the expression.
+fn accumulate_all<T, E, F>(expr: &E, context: &mut Context<T>, wrt: &[Variable], seed: F) -> (T, Vec<Option<T>>)
+    where T: Clone + Add<Output=T>, E: Expression<T> + ?Sized, F: FnOnce(&T) -> T
+{
+    context.node_count = 0;
+    let end = expr.eval(context);
+    let seed = seed(&end.value);
+
+    let mut node_in_grads = HashMap::new();
+    node_in_grads.insert(end.index, vec![seed]);
+
+    for node in full_reverse_topology(&end) {
+        if let Some(in_grads) = node_in_grads.get(&node.index).map(|grads| utils::assigning_sum(grads)) {
+            for (argnum, p_node) in node.parents.iter().enumerate() {
+                let in_grad = node.vjp(in_grads.clone(), p_node, argnum);
+                node_in_grads.entry(p_node.index).or_insert(vec![]).push(in_grad);
+            }
+        }
     }
+
+    let grads = wrt.iter().map(|v| node_in_grads.get(&v.0).map(|grads| utils::assigning_sum(grads))).collect();
+    (end.value.clone(), grads)
 }
 
 /// An expression which can be evaluated
@@ -198,6 +313,12 @@ pub trait Expression<T> {
     fn eval(&self, c: &mut Context<T>) -> Node<T>;
 }
 
+impl<T, E: Expression<T> + ?Sized> Expression<T> for Box<E> {
+    fn eval(&self, c: &mut Context<T>) -> Node<T> {
+        (**self).eval(c)
+    }
+}
+
 /// The Vector-Jacobian product of gradients
 pub trait VecJacProduct<T> {
     /// The vjp function which determines how the gradient is back propagated
@@ -212,8 +333,10 @@ pub trait VecJacProduct<T> {
 /// The user is responsible for managing `Variable`s
 /// within a context. This means that the user should ensure
 /// that they do not mix up variables between different contexts.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Context<T> {
     vars: Vec<T>,
+    #[cfg_attr(feature = "serde", serde(skip))]
     node_count: usize,
 }
 
@@ -226,6 +349,13 @@ impl<T> Context<T> {
         }
     }
 
+    /// Returns all of the `Variable`s in this context
+    ///
+    /// The variables are returned in the order they were created.
+    pub fn variables(&self) -> Vec<Variable> {
+        (0..self.vars.len()).map(Variable).collect()
+    }
+
     fn get_index(&mut self) -> usize {
         let idx = self.vars.len() + self.node_count;
         self.node_count += 1;
@@ -361,7 +491,8 @@ impl<'a, T> Node<'a, T> {
 /// A Variable
 ///
 /// Each variable specifies an index into a Context.
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Variable(usize);
 
 impl Variable {
@@ -468,4 +599,31 @@ mod tests {
         assert!((grad.grad(&x) - 1.0) < 1e-5);
         assert!((grad.grad(&y) - 0.5) < 1e-5);
     }
+
+    #[test]
+    fn test_grads() {
+        let mut context = Context::new();
+        let x = context.create_variable(0.5);
+        let y = context.create_variable(1.5);
+        let z = context.create_variable(2.0);
+        let f = x * sin(x * y) + exp(y) * x;
+
+        let mut grad = Gradient::of(f, context);
+        let grads = grad.grads(&[*x, *y, *z]);
+        assert_eq!(grads, vec![grad.grad(&x), grad.grad(&y), 0.0]);
+    }
+
+    #[test]
+    fn test_absent_variable() {
+        let mut context = Context::new();
+        let x = context.create_variable(0.5);
+        let y = context.create_variable(1.5);
+        let f = sin(x);
+
+        let mut grad = Gradient::of(f, context);
+        assert_eq!(grad.try_backprop(&y, 1.0), None);
+        assert_eq!(grad.backprop(&y, 1.0), 0.0);
+        assert_eq!(grad.grad(&y), 0.0);
+        assert_eq!(grad.try_backprop(&x, 1.0), Some(grad.backprop(&x, 1.0)));
+    }
 }
@@ -0,0 +1,108 @@
+//! Small dense linear algebra used by the solvers
+//!
+//! Matrices are stored as vectors of rows.
+
+use num::Float;
+
+/// An LU factorisation with partial pivoting, `PA = LU`
+pub struct Lu<T> {
+    // L below the diagonal with a unit diagonal, U on and above it
+    lu: Vec<Vec<T>>,
+    perm: Vec<usize>,
+}
+
+impl<T: Float> Lu<T> {
+    /// Factorises a square matrix, returning `None` if it is singular
+    pub fn new(mut a: Vec<Vec<T>>) -> Option<Self> {
+        let n = a.len();
+        let mut perm: Vec<usize> = (0..n).collect();
+        let scale = a.iter().flat_map(|r| r.iter()).fold(T::zero(), |m, v| m.max(v.abs()));
+        let tol = scale * T::epsilon() * T::from(n.max(1)).unwrap();
+
+        for k in 0..n {
+            let pivot = (k..n).fold(k, |p, i| if a[i][k].abs() > a[p][k].abs() { i } else { p });
+            if !(a[pivot][k].abs() > tol) {
+                return None;
+            }
+            a.swap(k, pivot);
+            perm.swap(k, pivot);
+
+            for i in (k + 1)..n {
+                let factor = a[i][k] / a[k][k];
+                a[i][k] = factor;
+                for j in (k + 1)..n {
+                    let akj = a[k][j];
+                    a[i][j] = a[i][j] - factor * akj;
+                }
+            }
+        }
+        Some(Lu { lu: a, perm: perm })
+    }
+
+    /// Solves `Ax = b`
+    pub fn solve(&self, b: &[T]) -> Vec<T> {
+        let n = self.lu.len();
+        let mut x: Vec<T> = self.perm.iter().map(|&p| b[p]).collect();
+        for i in 0..n {
+            for j in 0..i {
+                x[i] = x[i] - self.lu[i][j] * x[j];
+            }
+        }
+        for i in (0..n).rev() {
+            for j in (i + 1)..n {
+                x[i] = x[i] - self.lu[i][j] * x[j];
+            }
+            x[i] = x[i] / self.lu[i][i];
+        }
+        x
+    }
+
+    /// Solves `A^T x = b`
+    pub fn solve_transpose(&self, b: &[T]) -> Vec<T> {
+        // A^T = U^T L^T P, solve U^T y = b then L^T w = y and x = P^T w
+        let n = self.lu.len();
+        let mut y = b.to_vec();
+        for i in 0..n {
+            for j in 0..i {
+                y[i] = y[i] - self.lu[j][i] * y[j];
+            }
+            y[i] = y[i] / self.lu[i][i];
+        }
+        for i in (0..n).rev() {
+            for j in (i + 1)..n {
+                y[i] = y[i] - self.lu[j][i] * y[j];
+            }
+        }
+        let mut x = vec![T::zero(); n];
+        for (i, &p) in self.perm.iter().enumerate() {
+            x[p] = y[i];
+        }
+        x
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_solve() {
+        let a = vec![vec![0.0, 2.0, 1.0], vec![1.0, 1.0, 0.0], vec![3.0, 0.0, 1.0]];
+        let lu = Lu::new(a.clone()).unwrap();
+        let b = [3.0, 2.0, 4.0];
+
+        let x = lu.solve(&b);
+        for i in 0..3 {
+            let ax: f64 = (0..3).map(|j| a[i][j] * x[j]).sum();
+            assert!((ax - b[i]).abs() < 1e-12);
+        }
+
+        let x = lu.solve_transpose(&b);
+        for i in 0..3 {
+            let atx: f64 = (0..3).map(|j| a[j][i] * x[j]).sum();
+            assert!((atx - b[i]).abs() < 1e-12);
+        }
+
+        assert!(Lu::new(vec![vec![1.0, 2.0], vec![2.0, 4.0]]).is_none());
+    }
+}
@@ -0,0 +1,647 @@
+//! Markov chain Monte Carlo module
+//!
+//! A `Sampler` draws from the distribution whose log density, up to a
+//! constant, is the expression of a `Gradient`. It uses Hamiltonian
+//! Monte Carlo, moving the variables with the leapfrog integrator and the
+//! gradient of the log density, either for a fixed number of steps or
+//! with the No-U-Turn sampler, which extends each trajectory until it
+//! starts to turn back on itself.
+//!
+//! During warmup the step size is adapted by dual averaging towards a
+//! target acceptance rate, and the diagonal of the inverse mass matrix
+//! is set to the variance of the draws in a series of doubling windows.
+//! Warmup draws are discarded.
+//!
+//! Draws are made with a `Rng` from the sampler's seed, so the same seed,
+//! log density and starting point give the same chain.
+//!
+//! # Example
+//!
+//! ```
+//! use rugrads::{Context, Gradient};
+//! use rugrads::mcmc;
+//!
+//! // A normal distribution with mean 1 and standard deviation 2
+//! let mut context = Context::new();
+//! let x = context.create_variable(0.0f64);
+//! let one = context.create_variable(1.0);
+//! let c = context.create_variable(-0.125);
+//! let mut grad = Gradient::of(c * (x - one) * (x - one), context);
+//!
+//! let chain = mcmc::nuts().seed(3).samples(1000).sample(&mut grad, &[*x]);
+//! assert_eq!(chain.draws.len(), 1000);
+//! assert!((chain.mean()[0] - 1.0).abs() < 0.3);
+//! assert!((chain.variance()[0] - 4.0).abs() < 1.0);
+//! ```
+
+use num::Float;
+
+use ::{accumulate_all, Expression, Gradient, Variable};
+use ::rng::Rng;
+
+// Trajectories whose energy grows by more than this are divergent
+const MAX_ENERGY_ERROR: f64 = 1000.0;
+
+/// A way of proposing the next draw
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Algorithm {
+    /// Hamiltonian Monte Carlo with a fixed number of leapfrog steps
+    Hmc {
+        /// The number of leapfrog steps in each trajectory
+        steps: usize,
+    },
+    /// The No-U-Turn sampler
+    ///
+    /// Each trajectory is doubled in a random direction until its ends
+    /// start to move towards each other, or it has `2^max_depth` steps.
+    Nuts {
+        /// The most doublings of a trajectory
+        max_depth: usize,
+    },
+}
+
+/// The draws of one chain with the adapted sampler settings
+#[derive(Clone, Debug)]
+pub struct Chain<T> {
+    /// The values of the variables at each draw, in the order they were given
+    pub draws: Vec<Vec<T>>,
+    /// The log density at each draw
+    pub log_density: Vec<T>,
+    /// The acceptance statistic of each draw's transition
+    pub accept_stat: Vec<T>,
+    /// The number of divergent transitions after warmup
+    pub divergences: usize,
+    /// The adapted step size
+    pub step_size: T,
+    /// The adapted diagonal of the inverse mass matrix
+    pub inv_mass: Vec<T>,
+}
+
+impl<T: Float> Chain<T> {
+    /// Returns the draws of one variable
+    pub fn column(&self, i: usize) -> Vec<T> {
+        self.draws.iter().map(|d| d[i]).collect()
+    }
+
+    /// Returns the mean of each variable over the draws
+    pub fn mean(&self) -> Vec<T> {
+        let n = T::from(self.draws.len()).unwrap();
+        let dim = self.draws.first().map_or(0, |d| d.len());
+        (0..dim).map(|i| self.draws.iter().fold(T::zero(), |s, d| s + d[i]) / n).collect()
+    }
+
+    /// Returns the sample variance of each variable over the draws
+    pub fn variance(&self) -> Vec<T> {
+        let n = T::from(self.draws.len()).unwrap();
+        self.mean().iter().enumerate().map(|(i, &m)| {
+            self.draws.iter().fold(T::zero(), |s, d| s + (d[i] - m) * (d[i] - m)) / (n - T::one())
+        }).collect()
+    }
+
+    /// Returns the mean acceptance statistic
+    pub fn acceptance_rate(&self) -> T {
+        let n = T::from(self.accept_stat.len()).unwrap();
+        self.accept_stat.iter().fold(T::zero(), |s, &a| s + a) / n
+    }
+}
+
+/// A Hamiltonian Monte Carlo sampler
+#[derive(Clone, Copy, Debug)]
+pub struct Sampler<T> {
+    algorithm: Algorithm,
+    warmup: usize,
+    samples: usize,
+    seed: u64,
+    step_size: Option<T>,
+    target_accept: T,
+    adapt_mass: bool,
+}
+
+/// Creates a Hamiltonian Monte Carlo sampler with a fixed number of leapfrog steps
+pub fn hmc<T: Float>(steps: usize) -> Sampler<T> {
+    Sampler::new(Algorithm::Hmc { steps: steps })
+}
+
+/// Creates a No-U-Turn sampler with a maximum depth of 10
+pub fn nuts<T: Float>() -> Sampler<T> {
+    Sampler::new(Algorithm::Nuts { max_depth: 10 })
+}
+
+impl<T: Float> Sampler<T> {
+    /// Creates a sampler
+    ///
+    /// The defaults take 1000 warmup draws and 1000 draws with seed 0,
+    /// adapt the step size towards an acceptance rate of 0.8 starting from
+    /// a heuristic guess, and adapt the mass matrix.
+    pub fn new(algorithm: Algorithm) -> Self {
+        Sampler {
+            algorithm: algorithm,
+            warmup: 1000,
+            samples: 1000,
+            seed: 0,
+            step_size: None,
+            target_accept: T::from(0.8).unwrap(),
+            adapt_mass: true,
+        }
+    }
+
+    /// Sets the number of warmup draws, which are discarded
+    pub fn warmup(mut self, warmup: usize) -> Self {
+        self.warmup = warmup;
+        self
+    }
+
+    /// Sets the number of draws kept
+    pub fn samples(mut self, samples: usize) -> Self {
+        self.samples = samples;
+        self
+    }
+
+    /// Sets the seed of the random number generator
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Sets the initial step size
+    ///
+    /// With no warmup this step size is used throughout.
+    pub fn step_size(mut self, step_size: T) -> Self {
+        self.step_size = Some(step_size);
+        self
+    }
+
+    /// Sets the acceptance rate the step size is adapted towards
+    pub fn target_accept(mut self, target_accept: T) -> Self {
+        self.target_accept = target_accept;
+        self
+    }
+
+    /// Sets whether the mass matrix is adapted during warmup
+    pub fn adapt_mass(mut self, adapt_mass: bool) -> Self {
+        self.adapt_mass = adapt_mass;
+        self
+    }
+
+    /// Draws a chain over `params` starting from their current values
+    ///
+    /// The variables are left at the last draw.
+    pub fn sample<E: Expression<T>>(&self, grad: &mut Gradient<T, E>, params: &[Variable]) -> Chain<T> {
+        let mut rng = Rng::new(self.seed);
+        let mut target = Target { grad: grad, params: params };
+        let x = params.iter().map(|p| *target.grad.get(p)).collect();
+        let mut state = target.state(x);
+        let mut inv_mass = vec![T::one(); params.len()];
+
+        let mut step_size = match self.step_size {
+            Some(step_size) => step_size,
+            None => initial_step_size(&mut target, &mut rng, &state, &inv_mass),
+        };
+        let mut adaptation = DualAveraging::new(step_size, self.target_accept);
+        let windows = if self.adapt_mass { mass_windows(self.warmup) } else { vec![] };
+        let mut variance = Welford::new(params.len());
+
+        let mut chain = Chain {
+            draws: Vec::with_capacity(self.samples),
+            log_density: Vec::with_capacity(self.samples),
+            accept_stat: Vec::with_capacity(self.samples),
+            divergences: 0,
+            step_size: step_size,
+            inv_mass: vec![],
+        };
+
+        for i in 0..(self.warmup + self.samples) {
+            let (next, accept, divergent) = match self.algorithm {
+                Algorithm::Hmc { steps } => hmc_transition(&mut target, &mut rng, &state, step_size, &inv_mass, steps),
+                Algorithm::Nuts { max_depth } => {
+                    let mut nuts = Nuts { target: &mut target, rng: &mut rng, inv_mass: &inv_mass, step_size: step_size };
+                    nuts.transition(&state, max_depth)
+                }
+            };
+            state = next;
+
+            if i < self.warmup {
+                step_size = adaptation.update(accept);
+                if windows.iter().any(|&(start, end)| (start..end).contains(&i)) {
+                    variance.add(&state.x);
+                }
+                if windows.iter().any(|&(_, end)| i + 1 == end) {
+                    inv_mass = variance.regularized();
+                    variance = Welford::new(params.len());
+                    step_size = initial_step_size(&mut target, &mut rng, &state, &inv_mass);
+                    adaptation = DualAveraging::new(step_size, self.target_accept);
+                }
+                if i + 1 == self.warmup {
+                    step_size = adaptation.adapted();
+                }
+            } else {
+                chain.draws.push(state.x.clone());
+                chain.log_density.push(state.log_density);
+                chain.accept_stat.push(accept);
+                if divergent {
+                    chain.divergences += 1;
+                }
+            }
+        }
+
+        target.set(&state.x);
+        chain.step_size = step_size;
+        chain.inv_mass = inv_mass;
+        chain
+    }
+
+    /// Draws several chains from the same starting values
+    ///
+    /// Chain `k` uses the seed plus `k`.
+    pub fn chains<E: Expression<T>>(&self, grad: &mut Gradient<T, E>, params: &[Variable], n: usize) -> Vec<Chain<T>> {
+        let start: Vec<T> = params.iter().map(|p| *grad.get(p)).collect();
+        (0..n).map(|k| {
+            for (p, &v) in params.iter().zip(&start) {
+                *grad.get_mut(p) = v;
+            }
+            self.seed(self.seed.wrapping_add(k as u64)).sample(grad, params)
+        }).collect()
+    }
+}
+
+// A point in phase space
+#[derive(Clone)]
+struct State<T> {
+    x: Vec<T>,
+    p: Vec<T>,
+    log_density: T,
+    grad: Vec<T>,
+}
+
+impl<T: Float> State<T> {
+    // The Hamiltonian, the negative log density plus the kinetic energy
+    fn energy(&self, inv_mass: &[T]) -> T {
+        let kinetic = self.p.iter().zip(inv_mass).fold(T::zero(), |s, (&p, &m)| s + m * p * p);
+        kinetic / (T::one() + T::one()) - self.log_density
+    }
+}
+
+// The log density and its gradient at points of its parameters
+struct Target<'a, T: 'a, E: 'a + Expression<T>> {
+    grad: &'a mut Gradient<T, E>,
+    params: &'a [Variable],
+}
+
+impl<'a, T: Float, E: Expression<T>> Target<'a, T, E> {
+    fn set(&mut self, x: &[T]) {
+        for (p, &v) in self.params.iter().zip(x) {
+            *self.grad.get_mut(p) = v;
+        }
+    }
+
+    fn state(&mut self, x: Vec<T>) -> State<T> {
+        self.set(&x);
+        let grad = &mut *self.grad;
+        let (log_density, grads) = accumulate_all(&grad.expr, &mut grad.context, self.params, |_| T::one());
+        let grad = grads.into_iter().map(|g| g.unwrap_or(T::zero())).collect();
+        State { p: vec![T::zero(); x.len()], x: x, log_density: log_density, grad: grad }
+    }
+
+    // One leapfrog step of size `eps`, which is negative to go backwards
+    fn leapfrog(&mut self, state: &State<T>, eps: T, inv_mass: &[T]) -> State<T> {
+        let half = eps / (T::one() + T::one());
+        let p: Vec<T> = state.p.iter().zip(&state.grad).map(|(&p, &g)| p + half * g).collect();
+        let x = state.x.iter().zip(&p).zip(inv_mass).map(|((&x, &p), &m)| x + eps * m * p).collect();
+        let mut next = self.state(x);
+        next.p = p.iter().zip(&next.grad).map(|(&p, &g)| p + half * g).collect();
+        next
+    }
+}
+
+fn uniform<T: Float>(rng: &mut Rng) -> T {
+    T::from(rng.next_f64()).unwrap()
+}
+
+// Draws a momentum from the normal distribution with the mass matrix as covariance
+fn momentum<T: Float>(rng: &mut Rng, inv_mass: &[T]) -> Vec<T> {
+    inv_mass.iter().map(|&m| T::from(rng.normal()).unwrap() / m.sqrt()).collect()
+}
+
+// The Metropolis acceptance probability, which is zero for non-finite energies
+fn acceptance<T: Float>(h0: T, h: T) -> T {
+    if h.is_finite() { (h0 - h).exp().min(T::one()) } else { T::zero() }
+}
+
+// Doubles or halves a step size from 1 until the acceptance probability
+// of one leapfrog step crosses a half
+fn initial_step_size<T, E>(target: &mut Target<T, E>, rng: &mut Rng, state: &State<T>, inv_mass: &[T]) -> T
+    where T: Float, E: Expression<T>
+{
+    let two = T::one() + T::one();
+    let log_half = two.recip().ln();
+    let mut start = state.clone();
+    start.p = momentum(rng, inv_mass);
+    let h0 = start.energy(inv_mass);
+    let log_accept = |target: &mut Target<T, E>, eps: T| {
+        let h = target.leapfrog(&start, eps, inv_mass).energy(inv_mass);
+        if h.is_finite() { h0 - h } else { T::neg_infinity() }
+    };
+
+    let mut eps = T::one();
+    let grow = log_accept(target, eps) > log_half;
+    for _ in 0..100 {
+        let next = if grow { eps * two } else { eps / two };
+        if (log_accept(target, next) > log_half) != grow {
+            return if grow { eps } else { next };
+        }
+        eps = next;
+    }
+    eps
+}
+
+fn hmc_transition<T, E>(target: &mut Target<T, E>, rng: &mut Rng, state: &State<T>, eps: T, inv_mass: &[T], steps: usize)
+    -> (State<T>, T, bool)
+    where T: Float, E: Expression<T>
+{
+    let mut start = state.clone();
+    start.p = momentum(rng, inv_mass);
+    let h0 = start.energy(inv_mass);
+    let mut end = start.clone();
+    for _ in 0..steps {
+        end = target.leapfrog(&end, eps, inv_mass);
+    }
+    let h = end.energy(inv_mass);
+    let accept = acceptance(h0, h);
+    let error = h - h0;
+    let divergent = error.is_nan() || error >= T::from(MAX_ENERGY_ERROR).unwrap();
+    if uniform::<T>(rng) < accept {
+        (end, accept, divergent)
+    } else {
+        (start, accept, divergent)
+    }
+}
+
+// A subtree of a No-U-Turn trajectory
+struct Tree<T> {
+    minus: State<T>,
+    plus: State<T>,
+    proposal: State<T>,
+    // The number of states within the slice
+    n: usize,
+    // False once the subtree has turned or diverged
+    ok: bool,
+    divergent: bool,
+    accept_sum: T,
+    accept_count: usize,
+}
+
+// The slice sampling No-U-Turn sampler of Hoffman and Gelman
+struct Nuts<'a, 'b: 'a, T: 'a + 'b, E: 'b + Expression<T>> {
+    target: &'a mut Target<'b, T, E>,
+    rng: &'a mut Rng,
+    inv_mass: &'a [T],
+    step_size: T,
+}
+
+impl<'a, 'b, T: Float, E: Expression<T>> Nuts<'a, 'b, T, E> {
+    // True unless the ends of the trajectory are moving towards each other
+    fn no_u_turn(&self, minus: &State<T>, plus: &State<T>) -> bool {
+        let (mut a, mut b) = (T::zero(), T::zero());
+        for i in 0..minus.x.len() {
+            let dx = plus.x[i] - minus.x[i];
+            a = a + dx * self.inv_mass[i] * minus.p[i];
+            b = b + dx * self.inv_mass[i] * plus.p[i];
+        }
+        a >= T::zero() && b >= T::zero()
+    }
+
+    fn build(&mut self, state: &State<T>, log_u: T, forward: bool, depth: usize, h0: T) -> Tree<T> {
+        if depth == 0 {
+            let eps = if forward { self.step_size } else { -self.step_size };
+            let next = self.target.leapfrog(state, eps, self.inv_mass);
+            let h = next.energy(self.inv_mass);
+            let ok = log_u < T::from(MAX_ENERGY_ERROR).unwrap() - h;
+            return Tree {
+                minus: next.clone(),
+                plus: next.clone(),
+                n: if log_u <= -h { 1 } else { 0 },
+                proposal: next,
+                ok: ok,
+                divergent: !ok,
+                accept_sum: acceptance(h0, h),
+                accept_count: 1,
+            };
+        }
+
+        let mut tree = self.build(state, log_u, forward, depth - 1, h0);
+        if !tree.ok {
+            return tree;
+        }
+        let other = if forward {
+            let other = self.build(&tree.plus.clone(), log_u, forward, depth - 1, h0);
+            tree.plus = other.plus.clone();
+            other
+        } else {
+            let other = self.build(&tree.minus.clone(), log_u, forward, depth - 1, h0);
+            tree.minus = other.minus.clone();
+            other
+        };
+
+        let total = tree.n + other.n;
+        if total > 0 && uniform::<T>(self.rng) * T::from(total).unwrap() < T::from(other.n).unwrap() {
+            tree.proposal = other.proposal;
+        }
+        tree.n = total;
+        tree.ok = other.ok && self.no_u_turn(&tree.minus, &tree.plus);
+        tree.divergent = other.divergent;
+        tree.accept_sum = tree.accept_sum + other.accept_sum;
+        tree.accept_count += other.accept_count;
+        tree
+    }
+
+    fn transition(&mut self, state: &State<T>, max_depth: usize) -> (State<T>, T, bool) {
+        let mut start = state.clone();
+        start.p = momentum(self.rng, self.inv_mass);
+        let h0 = start.energy(self.inv_mass);
+        // The slice variable, drawn uniformly from (0, exp(-h0))
+        let log_u = (T::one() - uniform::<T>(self.rng)).ln() - h0;
+
+        let mut minus = start.clone();
+        let mut plus = start.clone();
+        let mut proposal = start;
+        let mut n = 1;
+        let mut divergent = false;
+        let (mut accept_sum, mut accept_count) = (T::zero(), 0);
+
+        for depth in 0..max_depth {
+            let forward = self.rng.next_u64() & 1 == 1;
+            let tree = if forward {
+                let tree = self.build(&plus, log_u, true, depth, h0);
+                plus = tree.plus.clone();
+                tree
+            } else {
+                let tree = self.build(&minus, log_u, false, depth, h0);
+                minus = tree.minus.clone();
+                tree
+            };
+            accept_sum = accept_sum + tree.accept_sum;
+            accept_count += tree.accept_count;
+            divergent = divergent || tree.divergent;
+
+            if tree.ok && uniform::<T>(self.rng) * T::from(n).unwrap() < T::from(tree.n).unwrap() {
+                proposal = tree.proposal;
+            }
+            n += tree.n;
+            if !tree.ok || !self.no_u_turn(&minus, &plus) {
+                break;
+            }
+        }
+
+        (proposal, accept_sum / T::from(accept_count).unwrap(), divergent)
+    }
+}
+
+// Nesterov dual averaging of the log step size, as in Hoffman and Gelman
+struct DualAveraging<T> {
+    mu: T,
+    target: T,
+    error: T,
+    log_eps_bar: T,
+    count: usize,
+}
+
+impl<T: Float> DualAveraging<T> {
+    fn new(step_size: T, target: T) -> Self {
+        DualAveraging {
+            mu: (T::from(10.0).unwrap() * step_size).ln(),
+            target: target,
+            error: T::zero(),
+            log_eps_bar: T::zero(),
+            count: 0,
+        }
+    }
+
+    // Records an acceptance statistic and returns the next step size
+    fn update(&mut self, accept: T) -> T {
+        let (gamma, t0, kappa) = (T::from(0.05).unwrap(), T::from(10.0).unwrap(), T::from(0.75).unwrap());
+        self.count += 1;
+        let m = T::from(self.count).unwrap();
+        let w = (m + t0).recip();
+        self.error = (T::one() - w) * self.error + w * (self.target - accept);
+        let log_eps = self.mu - m.sqrt() / gamma * self.error;
+        let eta = m.powf(-kappa);
+        self.log_eps_bar = eta * log_eps + (T::one() - eta) * self.log_eps_bar;
+        log_eps.exp()
+    }
+
+    // The averaged step size to use after warmup
+    fn adapted(&self) -> T {
+        self.log_eps_bar.exp()
+    }
+}
+
+// Running means and variances by Welford's algorithm
+struct Welford<T> {
+    n: usize,
+    mean: Vec<T>,
+    m2: Vec<T>,
+}
+
+impl<T: Float> Welford<T> {
+    fn new(dim: usize) -> Self {
+        Welford { n: 0, mean: vec![T::zero(); dim], m2: vec![T::zero(); dim] }
+    }
+
+    fn add(&mut self, x: &[T]) {
+        self.n += 1;
+        let n = T::from(self.n).unwrap();
+        for ((&x, mean), m2) in x.iter().zip(&mut self.mean).zip(&mut self.m2) {
+            let delta = x - *mean;
+            *mean = *mean + delta / n;
+            *m2 = *m2 + delta * (x - *mean);
+        }
+    }
+
+    // The variances shrunk towards a small constant, as Stan does
+    fn regularized(&self) -> Vec<T> {
+        let n = T::from(self.n).unwrap();
+        let five = T::from(5.0).unwrap();
+        let shrink = T::from(1e-3).unwrap() * five / (n + five);
+        self.m2.iter().map(|&m2| n / (n + five) * m2 / (n - T::one()) + shrink).collect()
+    }
+}
+
+// The warmup windows over which the mass matrix is estimated
+//
+// As in Stan, the first 15% and last 10% of warmup only adapt the step
+// size, and the rest is split into windows which double in length, the
+// last stretching to the end.
+fn mass_windows(warmup: usize) -> Vec<(usize, usize)> {
+    if warmup < 20 {
+        return vec![];
+    }
+    let (start, end) = (warmup * 15 / 100, warmup - warmup / 10);
+    let mut size = ((end - start) / 8).clamp(10, 25);
+    let mut windows = vec![];
+    let mut from = start;
+    while from < end {
+        let mut to = from + size;
+        if to + 2 * size > end {
+            to = end;
+        }
+        windows.push((from, to));
+        from = to;
+        size *= 2;
+    }
+    windows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::Context;
+
+    // A normal distribution with standard deviations 1 and 10 and correlation 0.5
+    fn normal() -> (Gradient<f64, Box<dyn Expression<f64>>>, Variable, Variable) {
+        let mut c = Context::new();
+        let x = c.create_variable(3.0);
+        let y = c.create_variable(-20.0);
+        let (a, b, d) = (c.create_variable(-2.0 / 3.0), c.create_variable(2.0 / 30.0), c.create_variable(-2.0 / 300.0));
+        let f: Box<dyn Expression<f64>> = Box::new(a * x * x + b * x * y + d * y * y);
+        (Gradient::of(::Container::new(f), c), *x, *y)
+    }
+
+    #[test]
+    fn test_mass_windows() {
+        assert!(mass_windows(10).is_empty());
+        let windows = mass_windows(1000);
+        assert_eq!(windows.first().unwrap().0, 150);
+        assert_eq!(windows.last().unwrap().1, 900);
+        for w in windows.windows(2) {
+            assert_eq!(w[0].1, w[1].0);
+        }
+    }
+
+    #[test]
+    fn test_nuts_normal() {
+        let (mut g, x, y) = normal();
+        let chain = nuts().seed(11).warmup(500).samples(1000).sample(&mut g, &[x, y]);
+
+        assert_eq!(chain.divergences, 0);
+        let (mean, var) = (chain.mean(), chain.variance());
+        assert!(mean[0].abs() < 0.2 && mean[1].abs() < 2.0, "{:?}", mean);
+        assert!((var[0] - 1.0).abs() < 0.3 && (var[1] - 100.0).abs() < 30.0, "{:?}", var);
+        assert!((chain.inv_mass[1] / chain.inv_mass[0] - 100.0).abs() < 50.0, "{:?}", chain.inv_mass);
+        assert!(chain.acceptance_rate() > 0.6);
+        assert_eq!(*g.get(&x), chain.draws[999][0]);
+    }
+
+    #[test]
+    fn test_hmc_reproducible() {
+        let (mut g, x, y) = normal();
+        let sampler = hmc(10).seed(5).warmup(200).samples(200);
+        let chains = sampler.chains(&mut g, &[x, y], 2);
+        let (mut g, x, y) = normal();
+        let again = sampler.sample(&mut g, &[x, y]);
+
+        assert_eq!(chains[0].draws, again.draws);
+        assert_eq!(chains[0].step_size, again.step_size);
+        assert!(chains[0].draws != chains[1].draws);
+        assert!(chains.iter().all(|c| c.acceptance_rate() > 0.5));
+    }
+}
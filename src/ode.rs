@@ -0,0 +1,505 @@
+//! Ordinary differential equation module
+//!
+//! `solve` integrates `dy/dt = f(t, y, θ)` from `y(t0) = y0` to `t1` and
+//! returns `y(t1)` as expressions. The right hand side is built from
+//! expressions by a closure, which is given the time, the state and the
+//! parameters as variables of a separate context.
+//!
+//! The steps of the forward solve are not recorded. The backward pass
+//! integrates the adjoint equations
+//!
+//! ```text
+//! da/dt = -a ∂f/∂y,    da_θ/dt = -a ∂f/∂θ
+//! ```
+//!
+//! from `t1` back to `t0` along with the state, so memory does not grow
+//! with the number of steps. The state is recomputed backwards rather than
+//! stored, which loses accuracy for systems which are unstable in reverse.
+//!
+//! # Example
+//!
+//! ```
+//! use rugrads::{Context, Expression, Gradient};
+//! use rugrads::ode::{self, Method};
+//!
+//! let mut context = Context::new();
+//! let y0 = context.create_variable(2.0);
+//! let k = context.create_variable(0.5);
+//!
+//! // dy/dt = -k y, so y(1) = y0 exp(-k)
+//! let y = ode::solve(|_, y, p| vec![Box::new(-(p[0] * y[0])) as Box<dyn Expression<f64>>],
+//!                    &[y0], (0.0, 1.0), &[k], Method::adaptive());
+//! let mut grad = Gradient::of(y[0].clone(), context);
+//!
+//! assert!((grad.value() - 2.0 * f64::exp(-0.5)).abs() < 1e-7);
+//! assert!((grad.grad(&y0) - f64::exp(-0.5)).abs() < 1e-7);
+//! assert!((grad.grad(&k) + 2.0 * f64::exp(-0.5)).abs() < 1e-7);
+//! ```
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use num::Float;
+
+use ::{accumulate_all, Container, Context, Expression, Node, VecJacProduct, Variable};
+
+/// An integration method
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Method<T> {
+    /// The classic fourth order Runge-Kutta method with a fixed number of steps
+    Rk4 {
+        /// The number of steps between the start and end times
+        steps: usize,
+    },
+    /// The Dormand-Prince 5(4) method with adaptive steps
+    ///
+    /// Steps are accepted when the estimated error in each component is
+    /// within `atol + rtol * |y|`.
+    Dopri5 {
+        /// The relative tolerance
+        rtol: T,
+        /// The absolute tolerance
+        atol: T,
+    },
+}
+
+impl<T: Float> Method<T> {
+    /// The adaptive method with `rtol = 1e-10` and `atol = 1e-12`
+    pub fn adaptive() -> Self {
+        Method::Dopri5 { rtol: T::from(1e-10).unwrap(), atol: T::from(1e-12).unwrap() }
+    }
+}
+
+// Butcher tableaus
+const RK4_C: [f64; 4] = [0.0, 0.5, 0.5, 1.0];
+const RK4_A: [&[f64]; 4] = [&[], &[0.5], &[0.0, 0.5], &[0.0, 0.0, 1.0]];
+const RK4_B: [f64; 4] = [1.0 / 6.0, 1.0 / 3.0, 1.0 / 3.0, 1.0 / 6.0];
+
+const DOPRI_C: [f64; 7] = [0.0, 1.0 / 5.0, 3.0 / 10.0, 4.0 / 5.0, 8.0 / 9.0, 1.0, 1.0];
+const DOPRI_A: [&[f64]; 7] = [
+    &[],
+    &[1.0 / 5.0],
+    &[3.0 / 40.0, 9.0 / 40.0],
+    &[44.0 / 45.0, -56.0 / 15.0, 32.0 / 9.0],
+    &[19372.0 / 6561.0, -25360.0 / 2187.0, 64448.0 / 6561.0, -212.0 / 729.0],
+    &[9017.0 / 3168.0, -355.0 / 33.0, 46732.0 / 5247.0, 49.0 / 176.0, -5103.0 / 18656.0],
+    &[35.0 / 384.0, 0.0, 500.0 / 1113.0, 125.0 / 192.0, -2187.0 / 6784.0, 11.0 / 84.0],
+];
+const DOPRI_B: [f64; 7] = [35.0 / 384.0, 0.0, 500.0 / 1113.0, 125.0 / 192.0, -2187.0 / 6784.0,
+                           11.0 / 84.0, 0.0];
+// The fifth order weights minus the embedded fourth order weights
+const DOPRI_E: [f64; 7] = [71.0 / 57600.0, 0.0, -71.0 / 16695.0, 71.0 / 1920.0, -17253.0 / 339200.0,
+                           22.0 / 525.0, -1.0 / 40.0];
+
+fn c<T: Float>(v: f64) -> T {
+    T::from(v).unwrap()
+}
+
+// y + h sum b_j k_j
+fn combine<T: Float>(y: &[T], h: T, ks: &[Vec<T>], b: &[f64]) -> Vec<T> {
+    let mut out = y.to_vec();
+    for (k, &b) in ks.iter().zip(b) {
+        if b != 0.0 {
+            for (o, &k) in out.iter_mut().zip(k) {
+                *o = *o + h * c::<T>(b) * k;
+            }
+        }
+    }
+    out
+}
+
+// The stages of an explicit Runge-Kutta step
+fn stages<T, F>(f: &mut F, t: T, y: &[T], h: T, a: &[&[f64]], cs: &[f64]) -> Vec<Vec<T>>
+    where T: Float, F: FnMut(T, &[T]) -> Vec<T>
+{
+    let mut ks: Vec<Vec<T>> = Vec::with_capacity(cs.len());
+    for (row, &c_s) in a.iter().zip(cs) {
+        let y_s = combine(y, h, &ks, row);
+        ks.push(f(t + h * c::<T>(c_s), &y_s));
+    }
+    ks
+}
+
+// Integrates `dy/dt = f(t, y)` from `t0` to `t1`, which may be before `t0`
+//
+// The adaptive method returns NaN if the step size underflows.
+fn integrate<T, F>(mut f: F, y0: Vec<T>, t0: T, t1: T, method: Method<T>) -> Vec<T>
+    where T: Float, F: FnMut(T, &[T]) -> Vec<T>
+{
+    match method {
+        Method::Rk4 { steps } => {
+            let h = (t1 - t0) / c(steps as f64);
+            let mut y = y0;
+            for step in 0..steps {
+                let t = t0 + h * c(step as f64);
+                let ks = stages(&mut f, t, &y, h, &RK4_A, &RK4_C);
+                y = combine(&y, h, &ks, &RK4_B);
+            }
+            y
+        }
+        Method::Dopri5 { rtol, atol } => {
+            let mut t = t0;
+            let mut y = y0;
+            let mut h = (t1 - t0) * c(0.01);
+            let mut done = t0 == t1;
+            while !done {
+                let last = (t + h - t1) * h >= T::zero();
+                if last {
+                    h = t1 - t;
+                }
+
+                let ks = stages(&mut f, t, &y, h, &DOPRI_A, &DOPRI_C);
+                let next = combine(&y, h, &ks, &DOPRI_B);
+                let zeros = vec![T::zero(); y.len()];
+                let err_est = combine(&zeros, h, &ks, &DOPRI_E);
+
+                // The RMS of the error relative to the tolerance
+                let sum_sq = err_est.iter().zip(&y).zip(&next).fold(T::zero(), |s, ((&e, &y), &n)| {
+                    let scale = atol + rtol * y.abs().max(n.abs());
+                    s + (e / scale) * (e / scale)
+                });
+                let err = (sum_sq / c(y.len().max(1) as f64)).sqrt();
+
+                if err <= T::one() {
+                    t = if last { t1 } else { t + h };
+                    y = next;
+                    done = last;
+                }
+
+                let factor = if err == T::zero() { c(5.0) } else { c::<T>(0.9) * err.powf(c(-0.2)) };
+                h = h * factor.max(c(0.2)).min(c(5.0));
+                if !done && h.abs() <= T::epsilon() * t.abs().max(T::one()) {
+                    return vec![T::nan(); y.len()];
+                }
+            }
+            y
+        }
+    }
+}
+
+// The right hand side of the system
+struct Rhs<T> {
+    context: Context<T>,
+    t: Variable,
+    y: Vec<Variable>,
+    params: Vec<Variable>,
+    exprs: Vec<Box<dyn Expression<T>>>,
+}
+
+impl<T: Float> Rhs<T> {
+    fn set(&mut self, t: T, y: &[T], params: &[T]) {
+        self.context.set_variable_value(&self.t, t);
+        for (var, &v) in self.y.iter().zip(y) {
+            self.context.set_variable_value(var, v);
+        }
+        for (var, &v) in self.params.iter().zip(params) {
+            self.context.set_variable_value(var, v);
+        }
+    }
+
+    fn eval(&mut self, t: T, y: &[T], params: &[T]) -> Vec<T> {
+        self.set(t, y, params);
+        let context = &mut self.context;
+        self.exprs.iter().map(|e| {
+            context.node_count = 0;
+            e.eval(context).value
+        }).collect()
+    }
+
+    // Returns `a ∂f/∂y` and `a ∂f/∂θ`
+    fn vjp(&mut self, t: T, y: &[T], params: &[T], a: &[T]) -> (Vec<T>, Vec<T>) {
+        self.set(t, y, params);
+        let dot = Dot { exprs: &self.exprs, weights: a };
+        let vars: Vec<Variable> = self.y.iter().chain(&self.params).cloned().collect();
+        let (_, grads) = accumulate_all(&dot, &mut self.context, &vars, |_| T::one());
+        let mut a_y: Vec<T> = grads.into_iter().map(|g| g.unwrap_or(T::zero())).collect();
+        let a_params = a_y.split_off(self.y.len());
+        (a_y, a_params)
+    }
+}
+
+struct WeightsVJP<T>(Vec<T>);
+
+impl<T: Float> VecJacProduct<T> for WeightsVJP<T> {
+    fn vjp(&self, g: T, _: &Node<T>, _: &Node<T>, idx: usize) -> T {
+        g * self.0[idx]
+    }
+}
+
+// The weighted sum of the right hand side, so one back propagation gives
+// all of `a ∂f/∂(y, θ)`
+struct Dot<'a, T: 'a> {
+    exprs: &'a [Box<dyn Expression<T>>],
+    weights: &'a [T],
+}
+
+impl<'a, T: Float> Expression<T> for Dot<'a, T> {
+    fn eval(&self, c: &mut Context<T>) -> Node<T> {
+        let parents: Vec<_> = self.exprs.iter().map(|e| e.eval(c)).collect();
+        let progenitors = Node::get_progenitors(&parents);
+
+        let value = parents.iter().zip(self.weights).fold(T::zero(), |s, (p, &w)| s + w * p.value);
+        Node {
+            index: c.get_index(),
+            value: value,
+            parents: parents,
+            progenitors: progenitors,
+            _vjp: Box::new(WeightsVJP(self.weights.to_vec())),
+        }
+    }
+}
+
+// The final state for the last inputs
+struct Solution<T> {
+    inputs: Vec<T>,
+    y1: Vec<T>,
+    // The gradient of each output with respect to the inputs, computed
+    // when it is first needed
+    adjoints: RefCell<Vec<Option<Rc<Vec<T>>>>>,
+}
+
+struct Problem<T> {
+    rhs: RefCell<Rhs<T>>,
+    t_span: (T, T),
+    method: Method<T>,
+    last: RefCell<Option<Rc<Solution<T>>>>,
+}
+
+impl<T: Float> Problem<T> {
+    fn dim(&self) -> usize {
+        self.rhs.borrow().y.len()
+    }
+
+    fn solve(&self, inputs: &[T]) -> Rc<Solution<T>> {
+        if let Some(ref last) = *self.last.borrow() {
+            if last.inputs.as_slice() == inputs {
+                return last.clone();
+            }
+        }
+
+        let n = self.dim();
+        let (y0, params) = inputs.split_at(n);
+        let mut rhs = self.rhs.borrow_mut();
+        let (t0, t1) = self.t_span;
+        let y1 = integrate(|t, y| rhs.eval(t, y, params), y0.to_vec(), t0, t1, self.method);
+
+        let solution = Rc::new(Solution {
+            inputs: inputs.to_vec(),
+            y1: y1,
+            adjoints: RefCell::new(vec![None; n]),
+        });
+        *self.last.borrow_mut() = Some(solution.clone());
+        solution
+    }
+
+    // Integrates the state and the adjoint of output `i` back to the start
+    fn adjoint(&self, solution: &Solution<T>, i: usize) -> Rc<Vec<T>> {
+        if let Some(ref adjoint) = solution.adjoints.borrow()[i] {
+            return adjoint.clone();
+        }
+
+        let n = self.dim();
+        let params = &solution.inputs[n..];
+        let mut state = solution.y1.clone();
+        state.extend((0..n).map(|j| if j == i { T::one() } else { T::zero() }));
+        state.extend(params.iter().map(|_| T::zero()));
+
+        let mut rhs = self.rhs.borrow_mut();
+        let (t0, t1) = self.t_span;
+        let state = integrate(|t, s| {
+            let (y, a) = s.split_at(n);
+            let mut ds = rhs.eval(t, y, params);
+            let (a_y, a_params) = rhs.vjp(t, y, params, &a[..n]);
+            ds.extend(a_y.into_iter().chain(a_params).map(|v| -v));
+            ds
+        }, state, t1, t0, self.method);
+
+        let adjoint = Rc::new(state[n..].to_vec());
+        solution.adjoints.borrow_mut()[i] = Some(adjoint.clone());
+        adjoint
+    }
+}
+
+struct AdjointVJP<T> {
+    problem: Rc<Problem<T>>,
+    solution: Rc<Solution<T>>,
+    i: usize,
+}
+
+impl<T: Float> VecJacProduct<T> for AdjointVJP<T> {
+    fn vjp(&self, g: T, _: &Node<T>, _: &Node<T>, idx: usize) -> T {
+        g * self.problem.adjoint(&self.solution, self.i)[idx]
+    }
+}
+
+/// One component of the final state of an ODE
+///
+/// The components of one problem share the solve, so evaluating all of
+/// them at the same inputs integrates the system once.
+pub struct OdeSolve<T, Y, P> {
+    problem: Rc<Problem<T>>,
+    y0: Vec<Y>,
+    params: Vec<P>,
+    i: usize,
+}
+
+impl<T, Y: Clone, P: Clone> Clone for OdeSolve<T, Y, P> {
+    fn clone(&self) -> Self {
+        OdeSolve {
+            problem: self.problem.clone(),
+            y0: self.y0.clone(),
+            params: self.params.clone(),
+            i: self.i,
+        }
+    }
+}
+
+impl<T: Float, Y: Expression<T>, P: Expression<T>> Expression<T> for OdeSolve<T, Y, P> {
+    fn eval(&self, c: &mut Context<T>) -> Node<T> {
+        let mut parents: Vec<_> = self.y0.iter().map(|y| y.eval(c)).collect();
+        parents.extend(self.params.iter().map(|p| p.eval(c)));
+        let progenitors = Node::get_progenitors(&parents);
+
+        let inputs: Vec<T> = parents.iter().map(|p| p.value).collect();
+        let solution = self.problem.solve(&inputs);
+        Node {
+            index: c.get_index(),
+            value: solution.y1[self.i],
+            parents: parents,
+            progenitors: progenitors,
+            _vjp: Box::new(AdjointVJP { problem: self.problem.clone(), solution: solution, i: self.i }),
+        }
+    }
+}
+
+/// Integrates `dy/dt = f(t, y, θ)` over `t_span` and returns the final state
+///
+/// `f` builds the right hand side from the time, the state and the
+/// parameters and must return one expression per component of the
+/// state. `t_span` may run backwards. Returns one expression for each
+/// component of `y(t1)`, which is NaN if the adaptive method cannot
+/// meet its tolerances.
+///
+/// # Panics
+///
+/// This function will panic if `f` does not return one expression for
+/// each component of `y0`, or if `method` is `Rk4` with no steps.
+pub fn solve<T, Y, P, F>(f: F, y0: &[Container<T, Y>], t_span: (T, T), params: &[Container<T, P>],
+                         method: Method<T>) -> Vec<Container<T, OdeSolve<T, Y, P>>>
+    where T: Float + 'static,
+          Y: Expression<T> + Clone,
+          P: Expression<T> + Clone,
+          F: FnOnce(Container<T, Variable>, &[Container<T, Variable>], &[Container<T, Variable>])
+                -> Vec<Box<dyn Expression<T>>>
+{
+    if let Method::Rk4 { steps } = method {
+        assert!(steps > 0, "Rk4 needs at least one step");
+    }
+
+    let mut context = Context::new();
+    let t = context.create_variable(T::zero());
+    let y: Vec<_> = y0.iter().map(|_| context.create_variable(T::zero())).collect();
+    let p: Vec<_> = params.iter().map(|_| context.create_variable(T::zero())).collect();
+    let exprs = f(t, &y, &p);
+    assert_eq!(exprs.len(), y0.len(), "The right hand side must have one expression per component");
+
+    let problem = Rc::new(Problem {
+        rhs: RefCell::new(Rhs {
+            context: context,
+            t: *t.inner(),
+            y: y.iter().map(|v| *v.inner()).collect(),
+            params: p.iter().map(|v| *v.inner()).collect(),
+            exprs: exprs,
+        }),
+        t_span: t_span,
+        method: method,
+        last: RefCell::new(None),
+    });
+    let y0: Vec<Y> = y0.iter().map(|y| y.inner().clone()).collect();
+    let params: Vec<P> = params.iter().map(|p| p.inner().clone()).collect();
+    (0..y0.len()).map(|i| {
+        Container::new(OdeSolve { problem: problem.clone(), y0: y0.clone(), params: params.clone(), i: i })
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::{Gradient, LeafVar};
+    use ::check::GradCheck;
+    use ::functions::*;
+
+    #[test]
+    fn test_decay() {
+        for &(method, tol) in &[(Method::Rk4 { steps: 100 }, 1e-8), (Method::adaptive(), 1e-9)] {
+            let mut c = Context::new();
+            let y0 = c.create_variable(2.0);
+            let k = c.create_variable(0.7);
+            let y = solve(|_, y, p| vec![Box::new(-(p[0] * y[0])) as Box<dyn Expression<f64>>],
+                          &[y0], (0.0, 3.0), &[k], method);
+
+            let mut g = Gradient::of(y[0].clone(), c);
+            let decay = f64::exp(-2.1);
+            assert!((g.value() - 2.0 * decay).abs() < tol);
+            assert!((g.grad(&y0) - decay).abs() < tol);
+            assert!((g.grad(&k) + 3.0 * 2.0 * decay).abs() < tol);
+        }
+    }
+
+    #[test]
+    fn test_time_dependent_and_backwards() {
+        // dy/dt = a t has y(t1) = y0 + a (t1^2 - t0^2) / 2
+        let mut c = Context::new();
+        let a = c.create_variable(1.5);
+        let y0 = Container::new(LeafVar(1.0));
+        let y = solve(|t, _, p| vec![Box::new(p[0] * t) as Box<dyn Expression<f64>>],
+                      &[y0], (2.0, 0.5), &[a], Method::Rk4 { steps: 10 });
+
+        let mut g = Gradient::of(y[0].clone(), c);
+        assert!((g.value() - (1.0 - 1.5 * 1.875)).abs() < 1e-12);
+        assert!((g.grad(&a) + 1.875).abs() < 1e-12);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_no_steps() {
+        let mut c = Context::new();
+        let a = c.create_variable(1.5);
+        let y0 = Container::new(LeafVar(1.0));
+        solve(|_, y, p| vec![Box::new(p[0] * y[0]) as Box<dyn Expression<f64>>],
+              &[y0], (0.0, 1.0), &[a], Method::Rk4 { steps: 0 });
+    }
+
+    #[test]
+    fn test_lotka_volterra() {
+        let mut c = Context::new();
+        let prey = c.create_variable(1.0);
+        let predators = c.create_variable(0.5);
+        let params: Vec<_> = [1.1, 0.4, 0.4, 0.1].iter().map(|&v| c.create_variable(v)).collect();
+        let y = solve(|_, y, p| vec![
+            Box::new(p[0] * y[0] - p[1] * y[0] * y[1]) as Box<dyn Expression<f64>>,
+            Box::new(p[3] * y[0] * y[1] - p[2] * y[1]),
+        ], &[prey, predators], (0.0, 2.0), &params, Method::Rk4 { steps: 100 });
+
+        // A loss depending on both components
+        let loss = y[0].clone() * y[1].clone() + ln(y[0].clone());
+        let mut g = Gradient::of(loss, c);
+        let report = GradCheck::new().tolerances(1e-6, 1e-8).check(&mut g);
+        assert!(report.passed(), "{}", report);
+
+        // The adaptive method agrees with the fixed step method
+        let fixed: Vec<f64> = params.iter().map(|p| g.grad(p)).collect();
+        let mut c = Context::new();
+        let prey = c.create_variable(1.0);
+        let predators = c.create_variable(0.5);
+        let params: Vec<_> = [1.1, 0.4, 0.4, 0.1].iter().map(|&v| c.create_variable(v)).collect();
+        let y = solve(|_, y, p| vec![
+            Box::new(p[0] * y[0] - p[1] * y[0] * y[1]) as Box<dyn Expression<f64>>,
+            Box::new(p[3] * y[0] * y[1] - p[2] * y[1]),
+        ], &[prey, predators], (0.0, 2.0), &params, Method::adaptive());
+        let mut g = Gradient::of(y[0].clone() * y[1].clone() + ln(y[0].clone()), c);
+        for (p, expected) in params.iter().zip(fixed) {
+            assert!((g.grad(p) - expected).abs() < 1e-6);
+        }
+    }
+}
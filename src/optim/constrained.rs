@@ -0,0 +1,621 @@
+//! Constrained minimisation
+//!
+//! `ProjectedGradient` minimises over box `Bounds` on the variables.
+//! `AugmentedLagrangian` handles general equality and inequality
+//! constraints, which are expressions over the same context as the
+//! objective, by minimising a sequence of penalised objectives whose
+//! gradients are back propagated through the objective and constraints
+//! together.
+
+use std::fmt;
+use std::mem;
+use std::rc::Rc;
+
+use num::Float;
+
+use ::{Container, Context, Expression, Gradient, Node, VecJacProduct, Variable};
+use super::{axpy, dot, max_abs, Iteration, Lbfgs, Minimum, Objective, Termination};
+
+/// Lower and upper bounds on variables
+#[derive(Clone, Debug)]
+pub struct Bounds<T> {
+    bounds: Vec<(Variable, T, T)>,
+}
+
+impl<T: Float> Bounds<T> {
+    /// Creates a set of bounds with every variable unbounded
+    pub fn new() -> Self {
+        Bounds { bounds: vec![] }
+    }
+
+    /// Bounds a variable to `[lower, upper]`, replacing any earlier bounds
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `lower` is greater than `upper` or
+    /// either is NaN.
+    pub fn bound(mut self, var: &Variable, lower: T, upper: T) -> Self {
+        assert!(lower <= upper, "The lower bound must not exceed the upper bound");
+        self.bounds.retain(|b| b.0 != *var);
+        self.bounds.push((*var, lower, upper));
+        self
+    }
+
+    /// Bounds a variable from below
+    pub fn lower(self, var: &Variable, lower: T) -> Self {
+        let upper = self.get(var).1;
+        self.bound(var, lower, upper)
+    }
+
+    /// Bounds a variable from above
+    pub fn upper(self, var: &Variable, upper: T) -> Self {
+        let lower = self.get(var).0;
+        self.bound(var, lower, upper)
+    }
+
+    /// Returns the lower and upper bounds of a variable, which are
+    /// infinite if it is unbounded
+    pub fn get(&self, var: &Variable) -> (T, T) {
+        match self.bounds.iter().find(|b| b.0 == *var) {
+            Some(&(_, lower, upper)) => (lower, upper),
+            None => (T::neg_infinity(), T::infinity()),
+        }
+    }
+
+    /// Clamps a value of a variable to its bounds
+    pub fn clamp(&self, var: &Variable, value: T) -> T {
+        let (lower, upper) = self.get(var);
+        value.max(lower).min(upper)
+    }
+
+    /// Clamps the bounded variables of a gradient's context in place
+    ///
+    /// Calling this after each `Optimizer::step` gives projected versions
+    /// of the first order optimisers.
+    pub fn project<E: Expression<T>>(&self, grad: &mut Gradient<T, E>) {
+        for &(var, lower, upper) in &self.bounds {
+            let value = grad.get_mut(&var);
+            *value = value.max(lower).min(upper);
+        }
+    }
+}
+
+impl<T: Float> Default for Bounds<T> {
+    fn default() -> Self {
+        Bounds::new()
+    }
+}
+
+/// The spectral projected gradient minimiser for box constraints
+///
+/// Each iteration projects a gradient step onto the bounds, with the
+/// step length chosen from the last change in the gradient, and
+/// backtracks along the projected direction until the objective
+/// decreases sufficiently. It stops when the projected gradient is small.
+///
+/// # Example
+///
+/// ```
+/// use rugrads::{Context, Gradient};
+/// use rugrads::optim::{Bounds, ProjectedGradient};
+///
+/// let mut context = Context::new();
+/// let x = context.create_variable(0.5f64);
+/// let y = context.create_variable(0.5);
+/// let two = context.create_variable(2.0);
+/// let mut grad = Gradient::of((x - two) * (x - two) + (y + two) * (y + two), context);
+///
+/// let bounds = Bounds::new().bound(&x, 0.0, 1.0).lower(&y, 0.0);
+/// let min = ProjectedGradient::new(bounds).minimize(&mut grad, &[*x, *y]);
+/// assert!(min.converged());
+/// assert!((min.x[0] - 1.0).abs() < 1e-12 && min.x[1].abs() < 1e-12);
+/// ```
+#[derive(Clone, Debug)]
+pub struct ProjectedGradient<T> {
+    bounds: Bounds<T>,
+    max_iter: usize,
+    gtol: T,
+    ftol: T,
+}
+
+impl<T: Float> ProjectedGradient<T> {
+    /// Creates a minimiser over the given bounds
+    ///
+    /// The defaults stop when the largest component of the projected
+    /// gradient is at most `1e-8` or the relative decrease is at most
+    /// machine epsilon, and take at most 1000 iterations.
+    pub fn new(bounds: Bounds<T>) -> Self {
+        ProjectedGradient {
+            bounds: bounds,
+            max_iter: 1000,
+            gtol: T::from(1e-8).unwrap(),
+            ftol: T::epsilon(),
+        }
+    }
+
+    /// Sets the maximum number of iterations
+    pub fn max_iter(mut self, max_iter: usize) -> Self {
+        self.max_iter = max_iter;
+        self
+    }
+
+    /// Sets the tolerance on the projected gradient
+    pub fn gtol(mut self, gtol: T) -> Self {
+        self.gtol = gtol;
+        self
+    }
+
+    /// Sets the tolerance on the relative decrease of the objective
+    pub fn ftol(mut self, ftol: T) -> Self {
+        self.ftol = ftol;
+        self
+    }
+
+    /// Minimises the expression over `params`, starting from their current
+    /// values clamped to the bounds
+    pub fn minimize<E: Expression<T>>(&self, grad: &mut Gradient<T, E>, params: &[Variable]) -> Minimum<T> {
+        let two = T::one() + T::one();
+        let (min_alpha, max_alpha) = (T::from(1e-10).unwrap(), T::from(1e10).unwrap());
+        let c1 = T::from(1e-4).unwrap();
+        let project = |x: &[T]| -> Vec<T> {
+            params.iter().zip(x).map(|(p, &v)| self.bounds.clamp(p, v)).collect()
+        };
+
+        let mut obj = Objective::new(grad, params);
+        let mut x = project(&obj.get());
+        let (mut value, mut g) = obj.eval(&x);
+        let mut alpha = max_abs(&g).recip().max(min_alpha).min(T::one());
+        let mut history = vec![];
+
+        let termination = loop {
+            let pg: Vec<T> = project(&axpy(&x, -T::one(), &g)).iter().zip(&x).map(|(&a, &b)| a - b).collect();
+            if max_abs(&pg) <= self.gtol {
+                break Termination::GradientTolerance;
+            }
+            if history.len() == self.max_iter {
+                break Termination::MaxIterations;
+            }
+
+            let d: Vec<T> = project(&axpy(&x, -alpha, &g)).iter().zip(&x).map(|(&a, &b)| a - b).collect();
+            let slope = dot(&g, &d);
+            let mut t = T::one();
+            let mut accepted = None;
+            for _ in 0..30 {
+                let trial = axpy(&x, t, &d);
+                let (trial_value, trial_g) = obj.eval(&trial);
+                if trial_value <= value + c1 * t * slope {
+                    accepted = Some((trial, trial_value, trial_g));
+                    break;
+                }
+                t = t / two;
+            }
+            let (next, next_value, next_g) = match accepted {
+                Some(step) => step,
+                None => break Termination::LineSearchFailed,
+            };
+
+            // The Barzilai-Borwein step length for the next iteration
+            let s: Vec<T> = next.iter().zip(&x).map(|(&a, &b)| a - b).collect();
+            let y: Vec<T> = next_g.iter().zip(&g).map(|(&a, &b)| a - b).collect();
+            let sy = dot(&s, &y);
+            alpha = if sy > T::zero() { (dot(&s, &s) / sy).max(min_alpha).min(max_alpha) } else { max_alpha };
+
+            let decrease = value - next_value;
+            x = next;
+            value = next_value;
+            g = next_g;
+            history.push(Iteration {
+                value: value,
+                grad_norm: max_abs(&g),
+                step: dot(&s, &s).sqrt(),
+                evaluations: obj.evaluations,
+            });
+            if decrease <= self.ftol * value.abs().max(decrease.abs()).max(T::one()) {
+                break Termination::ValueTolerance;
+            }
+        };
+
+        obj.set(&x);
+        Minimum {
+            x: x,
+            value: value,
+            history: history,
+            evaluations: obj.evaluations,
+            termination: termination,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    // c(x) = 0
+    Equality,
+    // c(x) >= 0
+    Inequality,
+}
+
+// The expression is shared so that the minimiser can be cloned
+#[derive(Clone)]
+struct Constraint<T> {
+    kind: Kind,
+    expr: Rc<dyn Expression<T>>,
+}
+
+impl<T> fmt::Debug for Constraint<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Constraint").field("kind", &self.kind).finish()
+    }
+}
+
+impl<T: Float> Constraint<T> {
+    // The penalty term of the augmented Lagrangian for the constraint
+    // value `c` and its slope with respect to `c`
+    fn penalty(&self, c: T, multiplier: T, mu: T) -> (T, T) {
+        let two = T::one() + T::one();
+        if self.kind == Kind::Inequality && multiplier - mu * c <= T::zero() {
+            // Inactive, so only the constant which keeps the penalty smooth
+            (-multiplier * multiplier / (two * mu), T::zero())
+        } else {
+            (-multiplier * c + mu * c * c / two, -multiplier + mu * c)
+        }
+    }
+
+    // The multiplier for the next outer iteration
+    fn update(&self, c: T, multiplier: T, mu: T) -> T {
+        match self.kind {
+            Kind::Equality => multiplier - mu * c,
+            Kind::Inequality => (multiplier - mu * c).max(T::zero()),
+        }
+    }
+
+    // How far the constraint is from being satisfied, including
+    // complementarity for inequalities
+    fn violation(&self, c: T, multiplier: T, mu: T) -> T {
+        match self.kind {
+            Kind::Equality => c.abs(),
+            Kind::Inequality => c.min(multiplier / mu).abs(),
+        }
+    }
+}
+
+struct LagrangianVJP<T> {
+    // The slope with respect to the objective and each constraint
+    slopes: Vec<T>,
+}
+
+impl<T: Float> VecJacProduct<T> for LagrangianVJP<T> {
+    fn vjp(&self, g: T, _: &Node<T>, _: &Node<T>, idx: usize) -> T {
+        g * self.slopes[idx]
+    }
+}
+
+// The augmented Lagrangian for fixed multipliers and penalty
+struct Lagrangian<'a, T: 'a, E: 'a> {
+    objective: &'a E,
+    constraints: &'a [Constraint<T>],
+    multipliers: Vec<T>,
+    mu: T,
+}
+
+impl<'a, T: Float, E: Expression<T>> Expression<T> for Lagrangian<'a, T, E> {
+    fn eval(&self, c: &mut Context<T>) -> Node<T> {
+        let f = self.objective.eval(c);
+        let mut value = f.value;
+        let mut slopes = vec![T::one()];
+        let mut parents = vec![f];
+        for (con, &multiplier) in self.constraints.iter().zip(&self.multipliers) {
+            let node = con.expr.eval(c);
+            let (penalty, slope) = con.penalty(node.value, multiplier, self.mu);
+            value = value + penalty;
+            slopes.push(slope);
+            parents.push(node);
+        }
+        let progenitors = Node::get_progenitors(&parents);
+
+        Node {
+            index: c.get_index(),
+            value: value,
+            parents: parents,
+            progenitors: progenitors,
+            _vjp: Box::new(LagrangianVJP { slopes: slopes }),
+        }
+    }
+}
+
+/// The result of a constrained minimisation
+#[derive(Clone, Debug)]
+pub struct ConstrainedMinimum<T> {
+    /// The parameters at the minimum
+    pub x: Vec<T>,
+    /// The objective at the minimum
+    pub value: T,
+    /// The Lagrange multipliers, in the order the constraints were added
+    ///
+    /// At the minimum the gradient of the objective is the sum of the
+    /// multipliers times the gradients of the constraints. Inequality
+    /// multipliers are non-negative and zero for inactive constraints.
+    pub multipliers: Vec<T>,
+    /// The largest constraint violation
+    pub violation: T,
+    /// The number of outer iterations taken
+    pub iterations: usize,
+    /// The number of objective evaluations made
+    pub evaluations: usize,
+    /// Why the minimiser stopped
+    pub termination: Termination,
+}
+
+impl<T> ConstrainedMinimum<T> {
+    /// Returns true if the constraints and the gradient tolerance were met
+    pub fn converged(&self) -> bool {
+        self.termination == Termination::GradientTolerance
+    }
+}
+
+/// The augmented Lagrangian minimiser for general constraints
+///
+/// Equality constraints `c(x) = 0` and inequality constraints `c(x) >= 0`
+/// are expressions over the objective's context. Each outer iteration
+/// minimises
+///
+/// `f(x) + sum -λ c(x) + μ c(x)^2 / 2`
+///
+/// with `Lbfgs`, or with `ProjectedGradient` if there are bounds, and
+/// then updates the multipliers `λ`. The penalty `μ` is multiplied by 10
+/// whenever the violation fails to fall by a factor of 4. Inequality
+/// terms use the smooth Powell-Hestenes-Rockafellar form, which vanishes
+/// for inactive constraints.
+///
+/// # Example
+///
+/// ```
+/// use rugrads::{Context, Gradient};
+/// use rugrads::optim::AugmentedLagrangian;
+///
+/// // Minimise x^2 + y^2 on the line x + y = 1
+/// let mut context = Context::new();
+/// let x = context.create_variable(0.0f64);
+/// let y = context.create_variable(0.0);
+/// let one = context.create_variable(1.0);
+/// let mut grad = Gradient::of(x * x + y * y, context);
+///
+/// let min = AugmentedLagrangian::new()
+///     .equality(x + y - one)
+///     .minimize(&mut grad, &[*x, *y]);
+/// assert!(min.converged());
+/// assert!((min.x[0] - 0.5).abs() < 1e-6 && (min.x[1] - 0.5).abs() < 1e-6);
+/// assert!((min.multipliers[0] - 1.0).abs() < 1e-6);
+/// ```
+#[derive(Clone, Debug)]
+pub struct AugmentedLagrangian<T> {
+    constraints: Vec<Constraint<T>>,
+    bounds: Option<Bounds<T>>,
+    max_iter: usize,
+    gtol: T,
+    ctol: T,
+    penalty: T,
+    max_penalty: T,
+}
+
+impl<T: Float> AugmentedLagrangian<T> {
+    /// Creates a minimiser with no constraints
+    ///
+    /// The defaults stop when the constraints are violated by at most
+    /// `1e-8` after an inner minimisation to a gradient of `1e-8`, and take
+    /// at most 50 outer iterations. The penalty starts at 10 and is at
+    /// most `1e12`.
+    pub fn new() -> Self {
+        AugmentedLagrangian {
+            constraints: vec![],
+            bounds: None,
+            max_iter: 50,
+            gtol: T::from(1e-8).unwrap(),
+            ctol: T::from(1e-8).unwrap(),
+            penalty: T::from(10.0).unwrap(),
+            max_penalty: T::from(1e12).unwrap(),
+        }
+    }
+
+    /// Adds the constraint `c(x) = 0`
+    pub fn equality<E: Expression<T> + 'static>(mut self, c: Container<T, E>) -> Self {
+        self.constraints.push(Constraint { kind: Kind::Equality, expr: Rc::new(c.inner) });
+        self
+    }
+
+    /// Adds the constraint `c(x) >= 0`
+    pub fn inequality<E: Expression<T> + 'static>(mut self, c: Container<T, E>) -> Self {
+        self.constraints.push(Constraint { kind: Kind::Inequality, expr: Rc::new(c.inner) });
+        self
+    }
+
+    /// Keeps the variables within bounds throughout
+    pub fn bounds(mut self, bounds: Bounds<T>) -> Self {
+        self.bounds = Some(bounds);
+        self
+    }
+
+    /// Sets the maximum number of outer iterations
+    pub fn max_iter(mut self, max_iter: usize) -> Self {
+        self.max_iter = max_iter;
+        self
+    }
+
+    /// Sets the gradient tolerance of each inner minimisation
+    pub fn gtol(mut self, gtol: T) -> Self {
+        self.gtol = gtol;
+        self
+    }
+
+    /// Sets the tolerance on the constraint violation
+    pub fn ctol(mut self, ctol: T) -> Self {
+        self.ctol = ctol;
+        self
+    }
+
+    /// Sets the initial and maximum penalty
+    pub fn penalty(mut self, penalty: T, max_penalty: T) -> Self {
+        self.penalty = penalty;
+        self.max_penalty = max_penalty;
+        self
+    }
+
+    // The constraint values at the context's current variable values
+    fn values(&self, context: &mut Context<T>) -> Vec<T> {
+        self.constraints.iter().map(|con| {
+            context.node_count = 0;
+            con.expr.eval(context).value
+        }).collect()
+    }
+
+    /// Minimises the expression over `params` subject to the constraints,
+    /// starting from their current values
+    pub fn minimize<E: Expression<T>>(&self, grad: &mut Gradient<T, E>, params: &[Variable]) -> ConstrainedMinimum<T> {
+        let mut multipliers = vec![T::zero(); self.constraints.len()];
+        let mut mu = self.penalty;
+        let mut last_violation = T::infinity();
+        let mut evaluations = 0;
+        let mut iterations = 0;
+
+        let termination = loop {
+            if iterations == self.max_iter {
+                break Termination::MaxIterations;
+            }
+            iterations += 1;
+
+            // Minimise the augmented Lagrangian in the objective's context
+            let context = mem::replace(&mut grad.context, Context::new());
+            let lagrangian = Lagrangian {
+                objective: &grad.expr,
+                constraints: &self.constraints,
+                multipliers: multipliers.clone(),
+                mu: mu,
+            };
+            let mut inner = Gradient::of(Container::new(lagrangian), context);
+            let min = match self.bounds {
+                Some(ref bounds) => ProjectedGradient::new(bounds.clone()).gtol(self.gtol).minimize(&mut inner, params),
+                None => Lbfgs::new().gtol(self.gtol).minimize(&mut inner, params),
+            };
+            grad.context = inner.context;
+            evaluations += min.evaluations;
+
+            let values = self.values(&mut grad.context);
+            let violation = self.constraints.iter().zip(&values).zip(&multipliers)
+                .fold(T::zero(), |v, ((con, &c), &m)| v.max(con.violation(c, m, mu)));
+            for ((m, con), &c) in multipliers.iter_mut().zip(&self.constraints).zip(&values) {
+                *m = con.update(c, *m, mu);
+            }
+            if violation <= self.ctol && min.converged() {
+                break Termination::GradientTolerance;
+            }
+            if violation > last_violation / T::from(4.0).unwrap() {
+                mu = (mu * T::from(10.0).unwrap()).min(self.max_penalty);
+            }
+            last_violation = violation;
+        };
+
+        let x = params.iter().map(|p| *grad.get(p)).collect();
+        let values = self.values(&mut grad.context);
+        let violation = self.constraints.iter().zip(&values)
+            .fold(T::zero(), |v, (con, &c)| match con.kind {
+                Kind::Equality => v.max(c.abs()),
+                Kind::Inequality => v.max(-c),
+            });
+        ConstrainedMinimum {
+            x: x,
+            value: grad.value(),
+            multipliers: multipliers,
+            violation: violation,
+            iterations: iterations,
+            evaluations: evaluations,
+            termination: termination,
+        }
+    }
+}
+
+impl<T: Float> Default for AugmentedLagrangian<T> {
+    fn default() -> Self {
+        AugmentedLagrangian::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::optim;
+
+    #[test]
+    fn test_projected_step() {
+        let mut c = Context::new();
+        let x = c.create_variable(0.5);
+        let y = c.create_variable(0.0);
+        let three = c.create_variable(3.0);
+        let mut g = Gradient::of(x * x - three * x + y * y, c);
+        let bounds = Bounds::new().upper(&x, 1.0).lower(&x, -1.0);
+        assert_eq!(bounds.get(&x), (-1.0, 1.0));
+        assert_eq!(bounds.get(&y), (f64::NEG_INFINITY, f64::INFINITY));
+
+        let mut sgd = optim::sgd(0.5);
+        for _ in 0..10 {
+            sgd.step(&mut g, &[*x, *y]);
+            bounds.project(&mut g);
+            assert!(*g.get(&x) <= 1.0);
+        }
+        assert_eq!(*g.get(&x), 1.0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_crossed_bounds() {
+        let mut c = Context::<f64>::new();
+        let x = c.create_variable(0.0);
+        Bounds::new().lower(&x, 1.0).upper(&x, 0.0);
+    }
+
+    #[test]
+    fn test_inequality() {
+        // The point on the unit disc closest to (2, 2)
+        let mut c = Context::new();
+        let x = c.create_variable(0.0);
+        let y = c.create_variable(0.0);
+        let one = c.create_variable(1.0);
+        let two = c.create_variable(2.0);
+        let five = c.create_variable(5.0);
+        let mut g = Gradient::of((x - two) * (x - two) + (y - two) * (y - two), c);
+
+        let solver = AugmentedLagrangian::new()
+            .inequality(one - x * x - y * y)
+            .inequality(x + five);
+        assert!(format!("{:?}", solver).contains("Inequality"));
+
+        let min = solver.clone().minimize(&mut g, &[*x, *y]);
+        assert!(min.converged(), "{:?}", min.termination);
+        let r = f64::sqrt(0.5);
+        assert!((min.x[0] - r).abs() < 1e-6 && (min.x[1] - r).abs() < 1e-6);
+        assert!((min.multipliers[0] - (2.0 * f64::sqrt(2.0) - 1.0)).abs() < 1e-5);
+        assert_eq!(min.multipliers[1], 0.0);
+        assert!(min.violation < 1e-8);
+        assert_eq!(*g.get(&x), min.x[0]);
+    }
+
+    #[test]
+    fn test_bounded_equality() {
+        // Minimise x + 2 y + z on x^2 + y^2 + z^2 = 1 with z in [0, 1]
+        let mut c = Context::new();
+        let x = c.create_variable(0.0);
+        let y = c.create_variable(0.0);
+        let z = c.create_variable(0.5);
+        let one = c.create_variable(1.0);
+        let two = c.create_variable(2.0);
+        let mut g = Gradient::of(x + two * y + z, c);
+
+        let min = AugmentedLagrangian::new()
+            .equality(x * x + y * y + z * z - one)
+            .bounds(Bounds::new().bound(&z, 0.0, 1.0))
+            .minimize(&mut g, &[*x, *y, *z]);
+        assert!(min.converged(), "{:?}", min.termination);
+        let r = f64::sqrt(5.0);
+        assert!((min.x[0] + 1.0 / r).abs() < 1e-6 && (min.x[1] + 2.0 / r).abs() < 1e-6);
+        assert!(min.x[2].abs() < 1e-12);
+        assert!((min.value + r).abs() < 1e-6);
+    }
+}
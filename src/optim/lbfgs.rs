@@ -0,0 +1,228 @@
+//! Limited memory BFGS
+
+use std::collections::VecDeque;
+
+use num::Float;
+
+use ::{Expression, Gradient, Variable};
+use super::{axpy, dot, max_abs, Iteration, Minimum, Objective, Termination};
+use super::line_search::Wolfe;
+
+/// The limited memory BFGS minimiser
+///
+/// This approximates the inverse Hessian from the last few steps and
+/// gradient changes, and takes steps which satisfy the strong Wolfe
+/// conditions.
+///
+/// # Example
+///
+/// ```
+/// use rugrads::{Context, Gradient};
+/// use rugrads::functions::*;
+/// use rugrads::optim::Lbfgs;
+///
+/// let mut context = Context::new();
+/// let x = context.create_variable(-1.2f64);
+/// let y = context.create_variable(1.0);
+/// let one = context.create_variable(1.0);
+/// let hundred = context.create_variable(100.0);
+/// let rosenbrock = (one - x) * (one - x) + hundred * (y - x * x) * (y - x * x);
+/// let mut grad = Gradient::of(rosenbrock, context);
+///
+/// let min = Lbfgs::new().minimize(&mut grad, &[*x, *y]);
+/// assert!(min.converged(), "{:?}", min.termination);
+/// assert!((min.x[0] - 1.0).abs() < 1e-6 && (min.x[1] - 1.0).abs() < 1e-6);
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct Lbfgs<T> {
+    memory: usize,
+    max_iter: usize,
+    gtol: T,
+    ftol: T,
+    wolfe: Wolfe<T>,
+}
+
+impl<T: Float> Lbfgs<T> {
+    /// Creates a minimiser
+    ///
+    /// The defaults keep 10 steps, stop when the largest gradient
+    /// component is at most `1e-8` or the relative decrease is at most
+    /// machine epsilon, and take at most 500 iterations. The line search
+    /// uses `c1 = 1e-4` and `c2 = 0.9`.
+    pub fn new() -> Self {
+        Lbfgs {
+            memory: 10,
+            max_iter: 500,
+            gtol: T::from(1e-8).unwrap(),
+            ftol: T::epsilon(),
+            wolfe: Wolfe::new(),
+        }
+    }
+
+    /// Sets the number of steps kept
+    pub fn memory(mut self, memory: usize) -> Self {
+        self.memory = memory;
+        self
+    }
+
+    /// Sets the maximum number of iterations
+    pub fn max_iter(mut self, max_iter: usize) -> Self {
+        self.max_iter = max_iter;
+        self
+    }
+
+    /// Sets the gradient tolerance
+    pub fn gtol(mut self, gtol: T) -> Self {
+        self.gtol = gtol;
+        self
+    }
+
+    /// Sets the tolerance on the relative decrease of the objective
+    pub fn ftol(mut self, ftol: T) -> Self {
+        self.ftol = ftol;
+        self
+    }
+
+    /// Sets the strong Wolfe constants, with `0 < c1 < c2 < 1`
+    pub fn wolfe(mut self, c1: T, c2: T) -> Self {
+        self.wolfe.c1 = c1;
+        self.wolfe.c2 = c2;
+        self
+    }
+
+    /// Minimises the expression over `params`, starting from their current values
+    pub fn minimize<E: Expression<T>>(&self, grad: &mut Gradient<T, E>, params: &[Variable]) -> Minimum<T> {
+        let mut obj = Objective::new(grad, params);
+        let mut x = obj.get();
+        let (mut value, mut g) = obj.eval(&x);
+        let mut history = vec![];
+        // Pairs of steps s and gradient changes y with 1 / (s . y)
+        let mut pairs: VecDeque<(Vec<T>, Vec<T>, T)> = VecDeque::new();
+
+        let termination = loop {
+            if max_abs(&g) <= self.gtol {
+                break Termination::GradientTolerance;
+            }
+            if history.len() == self.max_iter {
+                break Termination::MaxIterations;
+            }
+
+            // Two loop recursion for d = -H g
+            let mut q: Vec<T> = g.iter().map(|&v| -v).collect();
+            let mut alphas = Vec::with_capacity(pairs.len());
+            for &(ref s, ref y, rho) in pairs.iter().rev() {
+                let a = rho * dot(s, &q);
+                q = axpy(&q, -a, y);
+                alphas.push(a);
+            }
+            let gamma = match pairs.back() {
+                Some(&(_, ref y, rho)) => (rho * dot(y, y)).recip(),
+                None => T::one(),
+            };
+            let mut d: Vec<T> = q.into_iter().map(|v| gamma * v).collect();
+            for (&(ref s, ref y, rho), a) in pairs.iter().zip(alphas.into_iter().rev()) {
+                let b = rho * dot(y, &d);
+                d = axpy(&d, a - b, s);
+            }
+
+            // Without curvature information the first step has unit length
+            let alpha = if pairs.is_empty() { dot(&g, &g).sqrt().recip().min(T::one()) } else { T::one() };
+            let step = match self.wolfe.search(&mut obj, &x, value, &g, &d, alpha) {
+                Some(step) => step,
+                None if !pairs.is_empty() => {
+                    // Forget the curvature and try again along the gradient
+                    pairs.clear();
+                    continue;
+                }
+                None => break Termination::LineSearchFailed,
+            };
+
+            let next = axpy(&x, step.alpha, &d);
+            let s: Vec<T> = next.iter().zip(&x).map(|(&a, &b)| a - b).collect();
+            let y: Vec<T> = step.grad.iter().zip(&g).map(|(&a, &b)| a - b).collect();
+            let sy = dot(&s, &y);
+            if sy > T::epsilon() * dot(&y, &y) {
+                if pairs.len() == self.memory {
+                    pairs.pop_front();
+                }
+                pairs.push_back((s.clone(), y, sy.recip()));
+            }
+
+            let decrease = value - step.value;
+            x = next;
+            value = step.value;
+            g = step.grad;
+            history.push(Iteration {
+                value: value,
+                grad_norm: max_abs(&g),
+                step: dot(&s, &s).sqrt(),
+                evaluations: obj.evaluations,
+            });
+            if decrease <= self.ftol * value.abs().max(decrease.abs()).max(T::one()) {
+                break Termination::ValueTolerance;
+            }
+        };
+
+        obj.set(&x);
+        Minimum {
+            x: x,
+            value: value,
+            history: history,
+            evaluations: obj.evaluations,
+            termination: termination,
+        }
+    }
+}
+
+impl<T: Float> Default for Lbfgs<T> {
+    fn default() -> Self {
+        Lbfgs::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::{Container, Context, LeafVar};
+
+    #[test]
+    fn test_quadratic() {
+        // An ill conditioned quadratic in 8 variables
+        let mut c = Context::new();
+        let xs: Vec<_> = (0..8).map(|_| c.create_variable(1.0)).collect();
+        let mut f: Box<dyn Expression<f64>> = Box::new(Container::new(LeafVar(0.0)));
+        for (i, x) in xs.iter().enumerate() {
+            let scale = Container::new(LeafVar(3f64.powi(i as i32)));
+            let target = Container::new(LeafVar(i as f64));
+            f = Box::new(Container::new(f) + scale * (*x - target) * (*x - target));
+        }
+        let mut g = Gradient::of(Container::new(f), c);
+        let params: Vec<Variable> = xs.iter().map(|x| **x).collect();
+        let min = Lbfgs::new().minimize(&mut g, &params);
+
+        assert!(min.converged(), "{:?}", min.termination);
+        for (i, &x) in min.x.iter().enumerate() {
+            assert!((x - i as f64).abs() < 1e-6);
+            assert_eq!(*g.get(&params[i]), x);
+        }
+        for w in min.history.windows(2) {
+            assert!(w[1].value <= w[0].value);
+        }
+    }
+
+    #[test]
+    fn test_max_iter() {
+        let mut c = Context::new();
+        let x = c.create_variable(-1.2);
+        let y = c.create_variable(1.0);
+        let one = Container::new(LeafVar(1.0));
+        let hundred = Container::new(LeafVar(100.0));
+        let mut g = Gradient::of((one - x) * (one - x) + hundred * (y - x * x) * (y - x * x), c);
+
+        let min = Lbfgs::new().max_iter(3).minimize(&mut g, &[*x, *y]);
+        assert_eq!(min.termination, Termination::MaxIterations);
+        assert_eq!(min.iterations(), 3);
+        assert!(min.value < 24.2);
+        assert_eq!(min.evaluations, min.history[2].evaluations);
+    }
+}
@@ -0,0 +1,460 @@
+//! Nonlinear least squares
+
+use num::Float;
+
+use ::{accumulate_all, Container, Context, Expression, Variable};
+use ::linalg::Lu;
+use super::{dot, max_abs, Termination};
+
+/// The result of a least squares fit
+#[derive(Clone, Debug)]
+pub struct Fit<T> {
+    /// The fitted parameters
+    pub params: Vec<T>,
+    /// The residuals at the fitted parameters
+    pub residuals: Vec<T>,
+    /// Half the sum of squared residuals at the fitted parameters
+    pub cost: T,
+    /// The estimated covariance of the parameters
+    ///
+    /// This is `s^2 (J^T J)^-1` with the residual variance
+    /// `s^2 = 2 cost / (m - n)` for `m` residuals and `n` parameters. It is
+    /// `None` when there are no more residuals than parameters or `J^T J`
+    /// is singular.
+    pub covariance: Option<Vec<Vec<T>>>,
+    /// The cost after each iteration
+    pub history: Vec<T>,
+    /// The number of times the residuals were evaluated
+    pub evaluations: usize,
+    /// Why the solver stopped
+    pub termination: Termination,
+}
+
+impl<T: Float> Fit<T> {
+    /// Returns true if the solver stopped on one of its tolerances
+    pub fn converged(&self) -> bool {
+        matches!(self.termination,
+                 Termination::GradientTolerance | Termination::ValueTolerance | Termination::StepTolerance)
+    }
+
+    /// Returns the number of iterations taken
+    pub fn iterations(&self) -> usize {
+        self.history.len()
+    }
+
+    /// Returns the standard errors of the parameters, if the covariance is known
+    pub fn std_errors(&self) -> Option<Vec<T>> {
+        self.covariance.as_ref().map(|c| (0..c.len()).map(|i| c[i][i].sqrt()).collect())
+    }
+}
+
+/// The least squares solver
+///
+/// This minimises half the sum of squared residuals over the parameters.
+/// By default it takes Levenberg-Marquardt steps, solving
+/// `(J^T J + λ D) δ = -J^T r` where `D` is the largest diagonal of `J^T J`
+/// seen so far, and adapts the damping `λ` to how well the linear model
+/// predicted the decrease in cost. With `gauss_newton` it takes undamped
+/// steps, halving them until the cost decreases, and stops with
+/// `LineSearchFailed` if no halving does.
+///
+/// The Jacobian is found by back propagating each residual.
+///
+/// # Example
+///
+/// ```
+/// use rugrads::Context;
+/// use rugrads::functions::*;
+/// use rugrads::optim::LeastSquares;
+///
+/// // Fit y = a exp(b t) through points on 2 exp(-t / 2)
+/// let mut context = Context::new();
+/// let a = context.create_variable(1.0f64);
+/// let b = context.create_variable(0.0);
+/// let residuals: Vec<_> = (0..5).map(|i| {
+///     let t = context.create_variable(i as f64);
+///     let y = context.create_variable(2.0 * (-0.5 * i as f64).exp());
+///     a * exp(b * t) - y
+/// }).collect();
+///
+/// let fit = LeastSquares::new().fit(&residuals, &mut context, &[*a, *b]);
+/// assert!(fit.converged());
+/// assert!((fit.params[0] - 2.0).abs() < 1e-8 && (fit.params[1] + 0.5).abs() < 1e-8);
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct LeastSquares<T> {
+    max_iter: usize,
+    gtol: T,
+    ftol: T,
+    xtol: T,
+    damping: Option<T>,
+}
+
+impl<T: Float> LeastSquares<T> {
+    /// Creates a Levenberg-Marquardt solver
+    ///
+    /// The defaults start with a damping of `1e-3`, stop when the largest
+    /// component of `J^T r` is at most `1e-10`, the relative decrease in
+    /// cost is at most `1e-12` or the relative step is at most `1e-12`, and
+    /// take at most 200 iterations.
+    pub fn new() -> Self {
+        LeastSquares {
+            max_iter: 200,
+            gtol: T::from(1e-10).unwrap(),
+            ftol: T::from(1e-12).unwrap(),
+            xtol: T::from(1e-12).unwrap(),
+            damping: Some(T::from(1e-3).unwrap()),
+        }
+    }
+
+    /// Takes undamped Gauss-Newton steps
+    pub fn gauss_newton(mut self) -> Self {
+        self.damping = None;
+        self
+    }
+
+    /// Sets the initial Levenberg-Marquardt damping
+    pub fn damping(mut self, damping: T) -> Self {
+        self.damping = Some(damping);
+        self
+    }
+
+    /// Sets the maximum number of iterations
+    pub fn max_iter(mut self, max_iter: usize) -> Self {
+        self.max_iter = max_iter;
+        self
+    }
+
+    /// Sets the tolerance on the largest component of `J^T r`
+    pub fn gtol(mut self, gtol: T) -> Self {
+        self.gtol = gtol;
+        self
+    }
+
+    /// Sets the tolerance on the relative decrease in cost
+    pub fn ftol(mut self, ftol: T) -> Self {
+        self.ftol = ftol;
+        self
+    }
+
+    /// Sets the tolerance on the size of a step relative to the parameters
+    pub fn xtol(mut self, xtol: T) -> Self {
+        self.xtol = xtol;
+        self
+    }
+
+    /// Fits `params` to the residuals, starting from their current values
+    ///
+    /// The parameters are left at their fitted values in the context.
+    pub fn fit<E>(&self, residuals: &[Container<T, E>], context: &mut Context<T>, params: &[Variable]) -> Fit<T>
+        where E: Expression<T>
+    {
+        let two = T::one() + T::one();
+        let mut problem = Problem { residuals: residuals, context: context, params: params, evaluations: 0 };
+        let mut x: Vec<T> = params.iter().map(|p| problem.context.get_variable_value(p)).collect();
+        let mut r = problem.residuals_at(&x);
+        let mut cost = dot(&r, &r) / two;
+        let mut jac = problem.jacobian();
+        let mut lambda = self.damping;
+        let mut growth = two;
+        let mut scale = vec![T::zero(); x.len()];
+        let mut history = vec![];
+
+        let termination = loop {
+            let (jtj, g) = normal_equations(&jac, &r);
+            if max_abs(&g) <= self.gtol {
+                break Termination::GradientTolerance;
+            }
+            if history.len() == self.max_iter {
+                break Termination::MaxIterations;
+            }
+            for i in 0..scale.len() {
+                scale[i] = scale[i].max(jtj[i][i]).max(T::epsilon());
+            }
+
+            let rhs: Vec<T> = g.iter().map(|&v| -v).collect();
+            let x_norm = dot(&x, &x).sqrt();
+            let (next, step_norm) = match lambda {
+                Some(l) => {
+                    let mut a = jtj.clone();
+                    for i in 0..a.len() {
+                        a[i][i] = a[i][i] + l * scale[i];
+                    }
+                    let step = match Lu::new(a) {
+                        Some(lu) => lu.solve(&rhs),
+                        None => {
+                            lambda = Some(l * growth);
+                            growth = growth * two;
+                            history.push(cost);
+                            continue;
+                        }
+                    };
+                    let step_norm = dot(&step, &step).sqrt();
+                    let next: Vec<T> = x.iter().zip(&step).map(|(&x, &s)| x + s).collect();
+                    let next_r = problem.residuals_at(&next);
+                    let next_cost = dot(&next_r, &next_r) / two;
+
+                    // The decrease predicted by the linear model
+                    let predicted = step.iter().zip(&rhs).enumerate()
+                        .fold(T::zero(), |p, (i, (&s, &b))| p + s * (l * scale[i] * s + b)) / two;
+                    let rho = (cost - next_cost) / predicted;
+                    if rho > T::zero() {
+                        let t = two * rho - T::one();
+                        lambda = Some(l * (T::one() - t * t * t).max(T::from(1.0 / 3.0).unwrap()));
+                        growth = two;
+                        (Some((next, next_r, next_cost)), step_norm)
+                    } else {
+                        lambda = Some(l * growth);
+                        growth = growth * two;
+                        (None, step_norm)
+                    }
+                }
+                None => {
+                    let step = match Lu::new(jtj) {
+                        Some(lu) => lu.solve(&rhs),
+                        None => break Termination::Singular,
+                    };
+                    let mut t = T::one();
+                    let mut accepted = None;
+                    for _ in 0..30 {
+                        let next: Vec<T> = x.iter().zip(&step).map(|(&x, &s)| x + t * s).collect();
+                        let next_r = problem.residuals_at(&next);
+                        let next_cost = dot(&next_r, &next_r) / two;
+                        if next_cost < cost {
+                            accepted = Some((next, next_r, next_cost));
+                            break;
+                        }
+                        t = t / two;
+                    }
+                    if accepted.is_none() {
+                        history.push(cost);
+                        break Termination::LineSearchFailed;
+                    }
+                    (accepted, t * dot(&step, &step).sqrt())
+                }
+            };
+
+            let small_step = step_norm <= self.xtol * (x_norm + self.xtol);
+            match next {
+                Some((next, next_r, next_cost)) => {
+                    let decrease = cost - next_cost;
+                    x = next;
+                    r = next_r;
+                    cost = next_cost;
+                    jac = problem.jacobian();
+                    history.push(cost);
+                    if decrease <= self.ftol * cost {
+                        break Termination::ValueTolerance;
+                    }
+                }
+                None => history.push(cost),
+            }
+            if small_step {
+                break Termination::StepTolerance;
+            }
+        };
+
+        // Leave the parameters at the fit rather than the last trial
+        problem.set(&x);
+        let (m, n) = (r.len(), x.len());
+        let covariance = if m > n {
+            let variance = two * cost / T::from(m - n).unwrap();
+            Lu::new(normal_equations(&jac, &r).0).map(|lu| {
+                let columns: Vec<Vec<T>> = (0..n).map(|j| {
+                    let mut e = vec![T::zero(); n];
+                    e[j] = T::one();
+                    lu.solve(&e)
+                }).collect();
+                // Symmetrise away the rounding in the solves
+                (0..n).map(|i| {
+                    (0..n).map(|j| variance * (columns[j][i] + columns[i][j]) / two).collect()
+                }).collect()
+            })
+        } else {
+            None
+        };
+
+        Fit {
+            params: x,
+            residuals: r,
+            cost: cost,
+            covariance: covariance,
+            history: history,
+            evaluations: problem.evaluations,
+            termination: termination,
+        }
+    }
+}
+
+impl<T: Float> Default for LeastSquares<T> {
+    fn default() -> Self {
+        LeastSquares::new()
+    }
+}
+
+/// Fits `params` to the residuals with the default Levenberg-Marquardt solver
+///
+/// See `LeastSquares` for the settings.
+pub fn least_squares<T, E>(residuals: &[Container<T, E>], context: &mut Context<T>, params: &[Variable]) -> Fit<T>
+    where T: Float, E: Expression<T>
+{
+    LeastSquares::new().fit(residuals, context, params)
+}
+
+// The residuals with the context they are evaluated in
+struct Problem<'a, T: 'a, E: 'a + Expression<T>> {
+    residuals: &'a [Container<T, E>],
+    context: &'a mut Context<T>,
+    params: &'a [Variable],
+    evaluations: usize,
+}
+
+impl<'a, T: Float, E: Expression<T>> Problem<'a, T, E> {
+    fn set(&mut self, x: &[T]) {
+        for (p, &v) in self.params.iter().zip(x) {
+            self.context.set_variable_value(p, v);
+        }
+    }
+
+    fn residuals_at(&mut self, x: &[T]) -> Vec<T> {
+        self.set(x);
+        self.evaluations += 1;
+        let context = &mut *self.context;
+        self.residuals.iter().map(|r| {
+            context.node_count = 0;
+            r.eval(context).value
+        }).collect()
+    }
+
+    // The Jacobian at the current parameters, by rows
+    fn jacobian(&mut self) -> Vec<Vec<T>> {
+        let (params, context) = (self.params, &mut *self.context);
+        self.residuals.iter().map(|r| {
+            let (_, grads) = accumulate_all(r, context, params, |_| T::one());
+            grads.into_iter().map(|g| g.unwrap_or(T::zero())).collect()
+        }).collect()
+    }
+}
+
+// Returns J^T J and J^T r
+fn normal_equations<T: Float>(jac: &[Vec<T>], r: &[T]) -> (Vec<Vec<T>>, Vec<T>) {
+    let n = jac.first().map_or(0, |row| row.len());
+    let mut jtj = vec![vec![T::zero(); n]; n];
+    let mut g = vec![T::zero(); n];
+    for (row, &ri) in jac.iter().zip(r) {
+        for i in 0..n {
+            g[i] = g[i] + row[i] * ri;
+            for j in 0..n {
+                jtj[i][j] = jtj[i][j] + row[i] * row[j];
+            }
+        }
+    }
+    (jtj, g)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::functions::*;
+
+    type Residual = Container<f64, Box<dyn Expression<f64>>>;
+
+    // Points near y = 3 exp(-t / 2) + 1
+    fn decay(c: &mut Context<f64>) -> (Vec<Residual>, Vec<Variable>) {
+        let a = c.create_variable(1.0);
+        let b = c.create_variable(0.0);
+        let k = c.create_variable(-0.1);
+        let residuals = (0..20).map(|i| {
+            let t = i as f64 * 0.25;
+            let y = 3.0 * (-0.5 * t).exp() + 1.0 + 0.01 * (3.0 * t).sin();
+            let (t, y) = (c.create_variable(t), c.create_variable(y));
+            let r: Box<dyn Expression<f64>> = Box::new(a * exp(k * t) + b - y);
+            Container::new(r)
+        }).collect();
+        (residuals, vec![*a, *k, *b])
+    }
+
+    #[test]
+    fn test_exponential_decay() {
+        let mut c = Context::new();
+        let (residuals, params) = decay(&mut c);
+        let fit = least_squares(&residuals, &mut c, &params);
+
+        assert!(fit.converged(), "{:?}", fit.termination);
+        assert!((fit.params[0] - 3.0).abs() < 0.02);
+        assert!((fit.params[1] + 0.5).abs() < 0.01);
+        assert!((fit.params[2] - 1.0).abs() < 0.02);
+        for (p, &v) in params.iter().zip(&fit.params) {
+            assert_eq!(c.get_variable_value(p), v);
+        }
+        for w in fit.history.windows(2) {
+            assert!(w[1] <= w[0]);
+        }
+
+        // Gauss-Newton reaches the same fit
+        let mut c = Context::new();
+        let (residuals, params) = decay(&mut c);
+        let gn = LeastSquares::new().gauss_newton().fit(&residuals, &mut c, &params);
+        assert!(gn.converged(), "{:?}", gn.termination);
+        for (a, b) in fit.params.iter().zip(&gn.params) {
+            assert!((a - b).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_linear_covariance() {
+        // For a straight line the covariance is s^2 (X^T X)^-1 exactly
+        let ts: Vec<f64> = (0..10).map(|i| i as f64).collect();
+        let ys: Vec<f64> = ts.iter().map(|t| 2.0 * t - 1.0 + (1.7 * t).sin()).collect();
+
+        let mut c = Context::new();
+        let slope = c.create_variable(0.0);
+        let intercept = c.create_variable(0.0);
+        let residuals: Vec<_> = ts.iter().zip(&ys).map(|(&t, &y)| {
+            let (t, y) = (c.create_variable(t), c.create_variable(y));
+            slope * t + intercept - y
+        }).collect();
+        let fit = least_squares(&residuals, &mut c, &[*slope, *intercept]);
+        assert!(fit.converged(), "{:?}", fit.termination);
+
+        let n = ts.len() as f64;
+        let (st, stt) = (ts.iter().sum::<f64>(), ts.iter().map(|t| t * t).sum::<f64>());
+        let (sy, sty) = (ys.iter().sum::<f64>(), ts.iter().zip(&ys).map(|(t, y)| t * y).sum::<f64>());
+        let det = n * stt - st * st;
+        let (m, b) = ((n * sty - st * sy) / det, (stt * sy - st * sty) / det);
+        assert!((fit.params[0] - m).abs() < 1e-8 && (fit.params[1] - b).abs() < 1e-8);
+
+        let s2 = fit.residuals.iter().map(|r| r * r).sum::<f64>() / (n - 2.0);
+        let cov = fit.covariance.as_ref().unwrap();
+        assert!((cov[0][0] - s2 * n / det).abs() < 1e-10);
+        assert!((cov[1][1] - s2 * stt / det).abs() < 1e-10);
+        assert!((cov[0][1] + s2 * st / det).abs() < 1e-10);
+        assert_eq!(cov[0][1], cov[1][0]);
+        assert!((fit.std_errors().unwrap()[0] - (s2 * n / det).sqrt()).abs() < 1e-10);
+    }
+
+    #[test]
+    fn test_underdetermined() {
+        let mut c = Context::new();
+        let x = c.create_variable(3.0);
+        let y = c.create_variable(1.0);
+        let one = c.create_variable(1.0);
+        let fit = least_squares(&[x * y - one], &mut c, &[*x, *y]);
+        assert!(fit.converged(), "{:?}", fit.termination);
+        assert!(fit.cost < 1e-20);
+        assert!(fit.covariance.is_none());
+    }
+
+    #[test]
+    fn test_gauss_newton_line_search_failure() {
+        // Every Gauss-Newton step from near the kink of |x| + 1 overshoots
+        let mut c = Context::new();
+        let x = c.create_variable(1e-12);
+        let one = c.create_variable(1.0);
+        let fit = LeastSquares::new().gauss_newton().fit(&[sqrt(x * x) + one], &mut c, &[*x]);
+        assert_eq!(fit.termination, Termination::LineSearchFailed);
+        assert!(!fit.converged());
+        assert_eq!(fit.params, vec![1e-12]);
+        assert_eq!(fit.iterations(), 1);
+    }
+}
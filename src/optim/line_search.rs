@@ -0,0 +1,140 @@
+//! Strong Wolfe line search
+//!
+//! Algorithms 3.5 and 3.6 of Nocedal and Wright, Numerical Optimization,
+//! with cubic interpolation in the zoom phase.
+
+use num::Float;
+
+use ::Expression;
+use super::{axpy, dot, Objective};
+
+/// The line search parameters
+#[derive(Clone, Copy, Debug)]
+pub struct Wolfe<T> {
+    /// The sufficient decrease constant
+    pub c1: T,
+    /// The curvature constant
+    pub c2: T,
+    /// The most evaluations made in one search
+    pub max_evals: usize,
+}
+
+// A trial point on the line
+#[derive(Clone)]
+struct Trial<T> {
+    alpha: T,
+    value: T,
+    slope: T,
+    grad: Vec<T>,
+}
+
+/// An accepted step
+pub struct Step<T> {
+    pub alpha: T,
+    pub value: T,
+    pub grad: Vec<T>,
+}
+
+// The minimiser of the cubic through two trials, or the midpoint if it
+// is not safely inside the interval
+fn interpolate<T: Float>(lo: &Trial<T>, hi: &Trial<T>) -> T {
+    let three = T::from(3.0).unwrap();
+    let (a, b) = (lo.alpha, hi.alpha);
+    let d1 = lo.slope + hi.slope - three * (lo.value - hi.value) / (a - b);
+    let disc = d1 * d1 - lo.slope * hi.slope;
+    let mid = (a + b) / (T::one() + T::one());
+    if disc < T::zero() {
+        return mid;
+    }
+    let d2 = (b - a).signum() * disc.sqrt();
+    let alpha = b - (b - a) * (hi.slope + d2 - d1) / (hi.slope - lo.slope + d2 + d2);
+
+    // Keep away from the ends so the interval shrinks
+    let margin = T::from(0.1).unwrap() * (b - a).abs();
+    if alpha.is_finite() && alpha > a.min(b) + margin && alpha < a.max(b) - margin {
+        alpha
+    } else {
+        mid
+    }
+}
+
+impl<T: Float> Wolfe<T> {
+    /// The usual constants for quasi-Newton methods, `c1 = 1e-4` and `c2 = 0.9`
+    pub fn new() -> Self {
+        Wolfe { c1: T::from(1e-4).unwrap(), c2: T::from(0.9).unwrap(), max_evals: 30 }
+    }
+
+    /// Searches along `d` from `x` for a step satisfying the strong Wolfe
+    /// conditions, starting from the step `alpha`
+    ///
+    /// Returns `None` if `d` is not a descent direction or no step is found.
+    pub fn search<E>(&self, obj: &mut Objective<T, E>, x: &[T], value: T, grad: &[T], d: &[T], alpha: T)
+        -> Option<Step<T>>
+        where E: Expression<T>
+    {
+        let slope = dot(grad, d);
+        if slope.is_nan() || slope >= T::zero() {
+            return None;
+        }
+        let start = Trial { alpha: T::zero(), value: value, slope: slope, grad: grad.to_vec() };
+
+        let trial_at = |obj: &mut Objective<T, E>, alpha: T| {
+            let (value, grad) = obj.eval(&axpy(x, alpha, d));
+            Trial { alpha: alpha, value: value, slope: dot(&grad, d), grad: grad }
+        };
+        let sufficient = |t: &Trial<T>| t.value <= value + self.c1 * t.alpha * slope;
+        let curvature = |t: &Trial<T>| t.slope.abs() <= -self.c2 * slope;
+
+        // Bracket a step
+        let mut prev = start.clone();
+        let mut alpha = alpha;
+        let mut evals = 0;
+        let (mut lo, mut hi) = loop {
+            if evals == self.max_evals {
+                return None;
+            }
+            let trial = trial_at(obj, alpha);
+            evals += 1;
+            if !trial.value.is_finite() {
+                // Stepped out of the domain, so come back
+                alpha = (prev.alpha + alpha) / (T::one() + T::one());
+                continue;
+            }
+            if !sufficient(&trial) || (evals > 1 && trial.value >= prev.value) {
+                break (prev, trial);
+            }
+            if curvature(&trial) {
+                return Some(Step { alpha: trial.alpha, value: trial.value, grad: trial.grad });
+            }
+            if trial.slope >= T::zero() {
+                break (trial, prev);
+            }
+            alpha = alpha + alpha;
+            prev = trial;
+        };
+
+        // Zoom in on a step between lo and hi
+        while evals < self.max_evals {
+            let trial = trial_at(obj, interpolate(&lo, &hi));
+            evals += 1;
+            if !sufficient(&trial) || trial.value >= lo.value || !trial.value.is_finite() {
+                hi = trial;
+            } else {
+                if curvature(&trial) {
+                    return Some(Step { alpha: trial.alpha, value: trial.value, grad: trial.grad });
+                }
+                if trial.slope * (hi.alpha - lo.alpha) >= T::zero() {
+                    hi = lo;
+                }
+                lo = trial;
+            }
+        }
+
+        // Settle for sufficient decrease
+        if lo.alpha > T::zero() {
+            Some(Step { alpha: lo.alpha, value: lo.value, grad: lo.grad })
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,609 @@
+//! Optimisation module
+//!
+//! First order optimisers which update the variables of a `Gradient`
+//! in place. An `Optimizer` combines an update `Rule` with a learning
+//! rate, an optional weight decay and a learning rate `Schedule`. Each
+//! call to `step` computes the gradient for every parameter and then
+//! updates them all.
+//!
+//! The state of a rule, such as the running averages of Adam, is kept
+//! for each `Variable` and can be inspected with `state` and cleared
+//! with `reset`.
+//!
+//! For smooth deterministic objectives the `Lbfgs` minimiser runs to
+//! convergence on its own and reports a `Minimum`. The `NewtonCg` and
+//! `TrustRegion` minimisers also use curvature, through products of the
+//! Hessian with a direction found from the gradient along the direction.
+//!
+//! Fitting residual expressions is handled by `least_squares`, which takes
+//! Levenberg-Marquardt or Gauss-Newton steps and reports a `Fit`.
+//!
+//! Box `Bounds` are handled by `ProjectedGradient`, or by `Bounds::project`
+//! after each `step`. General equality and inequality constraints are
+//! expressions given to `AugmentedLagrangian`.
+//!
+//! # Example
+//!
+//! ```
+//! use rugrads::{Context, Gradient};
+//! use rugrads::functions::*;
+//! use rugrads::optim;
+//!
+//! let mut context = Context::new();
+//! let x = context.create_variable(3.0);
+//! let y = context.create_variable(-2.0);
+//! let mut grad = Gradient::of(x * x + (y - x) * (y - x), context);
+//!
+//! let mut adam = optim::adam(0.1);
+//! for _ in 0..500 {
+//!     adam.step(&mut grad, &[*x, *y]);
+//! }
+//! assert!(grad.value() < 1e-6);
+//! ```
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+
+use num::Float;
+
+use ::{accumulate_all, Expression, Gradient, Variable};
+
+mod constrained;
+mod lbfgs;
+mod least_squares;
+mod line_search;
+mod newton;
+
+pub use self::constrained::{AugmentedLagrangian, Bounds, ConstrainedMinimum, ProjectedGradient};
+pub use self::lbfgs::Lbfgs;
+pub use self::least_squares::{least_squares, Fit, LeastSquares};
+pub use self::newton::{NewtonCg, TrustRegion};
+
+/// An update rule for a single parameter
+pub trait Rule<T> {
+    /// The state kept for each parameter
+    type State: Clone + Debug;
+
+    /// The state of a parameter before its first update
+    fn init(&self) -> Self::State;
+
+    /// Returns the amount to subtract from a parameter with gradient `grad`
+    fn update(&self, state: &mut Self::State, grad: T, lr: T) -> T;
+}
+
+/// Gradient descent
+#[derive(Clone, Copy, Debug)]
+pub struct Sgd;
+
+impl<T: Float> Rule<T> for Sgd {
+    type State = ();
+
+    fn init(&self) {}
+
+    fn update(&self, _: &mut (), grad: T, lr: T) -> T {
+        lr * grad
+    }
+}
+
+/// Gradient descent with momentum
+///
+/// The state is the velocity `v = beta v + grad`. The update is `lr v`,
+/// or `lr (grad + beta v)` with Nesterov momentum.
+#[derive(Clone, Copy, Debug)]
+pub struct Momentum<T> {
+    /// The momentum coefficient
+    pub beta: T,
+    /// Whether to use Nesterov momentum
+    pub nesterov: bool,
+}
+
+impl<T: Float + Debug> Rule<T> for Momentum<T> {
+    type State = T;
+
+    fn init(&self) -> T {
+        T::zero()
+    }
+
+    fn update(&self, velocity: &mut T, grad: T, lr: T) -> T {
+        *velocity = self.beta * *velocity + grad;
+        if self.nesterov {
+            lr * (grad + self.beta * *velocity)
+        } else {
+            lr * *velocity
+        }
+    }
+}
+
+/// Adagrad
+///
+/// The state is the sum of the squared gradients `s` and the update is
+/// `lr grad / (sqrt(s) + eps)`.
+#[derive(Clone, Copy, Debug)]
+pub struct Adagrad<T> {
+    /// Added to the denominator to avoid dividing by zero
+    pub eps: T,
+}
+
+impl<T: Float + Debug> Rule<T> for Adagrad<T> {
+    type State = T;
+
+    fn init(&self) -> T {
+        T::zero()
+    }
+
+    fn update(&self, sum_sq: &mut T, grad: T, lr: T) -> T {
+        *sum_sq = *sum_sq + grad * grad;
+        lr * grad / (sum_sq.sqrt() + self.eps)
+    }
+}
+
+/// RMSProp
+///
+/// The state is a moving average of the squared gradients
+/// `s = decay s + (1 - decay) grad^2` and the update is
+/// `lr grad / (sqrt(s) + eps)`.
+#[derive(Clone, Copy, Debug)]
+pub struct RmsProp<T> {
+    /// The decay of the moving average
+    pub decay: T,
+    /// Added to the denominator to avoid dividing by zero
+    pub eps: T,
+}
+
+impl<T: Float + Debug> Rule<T> for RmsProp<T> {
+    type State = T;
+
+    fn init(&self) -> T {
+        T::zero()
+    }
+
+    fn update(&self, mean_sq: &mut T, grad: T, lr: T) -> T {
+        *mean_sq = self.decay * *mean_sq + (T::one() - self.decay) * grad * grad;
+        lr * grad / (mean_sq.sqrt() + self.eps)
+    }
+}
+
+/// Adam
+#[derive(Clone, Copy, Debug)]
+pub struct Adam<T> {
+    /// The decay of the moving average of the gradient
+    pub beta1: T,
+    /// The decay of the moving average of the squared gradient
+    pub beta2: T,
+    /// Added to the denominator to avoid dividing by zero
+    pub eps: T,
+}
+
+/// The state of one parameter under `Adam`
+#[derive(Clone, Copy, Debug)]
+pub struct AdamState<T> {
+    /// The moving average of the gradient
+    pub m: T,
+    /// The moving average of the squared gradient
+    pub v: T,
+    /// The number of updates made
+    pub t: i32,
+}
+
+impl<T: Float + Debug> Rule<T> for Adam<T> {
+    type State = AdamState<T>;
+
+    fn init(&self) -> AdamState<T> {
+        AdamState { m: T::zero(), v: T::zero(), t: 0 }
+    }
+
+    fn update(&self, state: &mut AdamState<T>, grad: T, lr: T) -> T {
+        state.t += 1;
+        state.m = self.beta1 * state.m + (T::one() - self.beta1) * grad;
+        state.v = self.beta2 * state.v + (T::one() - self.beta2) * grad * grad;
+        let m_hat = state.m / (T::one() - self.beta1.powi(state.t));
+        let v_hat = state.v / (T::one() - self.beta2.powi(state.t));
+        lr * m_hat / (v_hat.sqrt() + self.eps)
+    }
+}
+
+/// A learning rate schedule
+///
+/// Each schedule scales the initial learning rate according to the
+/// number of steps taken so far.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Schedule<T> {
+    /// A constant learning rate
+    Constant,
+    /// Multiplies the learning rate by `factor` every `every` steps
+    Step {
+        /// The number of steps between reductions
+        every: usize,
+        /// The factor applied at each reduction
+        factor: T,
+    },
+    /// Multiplies the learning rate by `gamma` every step
+    Exponential {
+        /// The factor applied each step
+        gamma: T,
+    },
+    /// Divides the learning rate by `1 + decay t` at step `t`
+    InverseTime {
+        /// The rate of decay
+        decay: T,
+    },
+    /// Anneals the learning rate to `min_lr` over `steps` steps along a
+    /// half cosine, then holds it there
+    Cosine {
+        /// The length of the schedule
+        steps: usize,
+        /// The final learning rate
+        min_lr: T,
+    },
+}
+
+impl<T: Float> Schedule<T> {
+    /// Returns the learning rate at step `t`, counting from zero
+    pub fn rate(&self, lr: T, t: usize) -> T {
+        let t_f = T::from(t).unwrap();
+        match *self {
+            Schedule::Constant => lr,
+            Schedule::Step { every, factor } => lr * factor.powi((t / every.max(1)) as i32),
+            Schedule::Exponential { gamma } => lr * gamma.powi(t as i32),
+            Schedule::InverseTime { decay } => lr / (T::one() + decay * t_f),
+            Schedule::Cosine { steps, min_lr } => {
+                let progress = T::from(t.min(steps)).unwrap() / T::from(steps.max(1)).unwrap();
+                let pi = T::from(::std::f64::consts::PI).unwrap();
+                let half = T::from(0.5).unwrap();
+                min_lr + (lr - min_lr) * half * (T::one() + (pi * progress).cos())
+            }
+        }
+    }
+}
+
+/// A first order optimiser
+///
+/// Weight decay adds `weight_decay * x` to the gradient of each
+/// parameter before the rule is applied, which is L2 regularisation.
+/// Decoupled weight decay instead subtracts `lr * weight_decay * x`
+/// after the update, as AdamW does.
+#[derive(Clone, Debug)]
+pub struct Optimizer<T, R: Rule<T>> {
+    rule: R,
+    lr: T,
+    weight_decay: T,
+    decoupled: bool,
+    schedule: Schedule<T>,
+    steps: usize,
+    state: HashMap<Variable, R::State>,
+}
+
+impl<T: Float, R: Rule<T>> Optimizer<T, R> {
+    /// Creates an optimiser with the given rule and initial learning rate
+    pub fn new(rule: R, lr: T) -> Self {
+        Optimizer {
+            rule: rule,
+            lr: lr,
+            weight_decay: T::zero(),
+            decoupled: false,
+            schedule: Schedule::Constant,
+            steps: 0,
+            state: HashMap::new(),
+        }
+    }
+
+    /// Sets the L2 weight decay
+    pub fn weight_decay(mut self, weight_decay: T) -> Self {
+        self.weight_decay = weight_decay;
+        self.decoupled = false;
+        self
+    }
+
+    /// Sets the decoupled weight decay
+    pub fn decoupled_weight_decay(mut self, weight_decay: T) -> Self {
+        self.weight_decay = weight_decay;
+        self.decoupled = true;
+        self
+    }
+
+    /// Sets the learning rate schedule
+    pub fn schedule(mut self, schedule: Schedule<T>) -> Self {
+        self.schedule = schedule;
+        self
+    }
+
+    /// Returns the update rule
+    pub fn rule(&self) -> &R {
+        &self.rule
+    }
+
+    /// Returns the learning rate which the next step will use
+    pub fn learning_rate(&self) -> T {
+        self.schedule.rate(self.lr, self.steps)
+    }
+
+    /// Returns the number of steps taken
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    /// Returns the state of a parameter, or `None` if it has not been updated
+    pub fn state(&self, var: &Variable) -> Option<&R::State> {
+        self.state.get(var)
+    }
+
+    /// Clears the state of every parameter and restarts the schedule
+    pub fn reset(&mut self) {
+        self.state.clear();
+        self.steps = 0;
+    }
+
+    /// Clears the state of one parameter
+    pub fn reset_variable(&mut self, var: &Variable) {
+        self.state.remove(var);
+    }
+
+    /// Updates each parameter from its gradient
+    ///
+    /// All of the gradients are computed before any parameter is changed.
+    pub fn step<E: Expression<T>>(&mut self, grad: &mut Gradient<T, E>, params: &[Variable]) {
+        let grads = grad.grads(params);
+        let lr = self.learning_rate();
+
+        for (param, g) in params.iter().zip(grads) {
+            let x = *grad.get(param);
+            let g = if self.decoupled { g } else { g + self.weight_decay * x };
+
+            let rule = &self.rule;
+            let state = self.state.entry(*param).or_insert_with(|| rule.init());
+            let mut next = x - rule.update(state, g, lr);
+            if self.decoupled {
+                next = next - lr * self.weight_decay * x;
+            }
+            *grad.get_mut(param) = next;
+        }
+        self.steps += 1;
+    }
+}
+
+/// Gradient descent
+pub fn sgd<T: Float>(lr: T) -> Optimizer<T, Sgd> {
+    Optimizer::new(Sgd, lr)
+}
+
+/// Gradient descent with momentum `beta`
+pub fn momentum<T: Float + Debug>(lr: T, beta: T) -> Optimizer<T, Momentum<T>> {
+    Optimizer::new(Momentum { beta: beta, nesterov: false }, lr)
+}
+
+/// Gradient descent with Nesterov momentum `beta`
+pub fn nesterov<T: Float + Debug>(lr: T, beta: T) -> Optimizer<T, Momentum<T>> {
+    Optimizer::new(Momentum { beta: beta, nesterov: true }, lr)
+}
+
+/// Adagrad with `eps = 1e-10`
+pub fn adagrad<T: Float + Debug>(lr: T) -> Optimizer<T, Adagrad<T>> {
+    Optimizer::new(Adagrad { eps: T::from(1e-10).unwrap() }, lr)
+}
+
+/// RMSProp with `decay = 0.99` and `eps = 1e-8`
+pub fn rmsprop<T: Float + Debug>(lr: T) -> Optimizer<T, RmsProp<T>> {
+    Optimizer::new(RmsProp { decay: T::from(0.99).unwrap(), eps: T::from(1e-8).unwrap() }, lr)
+}
+
+/// Adam with `beta1 = 0.9`, `beta2 = 0.999` and `eps = 1e-8`
+pub fn adam<T: Float + Debug>(lr: T) -> Optimizer<T, Adam<T>> {
+    let adam = Adam {
+        beta1: T::from(0.9).unwrap(),
+        beta2: T::from(0.999).unwrap(),
+        eps: T::from(1e-8).unwrap(),
+    };
+    Optimizer::new(adam, lr)
+}
+
+/// The reason a minimiser stopped
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Termination {
+    /// The largest gradient component was within the gradient tolerance
+    GradientTolerance,
+    /// The relative decrease in the objective was within the value tolerance
+    ValueTolerance,
+    /// The maximum number of iterations was reached
+    MaxIterations,
+    /// The line search could not find an acceptable step
+    LineSearchFailed,
+    /// The trust region shrank to nothing without an acceptable step
+    RadiusTooSmall,
+    /// The step was within the step tolerance
+    StepTolerance,
+    /// The equations for the step were singular
+    Singular,
+}
+
+/// One iteration of a minimiser
+#[derive(Clone, Copy, Debug)]
+pub struct Iteration<T> {
+    /// The objective after the iteration
+    pub value: T,
+    /// The largest gradient component after the iteration
+    pub grad_norm: T,
+    /// The length of the step taken
+    pub step: T,
+    /// The number of objective evaluations made so far
+    pub evaluations: usize,
+}
+
+/// The result of a minimisation
+///
+/// The parameters are left at `x` in the gradient's context.
+#[derive(Clone, Debug)]
+pub struct Minimum<T> {
+    /// The parameter values at the minimum, in the order they were given
+    pub x: Vec<T>,
+    /// The objective at `x`
+    pub value: T,
+    /// The objective, gradient norm and step of each iteration
+    pub history: Vec<Iteration<T>>,
+    /// The number of objective evaluations
+    pub evaluations: usize,
+    /// Why the minimiser stopped
+    pub termination: Termination,
+}
+
+impl<T> Minimum<T> {
+    /// Returns true if the minimiser stopped because it met a tolerance
+    pub fn converged(&self) -> bool {
+        self.termination == Termination::GradientTolerance || self.termination == Termination::ValueTolerance
+    }
+
+    /// Returns the number of iterations taken
+    pub fn iterations(&self) -> usize {
+        self.history.len()
+    }
+}
+
+// Evaluates an objective and its gradient at points of its parameters
+struct Objective<'a, T: 'a, E: 'a + Expression<T>> {
+    grad: &'a mut Gradient<T, E>,
+    params: &'a [Variable],
+    evaluations: usize,
+}
+
+impl<'a, T: Float, E: Expression<T>> Objective<'a, T, E> {
+    fn new(grad: &'a mut Gradient<T, E>, params: &'a [Variable]) -> Self {
+        Objective { grad: grad, params: params, evaluations: 0 }
+    }
+
+    fn get(&self) -> Vec<T> {
+        self.params.iter().map(|p| *self.grad.get(p)).collect()
+    }
+
+    fn set(&mut self, x: &[T]) {
+        for (p, &v) in self.params.iter().zip(x) {
+            *self.grad.get_mut(p) = v;
+        }
+    }
+
+    // Returns the objective and its gradient at `x`
+    fn eval(&mut self, x: &[T]) -> (T, Vec<T>) {
+        self.set(x);
+        self.evaluations += 1;
+        let grad = &mut *self.grad;
+        let (value, grads) = accumulate_all(&grad.expr, &mut grad.context, self.params, |_| T::one());
+        (value, grads.into_iter().map(|g| g.unwrap_or(T::zero())).collect())
+    }
+}
+
+fn dot<T: Float>(x: &[T], y: &[T]) -> T {
+    x.iter().zip(y).fold(T::zero(), |s, (&a, &b)| s + a * b)
+}
+
+fn max_abs<T: Float>(x: &[T]) -> T {
+    x.iter().fold(T::zero(), |m, v| m.max(v.abs()))
+}
+
+// x + t d
+fn axpy<T: Float>(x: &[T], t: T, d: &[T]) -> Vec<T> {
+    x.iter().zip(d).map(|(&x, &d)| x + t * d).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::{Container, Context, LeafVar};
+
+    // A badly scaled quadratic with its minimum at (1, -2)
+    fn quadratic() -> (Gradient<f64, Box<dyn Expression<f64>>>, Variable, Variable) {
+        let mut c = Context::new();
+        let x = c.create_variable(-1.0);
+        let y = c.create_variable(1.0);
+        let one = Container::new(LeafVar(1.0));
+        let two = Container::new(LeafVar(2.0));
+        let ten = Container::new(LeafVar(10.0));
+        let f = (x - one) * (x - one) + ten * (y + two) * (y + two);
+        let f: Box<dyn Expression<f64>> = Box::new(f);
+        (Gradient::of(Container::new(f), c), *x, *y)
+    }
+
+    fn minimise<R: Rule<f64>>(mut opt: Optimizer<f64, R>, steps: usize) -> (f64, f64) {
+        let (mut g, x, y) = quadratic();
+        for _ in 0..steps {
+            opt.step(&mut g, &[x, y]);
+        }
+        (*g.get(&x), *g.get(&y))
+    }
+
+    #[test]
+    fn test_converge() {
+        let results = [
+            ("sgd", minimise(sgd(0.04), 500)),
+            ("momentum", minimise(momentum(0.01, 0.9), 500)),
+            ("nesterov", minimise(nesterov(0.01, 0.9), 500)),
+            ("adagrad", minimise(adagrad(1.0), 500)),
+            ("rmsprop", minimise(rmsprop(0.01).schedule(Schedule::InverseTime { decay: 0.01 }), 2000)),
+            ("adam", minimise(adam(0.05).schedule(Schedule::Exponential { gamma: 0.995 }), 2000)),
+        ];
+        for &(name, (x, y)) in &results {
+            assert!((x - 1.0).abs() < 1e-3 && (y + 2.0).abs() < 1e-3, "{}: ({}, {})", name, x, y);
+        }
+    }
+
+    #[test]
+    fn test_state() {
+        let (mut g, x, y) = quadratic();
+        let mut opt = adam(0.1);
+        assert!(opt.state(&x).is_none());
+
+        // The first Adam step has length lr in each coordinate
+        opt.step(&mut g, &[x]);
+        assert!((*g.get(&x) + 0.9).abs() < 1e-6);
+        assert_eq!(*g.get(&y), 1.0);
+        let state = *opt.state(&x).unwrap();
+        assert_eq!(state.t, 1);
+        assert!((state.m + 0.4).abs() < 1e-12);
+        assert!(opt.state(&y).is_none());
+
+        opt.reset_variable(&x);
+        assert!(opt.state(&x).is_none());
+        opt.step(&mut g, &[x, y]);
+        opt.reset();
+        assert!(opt.state(&x).is_none() && opt.state(&y).is_none());
+        assert_eq!(opt.steps(), 0);
+    }
+
+    #[test]
+    fn test_weight_decay() {
+        // With L2 decay the minimum of (x - 1)^2 moves to 2 / (2 + wd)
+        let mut c = Context::new();
+        let x = c.create_variable(0.0);
+        let one = Container::new(LeafVar(1.0));
+        let mut g = Gradient::of((x - one) * (x - one), c);
+        let mut opt = sgd(0.1).weight_decay(0.5);
+        for _ in 0..200 {
+            opt.step(&mut g, &[*x]);
+        }
+        assert!((*g.get(&x) - 0.8).abs() < 1e-10);
+
+        // Decoupled decay is applied after the update
+        *g.get_mut(&x) = 2.0;
+        let mut opt = sgd(0.1).decoupled_weight_decay(0.5);
+        opt.step(&mut g, &[*x]);
+        assert!((*g.get(&x) - (2.0 - 0.1 * 2.0 - 0.1 * 0.5 * 2.0)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_schedules() {
+        let step = Schedule::Step { every: 10, factor: 0.5 };
+        assert_eq!(step.rate(1.0, 9), 1.0);
+        assert_eq!(step.rate(1.0, 25), 0.25);
+        assert!((Schedule::Exponential { gamma: 0.9 }.rate(2.0, 2) - 1.62).abs() < 1e-12);
+        assert_eq!(Schedule::InverseTime { decay: 0.5 }.rate(3.0, 4), 1.0);
+
+        let cosine = Schedule::Cosine { steps: 100, min_lr: 0.1 };
+        assert_eq!(cosine.rate(1.0, 0), 1.0);
+        assert!((cosine.rate(1.0, 50) - 0.55).abs() < 1e-12);
+        assert!((cosine.rate(1.0, 200) - 0.1).abs() < 1e-12);
+
+        let mut opt = sgd(1.0).schedule(step);
+        let (mut g, x, _) = quadratic();
+        for _ in 0..10 {
+            opt.step(&mut g, &[x]);
+        }
+        assert_eq!(opt.learning_rate(), 0.5);
+    }
+}
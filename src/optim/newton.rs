@@ -0,0 +1,579 @@
+//! Second order minimisers using Hessian-vector products
+
+use num::Float;
+
+use ::{accumulate_all, Context, Expression, Gradient, Variable};
+use ::simplify::Simplifier;
+use ::term::{Term, ToTerm};
+use super::{axpy, dot, max_abs, Iteration, Minimum, Objective, Termination};
+use super::line_search::Wolfe;
+
+// Hessian-vector products, the Hessian is never formed
+enum Curvature<T> {
+    // Central differences of the gradient along the direction
+    Differences,
+    // Back propagation through the derivative along the direction
+    //
+    // The directional derivative `sum v_i df/dx_i` is built symbolically
+    // once with the direction as extra variables in a copy of the
+    // context, so each product only sets the variables and back
+    // propagates.
+    Exact {
+        context: Context<T>,
+        params: Vec<Variable>,
+        direction: Vec<Variable>,
+        term: Term<T>,
+    },
+}
+
+impl<T: Float + 'static> Curvature<T> {
+    fn exact<E: Expression<T> + ToTerm<T>>(grad: &Gradient<T, E>, params: &[Variable]) -> Self {
+        let mut context = Context { vars: grad.context.vars.clone(), node_count: 0 };
+        let direction: Vec<Variable> = params.iter().map(|_| *context.create_variable(T::zero())).collect();
+
+        let f = grad.expr().to_term();
+        let term = params.iter().zip(&direction).fold(Term::Const(T::zero()), |sum, (p, v)| {
+            let along = Term::Mul(Box::new(Term::Var(*v)), Box::new(f.diff(p)));
+            Term::Add(Box::new(sum), Box::new(along))
+        });
+
+        Curvature::Exact {
+            context: context,
+            params: params.to_vec(),
+            direction: direction,
+            term: Simplifier::new().simplify(term),
+        }
+    }
+}
+
+impl<T: Float> Curvature<T> {
+    // Returns H(x) v
+    //
+    // Exact products take one back propagation. Differences take two and
+    // use a step balancing truncation against rounding, so they carry
+    // about two thirds of the digits of the gradient. Both are included
+    // in the evaluation count.
+    fn product<E: Expression<T>>(&mut self, obj: &mut Objective<T, E>, x: &[T], v: &[T]) -> Vec<T> {
+        match *self {
+            Curvature::Differences => {
+                let v_norm = norm(v);
+                if v_norm == T::zero() {
+                    return vec![T::zero(); v.len()];
+                }
+                let h = T::epsilon().cbrt() * norm(x).max(T::one()) / v_norm;
+                let (_, plus) = obj.eval(&axpy(x, h, v));
+                let (_, minus) = obj.eval(&axpy(x, -h, v));
+                plus.iter().zip(&minus).map(|(&a, &b)| (a - b) / (h + h)).collect()
+            },
+            Curvature::Exact { ref mut context, ref params, ref direction, ref term } => {
+                for (p, &x) in params.iter().zip(x) {
+                    context.set_variable_value(p, x);
+                }
+                for (d, &v) in direction.iter().zip(v) {
+                    context.set_variable_value(d, v);
+                }
+                obj.evaluations += 1;
+                let (_, grads) = accumulate_all(term, context, params, |_| T::one());
+                grads.into_iter().map(|g| g.unwrap_or(T::zero())).collect()
+            },
+        }
+    }
+}
+
+fn norm<T: Float>(x: &[T]) -> T {
+    dot(x, x).sqrt()
+}
+
+// The largest t >= 0 with |z + t d| = radius
+fn to_boundary<T: Float>(z: &[T], d: &[T], radius: T) -> T {
+    let a = dot(d, d);
+    let b = dot(z, d);
+    let c = dot(z, z) - radius * radius;
+    (-b + (b * b - a * c).max(T::zero()).sqrt()) / a
+}
+
+/// The truncated Newton minimiser
+///
+/// Each iteration solves the Newton equations `H d = -g` approximately by
+/// conjugate gradients, stopping early at a relative residual of
+/// `min(0.5, sqrt(|g|))` or when a direction of negative curvature is
+/// found, and then takes a strong Wolfe line search along `d`.
+///
+/// With `minimize` products of the Hessian with a direction are found
+/// from the gradient at two points along it, so each conjugate gradient
+/// step costs two gradient evaluations, which are included in the
+/// evaluation count. Expressions implementing `ToTerm` can use
+/// `minimize_exact` instead, which differentiates the gradient along the
+/// direction symbolically and back propagates once per product.
+///
+/// # Example
+///
+/// ```
+/// use rugrads::{Context, Gradient};
+/// use rugrads::functions::*;
+/// use rugrads::optim::NewtonCg;
+///
+/// let mut context = Context::new();
+/// let x = context.create_variable(2.0f64);
+/// let y = context.create_variable(1.0);
+/// let mut grad = Gradient::of(exp(x - y) + exp(y - x) + y * y, context);
+///
+/// let min = NewtonCg::new().minimize(&mut grad, &[*x, *y]);
+/// assert!(min.converged());
+/// assert!(min.x[0].abs() < 1e-8 && min.x[1].abs() < 1e-8);
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct NewtonCg<T> {
+    max_iter: usize,
+    max_cg: Option<usize>,
+    gtol: T,
+    ftol: T,
+    wolfe: Wolfe<T>,
+}
+
+impl<T: Float> NewtonCg<T> {
+    /// Creates a minimiser
+    ///
+    /// The defaults stop when the largest gradient component is at most
+    /// `1e-8` or the relative decrease is at most machine epsilon, and
+    /// take at most 200 iterations with at most one conjugate gradient
+    /// step per parameter in each.
+    pub fn new() -> Self {
+        NewtonCg {
+            max_iter: 200,
+            max_cg: None,
+            gtol: T::from(1e-8).unwrap(),
+            ftol: T::epsilon(),
+            wolfe: Wolfe::new(),
+        }
+    }
+
+    /// Sets the maximum number of iterations
+    pub fn max_iter(mut self, max_iter: usize) -> Self {
+        self.max_iter = max_iter;
+        self
+    }
+
+    /// Sets the maximum number of conjugate gradient steps in each iteration
+    pub fn max_cg(mut self, max_cg: usize) -> Self {
+        self.max_cg = Some(max_cg);
+        self
+    }
+
+    /// Sets the gradient tolerance
+    pub fn gtol(mut self, gtol: T) -> Self {
+        self.gtol = gtol;
+        self
+    }
+
+    /// Sets the tolerance on the relative decrease of the objective
+    pub fn ftol(mut self, ftol: T) -> Self {
+        self.ftol = ftol;
+        self
+    }
+
+    // Approximately solves H d = -g
+    fn direction<E: Expression<T>>(&self, curvature: &mut Curvature<T>, obj: &mut Objective<T, E>, x: &[T], g: &[T]) -> Vec<T> {
+        let g_norm = norm(g);
+        let tol = g_norm.sqrt().min(T::from(0.5).unwrap()) * g_norm;
+        let mut z = vec![T::zero(); g.len()];
+        let mut r = g.to_vec();
+        let mut d: Vec<T> = g.iter().map(|&v| -v).collect();
+        let mut rr = dot(&r, &r);
+
+        for j in 0..self.max_cg.unwrap_or(g.len()).max(1) {
+            let hd = curvature.product(obj, x, &d);
+            let curv = dot(&d, &hd);
+            if curv <= T::zero() {
+                // Negative curvature, so stop with the progress so far
+                return if j == 0 { d } else { z };
+            }
+            let alpha = rr / curv;
+            z = axpy(&z, alpha, &d);
+            r = axpy(&r, alpha, &hd);
+            let rr_next = dot(&r, &r);
+            if rr_next.sqrt() <= tol {
+                break;
+            }
+            let beta = rr_next / rr;
+            d = r.iter().zip(&d).map(|(&r, &d)| -r + beta * d).collect();
+            rr = rr_next;
+        }
+        z
+    }
+
+    /// Minimises the expression over `params`, starting from their current values
+    pub fn minimize<E>(&self, grad: &mut Gradient<T, E>, params: &[Variable]) -> Minimum<T>
+        where E: Expression<T>
+    {
+        self.run(Curvature::Differences, grad, params)
+    }
+
+    /// Minimises the expression over `params` with exact Hessian-vector products
+    pub fn minimize_exact<E>(&self, grad: &mut Gradient<T, E>, params: &[Variable]) -> Minimum<T>
+        where T: 'static, E: Expression<T> + ToTerm<T>
+    {
+        self.run(Curvature::exact(grad, params), grad, params)
+    }
+
+    fn run<E>(&self, mut curvature: Curvature<T>, grad: &mut Gradient<T, E>, params: &[Variable]) -> Minimum<T>
+        where E: Expression<T>
+    {
+        let mut obj = Objective::new(grad, params);
+        let mut x = obj.get();
+        let (mut value, mut g) = obj.eval(&x);
+        let mut history = vec![];
+
+        let termination = loop {
+            if max_abs(&g) <= self.gtol {
+                break Termination::GradientTolerance;
+            }
+            if history.len() == self.max_iter {
+                break Termination::MaxIterations;
+            }
+
+            let mut d = self.direction(&mut curvature, &mut obj, &x, &g);
+            let slope = dot(&d, &g);
+            if slope.is_nan() || slope >= T::zero() {
+                d = g.iter().map(|&v| -v).collect();
+            }
+            let step = match self.wolfe.search(&mut obj, &x, value, &g, &d, T::one()) {
+                Some(step) => step,
+                None => break Termination::LineSearchFailed,
+            };
+
+            let decrease = value - step.value;
+            x = axpy(&x, step.alpha, &d);
+            value = step.value;
+            g = step.grad;
+            history.push(Iteration {
+                value: value,
+                grad_norm: max_abs(&g),
+                step: step.alpha.abs() * norm(&d),
+                evaluations: obj.evaluations,
+            });
+            if decrease <= self.ftol * value.abs().max(decrease.abs()).max(T::one()) {
+                break Termination::ValueTolerance;
+            }
+        };
+
+        obj.set(&x);
+        Minimum {
+            x: x,
+            value: value,
+            history: history,
+            evaluations: obj.evaluations,
+            termination: termination,
+        }
+    }
+}
+
+impl<T: Float> Default for NewtonCg<T> {
+    fn default() -> Self {
+        NewtonCg::new()
+    }
+}
+
+/// The trust region minimiser with Steihaug's conjugate gradient steps
+///
+/// Each iteration minimises the quadratic model of the objective within
+/// a ball by conjugate gradients, stopping at the boundary or along a
+/// direction of negative curvature. The radius shrinks when the model
+/// predicts the decrease badly and grows when a step reaches the boundary
+/// and the model predicts it well, up to the maximum radius.
+///
+/// Products of the Hessian with a direction are found as for `NewtonCg`,
+/// by differences with `minimize` or exactly with `minimize_exact`.
+#[derive(Clone, Copy, Debug)]
+pub struct TrustRegion<T> {
+    max_iter: usize,
+    max_cg: Option<usize>,
+    gtol: T,
+    radius: T,
+    max_radius: T,
+    eta: T,
+}
+
+impl<T: Float> TrustRegion<T> {
+    /// Creates a minimiser
+    ///
+    /// The defaults stop when the largest gradient component is at most
+    /// `1e-8` and take at most 200 iterations. The radius starts at 1 and
+    /// is at most 1000, and steps are accepted when they achieve at least
+    /// 0.1 of the predicted decrease.
+    pub fn new() -> Self {
+        TrustRegion {
+            max_iter: 200,
+            max_cg: None,
+            gtol: T::from(1e-8).unwrap(),
+            radius: T::one(),
+            max_radius: T::from(1000.0).unwrap(),
+            eta: T::from(0.1).unwrap(),
+        }
+    }
+
+    /// Sets the maximum number of iterations
+    pub fn max_iter(mut self, max_iter: usize) -> Self {
+        self.max_iter = max_iter;
+        self
+    }
+
+    /// Sets the maximum number of conjugate gradient steps in each iteration
+    pub fn max_cg(mut self, max_cg: usize) -> Self {
+        self.max_cg = Some(max_cg);
+        self
+    }
+
+    /// Sets the gradient tolerance
+    pub fn gtol(mut self, gtol: T) -> Self {
+        self.gtol = gtol;
+        self
+    }
+
+    /// Sets the initial and maximum radius
+    pub fn radius(mut self, radius: T, max_radius: T) -> Self {
+        self.radius = radius;
+        self.max_radius = max_radius;
+        self
+    }
+
+    /// Sets the fraction of the predicted decrease a step must achieve
+    pub fn eta(mut self, eta: T) -> Self {
+        self.eta = eta;
+        self
+    }
+
+    // Steihaug's truncated conjugate gradients for min g.p + p.Hp / 2
+    // with |p| <= radius, returning p and Hp
+    fn step<E: Expression<T>>(&self, curvature: &mut Curvature<T>, obj: &mut Objective<T, E>, x: &[T], g: &[T], radius: T)
+        -> (Vec<T>, Vec<T>)
+    {
+        let g_norm = norm(g);
+        let tol = g_norm.sqrt().min(T::from(0.5).unwrap()) * g_norm;
+        let mut z = vec![T::zero(); g.len()];
+        let mut hz = vec![T::zero(); g.len()];
+        let mut r = g.to_vec();
+        let mut d: Vec<T> = g.iter().map(|&v| -v).collect();
+        let mut rr = dot(&r, &r);
+
+        for _ in 0..self.max_cg.unwrap_or(g.len()).max(1) {
+            let hd = curvature.product(obj, x, &d);
+            let curv = dot(&d, &hd);
+            if curv <= T::zero() {
+                let tau = to_boundary(&z, &d, radius);
+                return (axpy(&z, tau, &d), axpy(&hz, tau, &hd));
+            }
+            let alpha = rr / curv;
+            let z_next = axpy(&z, alpha, &d);
+            if norm(&z_next) >= radius {
+                let tau = to_boundary(&z, &d, radius);
+                return (axpy(&z, tau, &d), axpy(&hz, tau, &hd));
+            }
+            z = z_next;
+            hz = axpy(&hz, alpha, &hd);
+            r = axpy(&r, alpha, &hd);
+            let rr_next = dot(&r, &r);
+            if rr_next.sqrt() <= tol {
+                break;
+            }
+            let beta = rr_next / rr;
+            d = r.iter().zip(&d).map(|(&r, &d)| -r + beta * d).collect();
+            rr = rr_next;
+        }
+        (z, hz)
+    }
+
+    /// Minimises the expression over `params`, starting from their current values
+    pub fn minimize<E>(&self, grad: &mut Gradient<T, E>, params: &[Variable]) -> Minimum<T>
+        where E: Expression<T>
+    {
+        self.run(Curvature::Differences, grad, params)
+    }
+
+    /// Minimises the expression over `params` with exact Hessian-vector products
+    pub fn minimize_exact<E>(&self, grad: &mut Gradient<T, E>, params: &[Variable]) -> Minimum<T>
+        where T: 'static, E: Expression<T> + ToTerm<T>
+    {
+        self.run(Curvature::exact(grad, params), grad, params)
+    }
+
+    fn run<E>(&self, mut curvature: Curvature<T>, grad: &mut Gradient<T, E>, params: &[Variable]) -> Minimum<T>
+        where E: Expression<T>
+    {
+        let quarter = T::from(0.25).unwrap();
+        let mut obj = Objective::new(grad, params);
+        let mut x = obj.get();
+        let (mut value, mut g) = obj.eval(&x);
+        let mut radius = self.radius;
+        let mut history = vec![];
+
+        let termination = loop {
+            if max_abs(&g) <= self.gtol {
+                break Termination::GradientTolerance;
+            }
+            if history.len() == self.max_iter {
+                break Termination::MaxIterations;
+            }
+            if radius <= T::epsilon() * norm(&x).max(T::one()) {
+                break Termination::RadiusTooSmall;
+            }
+
+            let (p, hp) = self.step(&mut curvature, &mut obj, &x, &g, radius);
+            let predicted = -(dot(&g, &p) + dot(&p, &hp) / (T::one() + T::one()));
+            let next = axpy(&x, T::one(), &p);
+            let (next_value, next_g) = obj.eval(&next);
+            let actual = value - next_value;
+            let rho = if predicted > T::zero() { actual / predicted } else { -T::one() };
+
+            let p_norm = norm(&p);
+            if rho.is_nan() || rho < quarter {
+                radius = quarter * p_norm;
+            } else if rho > T::from(0.75).unwrap() && p_norm >= radius * T::from(0.99).unwrap() {
+                radius = (radius + radius).min(self.max_radius);
+            }
+
+            if rho > self.eta {
+                x = next;
+                value = next_value;
+                g = next_g;
+            }
+            history.push(Iteration {
+                value: value,
+                grad_norm: max_abs(&g),
+                step: if rho > self.eta { p_norm } else { T::zero() },
+                evaluations: obj.evaluations,
+            });
+        };
+
+        obj.set(&x);
+        Minimum {
+            x: x,
+            value: value,
+            history: history,
+            evaluations: obj.evaluations,
+            termination: termination,
+        }
+    }
+}
+
+impl<T: Float> Default for TrustRegion<T> {
+    fn default() -> Self {
+        TrustRegion::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::{Container, Context, LeafVar};
+    use ::functions::*;
+
+    fn rosenbrock() -> (Gradient<f64, Box<dyn Expression<f64>>>, Variable, Variable) {
+        let mut c = Context::new();
+        let x = c.create_variable(-1.2);
+        let y = c.create_variable(1.0);
+        let one = Container::new(LeafVar(1.0f64));
+        let hundred = Container::new(LeafVar(100.0));
+        let f: Box<dyn Expression<f64>> = Box::new((one - x) * (one - x) + hundred * (y - x * x) * (y - x * x));
+        (Gradient::of(Container::new(f), c), *x, *y)
+    }
+
+    #[test]
+    fn test_hessian_vector_product() {
+        let (mut g, x, y) = rosenbrock();
+        let params = [x, y];
+        let mut obj = Objective::new(&mut g, &params);
+        let mut curvature = Curvature::Differences;
+        // H = [[1200 x^2 - 400 y + 2, -400 x], [-400 x, 200]]
+        let hv = curvature.product(&mut obj, &[0.5, 2.0], &[1.0, -3.0]);
+        assert!((hv[0] - (-498.0 + 600.0)).abs() < 1e-6);
+        assert!((hv[1] - (-200.0 - 600.0)).abs() < 1e-6);
+        assert_eq!(obj.evaluations, 2);
+        assert_eq!(curvature.product(&mut obj, &[0.5, 2.0], &[0.0, 0.0]), vec![0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_exact_hessian_vector_product() {
+        let mut c = Context::new();
+        let x = c.create_variable(-1.2);
+        let y = c.create_variable(1.0);
+        let one = Container::new(LeafVar(1.0f64));
+        let hundred = Container::new(LeafVar(100.0));
+        let mut g = Gradient::of((one - x) * (one - x) + hundred * (y - x * x) * (y - x * x), c);
+        let params = [*x, *y];
+
+        let mut curvature = Curvature::exact(&g, &params);
+        let mut obj = Objective::new(&mut g, &params);
+        let hv = curvature.product(&mut obj, &[0.5, 2.0], &[1.0, -3.0]);
+        assert!((hv[0] - (-498.0 + 600.0)).abs() < 1e-12);
+        assert!((hv[1] - (-200.0 - 600.0)).abs() < 1e-12);
+        assert_eq!(obj.evaluations, 1);
+    }
+
+    #[test]
+    fn test_minimize_exact() {
+        let mut c = Context::new();
+        let x = c.create_variable(-1.2);
+        let y = c.create_variable(1.0);
+        let one = Container::new(LeafVar(1.0f64));
+        let hundred = Container::new(LeafVar(100.0));
+        let f = (one - x) * (one - x) + hundred * (y - x * x) * (y - x * x);
+
+        let mut g = Gradient::of(f, c);
+        let newton = NewtonCg::new().minimize_exact(&mut g, &[*x, *y]);
+        assert!(newton.converged(), "{:?}", newton.termination);
+
+        g.context().set_variable_value(&x, -1.2);
+        g.context().set_variable_value(&y, 1.0);
+        let trust = TrustRegion::new().minimize_exact(&mut g, &[*x, *y]);
+        assert!(trust.converged(), "{:?}", trust.termination);
+
+        for min in &[newton, trust] {
+            assert!((min.x[0] - 1.0).abs() < 1e-6 && (min.x[1] - 1.0).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_trust_region_evaluations() {
+        // One product of two gradients and the trial point per iteration
+        let (mut g, x, y) = rosenbrock();
+        let min = TrustRegion::new().max_cg(1).max_iter(5).minimize(&mut g, &[x, y]);
+        let mut last = 1;
+        for it in &min.history {
+            assert_eq!(it.evaluations - last, 3);
+            last = it.evaluations;
+        }
+    }
+
+    #[test]
+    fn test_rosenbrock() {
+        let (mut g, x, y) = rosenbrock();
+        let newton = NewtonCg::new().minimize(&mut g, &[x, y]);
+        assert!(newton.converged(), "{:?}", newton.termination);
+
+        let (mut g, x, y) = rosenbrock();
+        let trust = TrustRegion::new().minimize(&mut g, &[x, y]);
+        assert!(trust.converged(), "{:?}", trust.termination);
+
+        for min in &[newton, trust] {
+            assert!((min.x[0] - 1.0).abs() < 1e-6 && (min.x[1] - 1.0).abs() < 1e-6);
+            assert!(min.iterations() < 100, "{}", min.iterations());
+        }
+    }
+
+    #[test]
+    fn test_negative_curvature() {
+        // Starting near the saddle of x^4 - x^2 + y^2 at the origin
+        let mut c = Context::new();
+        let x = c.create_variable(1e-3);
+        let y = c.create_variable(0.5);
+        let f = powi(x, 4) - x * x + y * y;
+        let mut g = Gradient::of(f, c);
+        let min = TrustRegion::new().radius(0.1, 10.0).minimize(&mut g, &[*x, *y]);
+
+        assert!(min.converged(), "{:?}", min.termination);
+        assert!((min.x[0] - f64::sqrt(0.5)).abs() < 1e-8);
+        assert!(min.x[1].abs() < 1e-8);
+        assert!((min.value + 0.25).abs() < 1e-12);
+    }
+}
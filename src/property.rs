@@ -0,0 +1,504 @@
+//! Property module
+//!
+//! Randomized verification of gradients.
+//!
+//! A `Harness` holds a set of registered operations. It builds random
+//! expression trees from these operations, draws random points at which
+//! every operation is inside its domain and checks the gradient at that
+//! point. Operations are registered as boxed expression builders so that
+//! any crate can plug in its own.
+//!
+//! # Example
+//!
+//! ```
+//! use rugrads::property::{float_ops, Harness, Op};
+//! use rugrads::functions::*;
+//!
+//! // Check the built-in operations along with a custom one
+//! let summary = Harness::<f64>::float()
+//!     .with_op(Op::unary("sin_sq", |x| powf(sin(x), 2.0)))
+//!     .cases(50)
+//!     .run()
+//!     .unwrap();
+//! assert!(summary.cases + summary.rejected == 50);
+//! ```
+
+use std::fmt;
+use std::ops;
+
+use num::Float;
+
+use ::{Container, Context, Expression, Gradient, LeafVar, Scalar, Variable};
+use ::check::{Difference, GradCheck};
+use ::functions::*;
+use ::rng::Rng;
+
+/// A boxed expression used to build random trees
+pub type BoxedExpr<T> = Box<dyn Expression<T>>;
+
+/// A gradient over a randomly built expression
+pub type BoxedGradient<T> = Gradient<T, BoxedExpr<T>>;
+
+/// An operation which can appear in random expressions
+pub struct Op<T> {
+    name: String,
+    arity: usize,
+    build: Box<dyn Fn(Vec<BoxedExpr<T>>) -> BoxedExpr<T>>,
+    domain: Box<dyn Fn(&[T]) -> bool>,
+}
+
+impl<T: 'static> Op<T> {
+    /// Creates an operation from a function building it from its arguments
+    ///
+    /// The builder is always given exactly `arity` arguments.
+    pub fn new<F>(name: &str, arity: usize, build: F) -> Self
+        where F: Fn(Vec<BoxedExpr<T>>) -> BoxedExpr<T> + 'static
+    {
+        Op {
+            name: name.to_string(),
+            arity: arity,
+            build: Box::new(build),
+            domain: Box::new(|_| true),
+        }
+    }
+
+    /// Creates an operation taking a single argument
+    pub fn unary<F, R>(name: &str, f: F) -> Self
+        where F: Fn(Container<T, BoxedExpr<T>>) -> Container<T, R> + 'static,
+              R: Expression<T> + 'static
+    {
+        Op::new(name, 1, move |mut args| {
+            let x = args.pop().unwrap();
+            Box::new(f(Container::new(x)).into_inner())
+        })
+    }
+
+    /// Creates an operation taking two arguments
+    pub fn binary<F, R>(name: &str, f: F) -> Self
+        where F: Fn(Container<T, BoxedExpr<T>>, Container<T, BoxedExpr<T>>) -> Container<T, R> + 'static,
+              R: Expression<T> + 'static
+    {
+        Op::new(name, 2, move |mut args| {
+            let y = args.pop().unwrap();
+            let x = args.pop().unwrap();
+            Box::new(f(Container::new(x), Container::new(y)).into_inner())
+        })
+    }
+
+    /// Restricts the argument values at which the operation is checked
+    ///
+    /// The predicate is given the values of the arguments. Points should
+    /// be kept away from singularities, where finite differences are
+    /// unreliable.
+    pub fn domain<D>(mut self, domain: D) -> Self
+        where D: Fn(&[T]) -> bool + 'static
+    {
+        self.domain = Box::new(domain);
+        self
+    }
+
+    /// Returns the name of the operation
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the number of arguments the operation takes
+    pub fn arity(&self) -> usize {
+        self.arity
+    }
+}
+
+/// Returns the operations from `functions` with domains suitable for checking
+pub fn float_ops<T>() -> Vec<Op<T>>
+    where T: Float + Scalar + 'static,
+          for<'a, 'b> &'a T: ops::Add<&'b T, Output=T> + ops::Sub<&'b T, Output=T>
+              + ops::Mul<&'b T, Output=T> + ops::Div<&'b T, Output=T>
+{
+    let c = |v: f64| T::from(v).unwrap();
+
+    vec![
+        Op::binary("add", |x, y| x + y),
+        Op::binary("sub", |x, y| x - y),
+        Op::binary("mul", |x, y| x * y),
+        Op::binary("div", |x, y| x / y).domain(move |a: &[T]| a[1].abs() > c(0.25)),
+        Op::unary("neg", |x| -x),
+        Op::unary("sin", sin).domain(move |a: &[T]| a[0].abs() < c(8.0)),
+        Op::unary("cos", cos).domain(move |a: &[T]| a[0].abs() < c(8.0)),
+        Op::unary("exp", exp).domain(move |a: &[T]| a[0] < c(2.0)),
+        Op::unary("ln", ln).domain(move |a: &[T]| a[0] > c(0.25)),
+        Op::unary("powf", move |x| powf(x, c(2.5))).domain(move |a: &[T]| a[0] > c(0.25) && a[0] < c(4.0)),
+        Op::unary("cube", move |x| powf(x, c(3.0))).domain(move |a: &[T]| a[0].abs() < c(1.5)),
+        Op::binary("pow", pow).domain(move |a: &[T]| a[0] > c(0.25) && a[0] < c(4.0) && a[1].abs() < c(2.0)),
+        Op::unary("powi", |x| powi(x, 3)).domain(move |a: &[T]| a[0].abs() < c(1.5)),
+        Op::unary("powi_neg", |x| powi(x, -2)).domain(move |a: &[T]| a[0].abs() > c(0.5)),
+        Op::unary("tan", tan).domain(move |a: &[T]| a[0].abs() < c(1.2)),
+        Op::unary("asin", asin).domain(move |a: &[T]| a[0].abs() < c(0.9)),
+        Op::unary("acos", acos).domain(move |a: &[T]| a[0].abs() < c(0.9)),
+        Op::unary("atan", atan),
+        Op::binary("atan2", atan2).domain(move |a: &[T]| a[0] * a[0] + a[1] * a[1] > c(0.1)),
+        Op::unary("sinh", sinh).domain(move |a: &[T]| a[0].abs() < c(3.0)),
+        Op::unary("cosh", cosh).domain(move |a: &[T]| a[0].abs() < c(3.0)),
+        Op::unary("tanh", tanh),
+        Op::unary("asinh", asinh),
+        Op::unary("acosh", acosh).domain(move |a: &[T]| a[0] > c(1.1)),
+        Op::unary("atanh", atanh).domain(move |a: &[T]| a[0].abs() < c(0.9)),
+        Op::unary("sqrt", sqrt).domain(move |a: &[T]| a[0] > c(0.25)),
+        Op::unary("cbrt", cbrt).domain(move |a: &[T]| a[0].abs() > c(0.25)),
+        Op::binary("hypot", hypot).domain(move |a: &[T]| a[0] * a[0] + a[1] * a[1] > c(0.1)),
+        Op::unary("log2", log2).domain(move |a: &[T]| a[0] > c(0.25)),
+        Op::unary("log10", log10).domain(move |a: &[T]| a[0] > c(0.25)),
+        Op::binary("log", log).domain(move |a: &[T]| a[0] > c(0.25) && a[1] > c(1.25)),
+        Op::unary("exp2", exp2).domain(move |a: &[T]| a[0] < c(3.0)),
+        Op::unary("exp_m1", exp_m1).domain(move |a: &[T]| a[0] < c(2.0)),
+        Op::unary("ln_1p", ln_1p).domain(move |a: &[T]| a[0] > c(-0.75)),
+        Op::unary("erf", erf),
+        Op::unary("erfc", erfc),
+        Op::unary("gamma", gamma).domain(move |a: &[T]| a[0] > c(0.25) && a[0] < c(4.0)),
+        Op::unary("lgamma", lgamma).domain(move |a: &[T]| a[0] > c(0.25)),
+        Op::unary("digamma", digamma).domain(move |a: &[T]| a[0] > c(0.25)),
+        Op::unary("trigamma", trigamma).domain(move |a: &[T]| a[0] > c(0.25)),
+        Op::binary("lbeta", lbeta).domain(move |a: &[T]| a[0] > c(0.25) && a[1] > c(0.25)),
+        Op::unary("abs", abs).domain(move |a: &[T]| a[0].abs() > c(0.05)),
+        Op::unary("relu", relu).domain(move |a: &[T]| a[0].abs() > c(0.05)),
+        Op::binary("min", min).domain(move |a: &[T]| (a[0] - a[1]).abs() > c(0.05)),
+        Op::binary("max", max).domain(move |a: &[T]| (a[0] - a[1]).abs() > c(0.05)),
+        Op::unary("clamp", move |x| clamp(x, c(-1.0), c(1.0)))
+            .domain(move |a: &[T]| (a[0].abs() - c(1.0)).abs() > c(0.05)),
+        Op::unary("sign", sign).domain(move |a: &[T]| a[0].abs() > c(0.05)),
+        Op::unary("floor", floor).domain(move |a: &[T]| (a[0] - a[0].round()).abs() > c(0.05)),
+        Op::unary("ceil", ceil).domain(move |a: &[T]| (a[0] - a[0].round()).abs() > c(0.05)),
+        Op::unary("round", round).domain(move |a: &[T]| (a[0] - a[0].floor() - c(0.5)).abs() > c(0.05)),
+    ]
+}
+
+/// The outcome of a successful run
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Summary {
+    /// The number of expressions which were checked
+    pub cases: usize,
+    /// The number of expressions skipped because no point in their domain was found
+    pub rejected: usize,
+}
+
+/// A randomly built expression which failed the check
+#[derive(Clone, Debug)]
+pub struct Failure<T> {
+    /// The seed of the run
+    pub seed: u64,
+    /// The index of the failing case within the run
+    pub case: usize,
+    /// A description of the expression, such as `sin(mul(x0, x1))`
+    pub expr: String,
+    /// The variable values at which the check failed
+    pub point: Vec<T>,
+    /// The message produced by the check
+    pub message: String,
+}
+
+impl<T: fmt::Debug> fmt::Display for Failure<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Gradient check failed for `{}` at {:?} (seed {}, case {}):\n{}",
+               self.expr, self.point, self.seed, self.case, self.message)
+    }
+}
+
+enum Tree {
+    Var(usize),
+    Op(usize, Vec<Tree>),
+}
+
+impl Tree {
+    fn contains(&self, var: usize) -> bool {
+        match *self {
+            Tree::Var(i) => i == var,
+            Tree::Op(_, ref args) => args.iter().any(|a| a.contains(var)),
+        }
+    }
+}
+
+/// Generates random expressions and checks their gradients
+pub struct Harness<T> {
+    ops: Vec<Op<T>>,
+    sample: Box<dyn Fn(&mut Rng) -> T>,
+    check: Box<dyn Fn(&mut BoxedGradient<T>, &[Variable]) -> Result<(), String>>,
+    output: Option<Box<dyn Fn(BoxedExpr<T>) -> BoxedExpr<T>>>,
+    variables: usize,
+    max_depth: usize,
+    cases: usize,
+    attempts: usize,
+    seed: u64,
+}
+
+impl<T: Clone + 'static> Harness<T> {
+    /// Creates a harness with no operations
+    ///
+    /// `sample` draws a random variable value and `check` compares the
+    /// gradient with respect to each of the given variables against a
+    /// reference, returning a description of any mismatch. Only the
+    /// variables which appear in the expression are given to `check`.
+    pub fn new<S, C>(sample: S, check: C) -> Self
+        where S: Fn(&mut Rng) -> T + 'static,
+              C: Fn(&mut BoxedGradient<T>, &[Variable]) -> Result<(), String> + 'static
+    {
+        Harness {
+            ops: vec![],
+            sample: Box::new(sample),
+            check: Box::new(check),
+            output: None,
+            variables: 2,
+            max_depth: 3,
+            cases: 100,
+            attempts: 20,
+            seed: 0,
+        }
+    }
+
+    /// Registers an operation
+    pub fn with_op(mut self, op: Op<T>) -> Self {
+        self.ops.push(op);
+        self
+    }
+
+    /// Registers several operations
+    pub fn with_ops<I: IntoIterator<Item=Op<T>>>(mut self, ops: I) -> Self {
+        self.ops.extend(ops);
+        self
+    }
+
+    /// Wraps every generated expression before it is checked
+    ///
+    /// This is useful when the gradient needs a particular output,
+    /// for example reducing an array to a scalar.
+    pub fn output<F>(mut self, f: F) -> Self
+        where F: Fn(BoxedExpr<T>) -> BoxedExpr<T> + 'static
+    {
+        self.output = Some(Box::new(f));
+        self
+    }
+
+    /// Sets the number of variables in each expression
+    pub fn variables(mut self, n: usize) -> Self {
+        self.variables = n;
+        self
+    }
+
+    /// Sets the maximum depth of the generated expressions
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = depth;
+        self
+    }
+
+    /// Sets the number of expressions to check
+    pub fn cases(mut self, cases: usize) -> Self {
+        self.cases = cases;
+        self
+    }
+
+    /// Sets the number of points tried before an expression is skipped
+    pub fn attempts(mut self, attempts: usize) -> Self {
+        self.attempts = attempts;
+        self
+    }
+
+    /// Sets the random seed
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    fn generate(&self, rng: &mut Rng, depth: usize) -> Tree {
+        // Below the maximum depth the root is always an operation so
+        // every op is exercised
+        let leaf = depth == 0 || (depth < self.max_depth && rng.below(3) == 0);
+        if leaf || self.ops.is_empty() {
+            return Tree::Var(rng.below(self.variables));
+        }
+
+        let op = rng.below(self.ops.len());
+        let args = (0..self.ops[op].arity).map(|_| self.generate(rng, depth - 1)).collect();
+        Tree::Op(op, args)
+    }
+
+    fn build(&self, tree: &Tree, vars: &[Variable]) -> BoxedExpr<T> {
+        match *tree {
+            Tree::Var(i) => Box::new(vars[i]),
+            Tree::Op(op, ref args) => {
+                (self.ops[op].build)(args.iter().map(|a| self.build(a, vars)).collect())
+            },
+        }
+    }
+
+    fn describe(&self, tree: &Tree) -> String {
+        match *tree {
+            Tree::Var(i) => format!("x{}", i),
+            Tree::Op(op, ref args) => {
+                let args: Vec<String> = args.iter().map(|a| self.describe(a)).collect();
+                format!("{}({})", self.ops[op].name, args.join(", "))
+            },
+        }
+    }
+
+    // Evaluates the tree bottom up, or returns None if an operation is
+    // given arguments outside its domain
+    fn eval_in_domain(&self, tree: &Tree, vars: &[Variable], c: &mut Context<T>) -> Option<T> {
+        match *tree {
+            Tree::Var(i) => Some(vars[i].value(c).clone()),
+            Tree::Op(op, ref args) => {
+                let mut values = Vec::with_capacity(args.len());
+                for a in args {
+                    values.push(self.eval_in_domain(a, vars, c)?);
+                }
+                if !(self.ops[op].domain)(&values) {
+                    return None;
+                }
+                let leaves = values.into_iter().map(|v| Box::new(LeafVar(v)) as BoxedExpr<T>).collect();
+                Some((self.ops[op].build)(leaves).eval(c).value().clone())
+            },
+        }
+    }
+
+    /// Checks the configured number of random expressions
+    ///
+    /// Returns the first failure found.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if no operations are registered or the
+    /// harness has no variables.
+    pub fn run(&self) -> Result<Summary, Failure<T>> {
+        assert!(!self.ops.is_empty(), "No operations registered");
+        assert!(self.variables > 0, "Expressions need at least one variable");
+
+        let mut rng = Rng::new(self.seed);
+        let mut rejected = 0;
+
+        for case in 0..self.cases {
+            let tree = self.generate(&mut rng, self.max_depth);
+
+            let mut c = Context::new();
+            let vars: Vec<Variable> = (0..self.variables)
+                .map(|_| *c.create_variable((self.sample)(&mut rng)))
+                .collect();
+
+            let mut found = false;
+            for _ in 0..self.attempts {
+                for var in vars.iter() {
+                    c.set_variable_value(var, (self.sample)(&mut rng));
+                }
+                if self.eval_in_domain(&tree, &vars, &mut c).is_some() {
+                    found = true;
+                    break;
+                }
+            }
+            if !found {
+                rejected += 1;
+                continue;
+            }
+
+            let point = vars.iter().map(|v| c.get_variable_value(v)).collect();
+            let mut expr = self.build(&tree, &vars);
+            if let Some(ref output) = self.output {
+                expr = output(expr);
+            }
+
+            let used: Vec<Variable> = vars.iter().enumerate()
+                .filter(|&(i, _)| tree.contains(i))
+                .map(|(_, v)| *v)
+                .collect();
+
+            let mut grad = Gradient::of(Container::new(expr), c);
+            if let Err(message) = (self.check)(&mut grad, &used) {
+                return Err(Failure {
+                    seed: self.seed,
+                    case: case,
+                    expr: self.describe(&tree),
+                    point: point,
+                    message: message,
+                });
+            }
+        }
+
+        Ok(Summary {
+            cases: self.cases - rejected,
+            rejected: rejected,
+        })
+    }
+}
+
+impl<T> Harness<T>
+    where T: Float + Scalar + fmt::Display + 'static,
+          for<'a, 'b> &'a T: ops::Add<&'b T, Output=T> + ops::Sub<&'b T, Output=T>
+              + ops::Mul<&'b T, Output=T> + ops::Div<&'b T, Output=T>
+{
+    /// Creates a harness for the operations in `functions`
+    ///
+    /// Variables are drawn from `[-2, 2)` and gradients are compared
+    /// against Richardson extrapolated central differences.
+    pub fn float() -> Self {
+        Harness::new(|rng| T::from(rng.range(-2.0, 2.0)).unwrap(), |grad, vars| {
+            let rtol = T::from(1e-5).unwrap();
+            let atol = T::from(1e-6).unwrap();
+            let report = GradCheck::new()
+                .difference(Difference::Richardson)
+                .tolerances(rtol, atol)
+                .check_variables(grad, vars);
+            if report.passed() {
+                Ok(())
+            } else {
+                Err(report.to_string())
+            }
+        }).with_ops(float_ops())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::marker::PhantomData;
+
+    use ::{Node, VecJacProduct};
+
+    #[test]
+    fn test_float_ops() {
+        let summary = Harness::<f64>::float().cases(500).max_depth(4).run()
+            .unwrap_or_else(|f| panic!("{}", f));
+        assert!(summary.cases > 400);
+    }
+
+    // The derivative of cube with the wrong sign
+    struct BadCube<X>(X);
+
+    struct BadCubeVJP(PhantomData<f64>);
+
+    impl VecJacProduct<f64> for BadCubeVJP {
+        fn vjp(&self, g: f64, _: &Node<f64>, x: &Node<f64>, _: usize) -> f64 {
+            -3.0 * g * x.value() * x.value()
+        }
+    }
+
+    impl<X: Expression<f64>> Expression<f64> for BadCube<X> {
+        fn eval(&self, c: &mut Context<f64>) -> Node<f64> {
+            let parents = vec![self.0.eval(c)];
+            let progenitors = Node::get_progenitors(&parents);
+            let value = parents[0].value().powi(3);
+            Node::new(c, value, parents, progenitors, Box::new(BadCubeVJP(PhantomData)))
+        }
+    }
+
+    #[test]
+    fn test_detects_plugged_in_op() {
+        let harness = || {
+            Harness::<f64>::float()
+                .with_op(Op::unary("bad_cube", |x| Container::new(BadCube(x.into_inner()))))
+                .seed(3)
+        };
+
+        let failure = harness().run().unwrap_err();
+        assert!(failure.expr.contains("bad_cube"));
+
+        // The same seed finds the same failure
+        let again = harness().run().unwrap_err();
+        assert_eq!(again.case, failure.case);
+        assert_eq!(again.point, failure.point);
+    }
+}
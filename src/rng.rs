@@ -0,0 +1,103 @@
+//! Random number module
+//!
+//! A small seedable pseudo-random number generator. This keeps
+//! randomized routines reproducible without pulling in another crate.
+
+/// A xorshift64* generator seeded through SplitMix64
+///
+/// This is not suitable for cryptographic use.
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    /// Creates a generator from a seed
+    ///
+    /// Generators created with the same seed produce the same sequence.
+    pub fn new(seed: u64) -> Self {
+        // SplitMix64 scrambles the seed so that nearby seeds give
+        // unrelated sequences, it also never produces a zero state here.
+        let mut z = seed.wrapping_add(0x9E3779B97F4A7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^= z >> 31;
+
+        Rng {
+            state: if z == 0 { 0x9E3779B97F4A7C15 } else { z },
+        }
+    }
+
+    /// Returns the next random `u64`
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545F4914F6CDD1D)
+    }
+
+    /// Returns a float drawn uniformly from `[0, 1)`
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Returns a float drawn uniformly from `[low, high)`
+    pub fn range(&mut self, low: f64, high: f64) -> f64 {
+        low + (high - low) * self.next_f64()
+    }
+
+    /// Returns a float drawn from the standard normal distribution
+    pub fn normal(&mut self) -> f64 {
+        // Box-Muller, with 1 - u in (0, 1] so the logarithm is finite
+        let u = 1.0 - self.next_f64();
+        let v = self.next_f64();
+        (-2.0 * u.ln()).sqrt() * (2.0 * ::std::f64::consts::PI * v).cos()
+    }
+
+    /// Returns an integer drawn uniformly from `0..n`
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `n` is zero.
+    pub fn below(&mut self, n: usize) -> usize {
+        assert!(n > 0, "Cannot sample from an empty range");
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reproducible() {
+        let mut a = Rng::new(42);
+        let mut b = Rng::new(42);
+        let mut c = Rng::new(43);
+        let xs: Vec<u64> = (0..10).map(|_| a.next_u64()).collect();
+        let ys: Vec<u64> = (0..10).map(|_| b.next_u64()).collect();
+        let zs: Vec<u64> = (0..10).map(|_| c.next_u64()).collect();
+        assert_eq!(xs, ys);
+        assert!(xs != zs);
+    }
+
+    #[test]
+    fn test_ranges() {
+        let mut rng = Rng::new(0);
+        for _ in 0..1000 {
+            let x = rng.range(-2.0, 3.0);
+            assert!((-2.0..3.0).contains(&x));
+            assert!(rng.below(7) < 7);
+        }
+    }
+
+    #[test]
+    fn test_normal() {
+        let mut rng = Rng::new(7);
+        let xs: Vec<f64> = (0..20000).map(|_| rng.normal()).collect();
+        let mean = xs.iter().sum::<f64>() / xs.len() as f64;
+        let var = xs.iter().map(|x| (x - mean) * (x - mean)).sum::<f64>() / xs.len() as f64;
+        assert!(mean.abs() < 0.03);
+        assert!((var - 1.0).abs() < 0.05);
+    }
+}
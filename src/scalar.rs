@@ -0,0 +1,183 @@
+//! Scalar module
+//!
+//! The `Scalar` trait collects the elementary functions needed by the
+//! operations in `functions`. It is implemented for the real floats and
+//! for `num::Complex` so that both can be used as the value type of an
+//! expression.
+//!
+//! The operations in `functions` used to accept any `num::Float`. They
+//! now require `Scalar`, so a downstream float type must implement it.
+//! The exported `real_scalar!` macro does this through the type's `Float`
+//! implementation:
+//!
+//! ```ignore
+//! #[macro_use]
+//! extern crate rugrads;
+//!
+//! real_scalar!(MyFloat);
+//! ```
+//!
+//! The `Seed` trait provides the values `Gradient::grad` needs to start
+//! and finish back propagation.
+
+use std::ops;
+
+use num::{Complex, Num, One, Zero};
+
+/// A type which can seed back propagation
+///
+/// This is implemented for every type with a `Zero` and a `One`. Types
+/// whose zero and one depend on a shape, such as arrays and matrices,
+/// can implement it directly.
+pub trait Seed: Sized {
+    /// The gradient of an output with respect to itself
+    fn seed(output: &Self) -> Self;
+
+    /// The gradient with respect to a variable the output does not depend on
+    fn zero_grad(var: &Self) -> Self;
+}
+
+impl<T: Zero + One> Seed for T {
+    fn seed(_: &T) -> T {
+        T::one()
+    }
+
+    fn zero_grad(_: &T) -> T {
+        T::zero()
+    }
+}
+
+/// A number type with the elementary functions
+///
+/// For complex numbers every function is the principal branch
+/// from `num::Complex`.
+pub trait Scalar: Copy + Num + ops::Neg<Output=Self> {
+    /// Sine
+    fn sin(self) -> Self;
+    /// Cosine
+    fn cos(self) -> Self;
+    /// Exponential
+    fn exp(self) -> Self;
+    /// Natural Logarithm
+    fn ln(self) -> Self;
+    /// Raises `self` to the power `n`
+    fn powf(self, n: Self) -> Self;
+}
+
+#[doc(hidden)]
+pub use num::Float as __Float;
+
+/// Implements `Scalar` for a type through its `num::Float` implementation
+#[macro_export]
+macro_rules! real_scalar {
+    ($t: ty) => {
+impl $crate::Scalar for $t {
+    fn sin(self) -> Self { $crate::scalar::__Float::sin(self) }
+    fn cos(self) -> Self { $crate::scalar::__Float::cos(self) }
+    fn exp(self) -> Self { $crate::scalar::__Float::exp(self) }
+    fn ln(self) -> Self { $crate::scalar::__Float::ln(self) }
+    fn powf(self, n: Self) -> Self { $crate::scalar::__Float::powf(self, n) }
+}
+    };
+}
+
+macro_rules! complex_scalar {
+    ($t: ty) => {
+impl Scalar for Complex<$t> {
+    fn sin(self) -> Self { Complex::sin(&self) }
+    fn cos(self) -> Self { Complex::cos(&self) }
+    fn exp(self) -> Self { Complex::exp(&self) }
+    fn ln(self) -> Self { Complex::ln(&self) }
+    fn powf(self, n: Self) -> Self {
+        // Keep real exponents exact, `powc` goes through `exp(n ln z)`
+        if n.im == 0.0 {
+            Complex::powf(&self, n.re)
+        } else {
+            Complex::powc(&self, n)
+        }
+    }
+}
+    };
+}
+
+real_scalar!(f32);
+real_scalar!(f64);
+complex_scalar!(f32);
+complex_scalar!(f64);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use num::rational::Ratio;
+
+    use ::{Context, Gradient};
+
+    #[test]
+    fn test_rational_grad() {
+        let mut c = Context::new();
+        let x = c.create_variable(Ratio::new(1i64, 2));
+        let y = c.create_variable(Ratio::new(2i64, 3));
+        let f = x * y / (x + y);
+
+        // f = xy / (x + y), df/dx = y^2 / (x + y)^2 = 16/49
+        let mut g = Gradient::of(f, c);
+        assert_eq!(g.grad(&x), Ratio::new(16, 49));
+        assert_eq!(g.grad(&y), Ratio::new(9, 49));
+    }
+
+    // A pair of numbers with elementwise arithmetic
+    #[derive(Clone, Debug, PartialEq)]
+    struct Pair(f64, f64);
+
+    impl<'a, 'b> ops::Add<&'b Pair> for &'a Pair {
+        type Output = Pair;
+        fn add(self, rhs: &Pair) -> Pair { Pair(self.0 + rhs.0, self.1 + rhs.1) }
+    }
+
+    impl ops::Add for Pair {
+        type Output = Pair;
+        fn add(self, rhs: Pair) -> Pair { &self + &rhs }
+    }
+
+    impl<'a, 'b> ops::Mul<&'b Pair> for &'a Pair {
+        type Output = Pair;
+        fn mul(self, rhs: &Pair) -> Pair { Pair(self.0 * rhs.0, self.1 * rhs.1) }
+    }
+
+    impl<'a, 'b> ops::Div<&'b Pair> for &'a Pair {
+        type Output = Pair;
+        fn div(self, rhs: &Pair) -> Pair { Pair(self.0 / rhs.0, self.1 / rhs.1) }
+    }
+
+    impl ops::Neg for Pair {
+        type Output = Pair;
+        fn neg(self) -> Pair { Pair(-self.0, -self.1) }
+    }
+
+    impl Seed for Pair {
+        fn seed(_: &Pair) -> Pair { Pair(1.0, 1.0) }
+        fn zero_grad(_: &Pair) -> Pair { Pair(0.0, 0.0) }
+    }
+
+    #[test]
+    fn test_custom_seed() {
+        let mut c = Context::new();
+        let x = c.create_variable(Pair(2.0, 4.0));
+        let y = c.create_variable(Pair(1.0, 0.5));
+        let z = c.create_variable(Pair(3.0, 3.0));
+
+        let mut g = Gradient::of(x * x / y, c);
+        assert_eq!(g.grad(&x), Pair(4.0, 16.0));
+        assert_eq!(g.grad(&y), Pair(-4.0, -64.0));
+        assert_eq!(g.grad(&z), Pair(0.0, 0.0));
+    }
+
+    #[test]
+    fn test_complex_matches_real() {
+        let x = 0.7f64;
+        let z = Complex::new(x, 0.0);
+        assert!((Scalar::sin(z).re - x.sin()).abs() < 1e-12);
+        assert!((Scalar::ln(z).re - x.ln()).abs() < 1e-12);
+        assert!((Scalar::powf(z, Complex::new(2.5, 0.0)).re - x.powf(2.5)).abs() < 1e-12);
+    }
+}
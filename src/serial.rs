@@ -0,0 +1,705 @@
+//! Serial module
+//!
+//! Reads and writes expressions in a JSON based text format.
+//!
+//! Each operation is written as an object holding the operation name,
+//! its arguments and any constants it needs. Variables are written
+//! using their index into the `Context`, so an expression should be
+//! loaded alongside a context holding the same variables. Evaluating
+//! an expression in a context without one of its variables panics, so
+//! use `from_str_in` to check the indices when reading untrusted input.
+//!
+//! ```text
+//! {"format":"rugrads","version":1,"expr":
+//!     {"op":"mul","args":[{"var":0},{"op":"sin","args":[{"const":0.5}]}]}}
+//! ```
+//!
+//! Constants are written by a `Codec`. This allows crates which use
+//! other value types to reuse the format for their own operations.
+//!
+//! # Example
+//!
+//! ```
+//! use rugrads::{Context, Gradient};
+//! use rugrads::functions::*;
+//! use rugrads::serial;
+//!
+//! let mut context = Context::new();
+//! let x = context.create_variable(0.5);
+//! let f = x * sin(x);
+//!
+//! let text = serial::to_string(&f);
+//! let g = serial::from_str::<f64>(&text).unwrap();
+//!
+//! let mut grad = Gradient::of(g, context);
+//! grad.grad(&x);
+//! ```
+
+use std::error;
+use std::fmt;
+
+use num::Float;
+
+use ::{Container, Context, Expression, Variable, LeafVar};
+use ::term::{Func, Func2, Term};
+
+/// The version of the format written by this module
+pub const VERSION: u64 = 1;
+
+/// The deepest nesting of arrays and objects accepted by `Json::parse`
+pub const MAX_DEPTH: usize = 1024;
+
+/// An error produced while reading the serial format
+#[derive(Clone, Debug, PartialEq)]
+pub struct Error {
+    msg: String,
+}
+
+impl Error {
+    /// Creates a new error with the given message
+    pub fn new(msg: &str) -> Error {
+        Error {
+            msg: msg.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.msg)
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        &self.msg
+    }
+}
+
+/// A JSON value
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    /// The null value
+    Null,
+    /// A boolean
+    Bool(bool),
+    /// A number
+    Number(f64),
+    /// A string
+    String(String),
+    /// An array of values
+    Array(Vec<Json>),
+    /// An object, with keys kept in the order they were written
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// Creates an object from key value pairs
+    pub fn object(fields: Vec<(&str, Json)>) -> Json {
+        Json::Object(fields.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+    }
+
+    /// Creates the object describing an operation
+    pub fn op(name: &str, args: Vec<Json>) -> Json {
+        Json::object(vec![("op", Json::String(name.to_string())), ("args", Json::Array(args))])
+    }
+
+    /// Creates a number, writing non-finite values as strings
+    pub fn float(v: f64) -> Json {
+        if v.is_finite() {
+            Json::Number(v)
+        } else if v.is_nan() {
+            Json::String("NaN".to_string())
+        } else if v > 0.0 {
+            Json::String("inf".to_string())
+        } else {
+            Json::String("-inf".to_string())
+        }
+    }
+
+    /// Adds a field to an object
+    ///
+    /// # Panics
+    ///
+    /// Panics if this value is not an object.
+    pub fn with(mut self, key: &str, value: Json) -> Json {
+        match self {
+            Json::Object(ref mut fields) => fields.push((key.to_string(), value)),
+            _ => panic!("Fields can only be added to objects"),
+        }
+        self
+    }
+
+    /// Looks up a key in an object
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match *self {
+            Json::Object(ref fields) => fields.iter().find(|f| f.0 == key).map(|f| &f.1),
+            _ => None,
+        }
+    }
+
+    /// Looks up a key in an object, returning an error if it is missing
+    pub fn field(&self, key: &str) -> Result<&Json, Error> {
+        self.get(key).ok_or_else(|| Error::new(&format!("Missing field `{}`", key)))
+    }
+
+    /// Returns the number held by this value
+    ///
+    /// Numbers written by `Json::float` are also accepted.
+    pub fn as_f64(&self) -> Result<f64, Error> {
+        match *self {
+            Json::Number(v) => Ok(v),
+            Json::String(ref s) if s == "NaN" => Ok(f64::NAN),
+            Json::String(ref s) if s == "inf" => Ok(f64::INFINITY),
+            Json::String(ref s) if s == "-inf" => Ok(f64::NEG_INFINITY),
+            _ => Err(Error::new("Expected a number")),
+        }
+    }
+
+    /// Returns the non-negative integer held by this value
+    pub fn as_u64(&self) -> Result<u64, Error> {
+        match *self {
+            Json::Number(v) if v >= 0.0 && v.fract() == 0.0 => Ok(v as u64),
+            _ => Err(Error::new("Expected a non-negative integer")),
+        }
+    }
+
+    /// Returns the string held by this value
+    pub fn as_str(&self) -> Result<&str, Error> {
+        match *self {
+            Json::String(ref s) => Ok(s),
+            _ => Err(Error::new("Expected a string")),
+        }
+    }
+
+    /// Returns the elements of an array
+    pub fn as_array(&self) -> Result<&[Json], Error> {
+        match *self {
+            Json::Array(ref xs) => Ok(xs),
+            _ => Err(Error::new("Expected an array")),
+        }
+    }
+
+    /// Parses a JSON value from a string
+    ///
+    /// Values nested more than `MAX_DEPTH` deep are rejected.
+    pub fn parse(s: &str) -> Result<Json, Error> {
+        let mut parser = Parser {
+            chars: s.chars().collect(),
+            pos: 0,
+            depth: 0,
+        };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        if parser.pos != parser.chars.len() {
+            return Err(parser.error("Unexpected trailing characters"));
+        }
+        Ok(value)
+    }
+}
+
+fn write_str(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for ch in s.chars() {
+        match ch {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            // Debug formatting gives the shortest representation which round trips
+            Json::Number(v) => write!(f, "{:?}", v),
+            Json::String(ref s) => write_str(f, s),
+            Json::Array(ref xs) => {
+                write!(f, "[")?;
+                for (i, x) in xs.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", x)?;
+                }
+                write!(f, "]")
+            },
+            Json::Object(ref fields) => {
+                write!(f, "{{")?;
+                for (i, (k, v)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_str(f, k)?;
+                    write!(f, ":{}", v)?;
+                }
+                write!(f, "}}")
+            },
+        }
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    // The number of arrays and objects currently open
+    depth: usize,
+}
+
+impl Parser {
+    fn error(&self, msg: &str) -> Error {
+        Error::new(&format!("{} at character {}", msg, self.pos))
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.pos < self.chars.len() && self.chars[self.pos].is_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.chars.get(self.pos).cloned()
+    }
+
+    fn expect(&mut self, ch: char) -> Result<(), Error> {
+        if self.peek() == Some(ch) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.error(&format!("Expected `{}`", ch)))
+        }
+    }
+
+    fn keyword(&mut self, word: &str, value: Json) -> Result<Json, Error> {
+        let end = self.pos + word.len();
+        if end <= self.chars.len() && self.chars[self.pos..end].iter().cloned().eq(word.chars()) {
+            self.pos = end;
+            Ok(value)
+        } else {
+            Err(self.error("Unexpected token"))
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, Error> {
+        match self.peek() {
+            Some('{') => self.nested(Parser::object),
+            Some('[') => self.nested(Parser::array),
+            Some('"') => self.string().map(Json::String),
+            Some('t') => self.keyword("true", Json::Bool(true)),
+            Some('f') => self.keyword("false", Json::Bool(false)),
+            Some('n') => self.keyword("null", Json::Null),
+            Some(c) if c == '-' || c.is_ascii_digit() => self.number(),
+            Some(_) => Err(self.error("Unexpected character")),
+            None => Err(self.error("Unexpected end of input")),
+        }
+    }
+
+    fn nested<F: FnOnce(&mut Parser) -> Result<Json, Error>>(&mut self, f: F) -> Result<Json, Error> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error("Too deeply nested"));
+        }
+        self.depth += 1;
+        let value = f(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn object(&mut self) -> Result<Json, Error> {
+        self.expect('{')?;
+        let mut fields = vec![];
+        if self.peek() == Some('}') {
+            self.pos += 1;
+            return Ok(Json::Object(fields));
+        }
+        loop {
+            if self.peek() != Some('"') {
+                return Err(self.error("Expected a key"));
+            }
+            let key = self.string()?;
+            self.expect(':')?;
+            fields.push((key, self.value()?));
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some('}') => {
+                    self.pos += 1;
+                    return Ok(Json::Object(fields));
+                },
+                _ => return Err(self.error("Expected `,` or `}`")),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Json, Error> {
+        self.expect('[')?;
+        let mut xs = vec![];
+        if self.peek() == Some(']') {
+            self.pos += 1;
+            return Ok(Json::Array(xs));
+        }
+        loop {
+            xs.push(self.value()?);
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some(']') => {
+                    self.pos += 1;
+                    return Ok(Json::Array(xs));
+                },
+                _ => return Err(self.error("Expected `,` or `]`")),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, Error> {
+        self.expect('"')?;
+        let mut s = String::new();
+        loop {
+            let ch = match self.chars.get(self.pos) {
+                Some(&ch) => ch,
+                None => return Err(self.error("Unterminated string")),
+            };
+            self.pos += 1;
+            match ch {
+                '"' => return Ok(s),
+                '\\' => {
+                    let esc = match self.chars.get(self.pos) {
+                        Some(&esc) => esc,
+                        None => return Err(self.error("Unterminated string")),
+                    };
+                    self.pos += 1;
+                    match esc {
+                        '"' => s.push('"'),
+                        '\\' => s.push('\\'),
+                        '/' => s.push('/'),
+                        'n' => s.push('\n'),
+                        'r' => s.push('\r'),
+                        't' => s.push('\t'),
+                        'b' => s.push('\u{8}'),
+                        'f' => s.push('\u{c}'),
+                        'u' => {
+                            let end = self.pos + 4;
+                            if end > self.chars.len() {
+                                return Err(self.error("Invalid unicode escape"));
+                            }
+                            let hex: String = self.chars[self.pos..end].iter().cloned().collect();
+                            let code = u32::from_str_radix(&hex, 16)
+                                .map_err(|_| self.error("Invalid unicode escape"))?;
+                            s.push(::std::char::from_u32(code).unwrap_or('\u{fffd}'));
+                            self.pos = end;
+                        },
+                        _ => return Err(self.error("Invalid escape")),
+                    }
+                },
+                ch => s.push(ch),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, Error> {
+        let start = self.pos;
+        while self.pos < self.chars.len() {
+            match self.chars[self.pos] {
+                '0'..='9' | '-' | '+' | '.' | 'e' | 'E' => self.pos += 1,
+                _ => break,
+            }
+        }
+        let text: String = self.chars[start..self.pos].iter().cloned().collect();
+        text.parse::<f64>()
+            .map(Json::Number)
+            .map_err(|_| Error::new(&format!("Invalid number at character {}", start)))
+    }
+}
+
+/// Converts constant values to and from JSON
+pub trait Codec<T> {
+    /// Writes a constant
+    fn encode_const(&self, value: &T) -> Json;
+
+    /// Reads a constant
+    fn decode_const(&self, json: &Json) -> Result<T, Error>;
+}
+
+/// The `Codec` for `Float` scalars
+#[derive(Clone, Copy, Debug)]
+pub struct Scalars;
+
+impl<T: Float> Codec<T> for Scalars {
+    fn encode_const(&self, value: &T) -> Json {
+        Json::float(value.to_f64().unwrap_or(f64::NAN))
+    }
+
+    fn decode_const(&self, json: &Json) -> Result<T, Error> {
+        json.as_f64().and_then(|v| T::from(v).ok_or_else(|| Error::new("Constant out of range")))
+    }
+}
+
+/// Expressions which can be written in the serial format
+///
+/// The `Codec` is used to write any constants held by the expression.
+pub trait Encode<C> {
+    /// Returns the JSON describing this expression
+    fn encode(&self, codec: &C) -> Json;
+}
+
+impl<C> Encode<C> for Variable {
+    fn encode(&self, _: &C) -> Json {
+        Json::object(vec![("var", Json::Number(self.0 as f64))])
+    }
+}
+
+impl<T, C: Codec<T>> Encode<C> for LeafVar<T> {
+    fn encode(&self, codec: &C) -> Json {
+        Json::object(vec![("const", codec.encode_const(&self.0))])
+    }
+}
+
+impl<T, C, E: Expression<T> + Encode<C>> Encode<C> for Container<T, E> {
+    fn encode(&self, codec: &C) -> Json {
+        self.inner.encode(codec)
+    }
+}
+
+impl<C, E: Encode<C> + ?Sized> Encode<C> for Box<E> {
+    fn encode(&self, codec: &C) -> Json {
+        (**self).encode(codec)
+    }
+}
+
+impl<T, C: Codec<T>> Encode<C> for Term<T> {
+    fn encode(&self, codec: &C) -> Json {
+        let args = self.args().iter().map(|a| a.encode(codec)).collect();
+        match *self {
+            Term::Var(ref v) => Encode::<C>::encode(v, codec),
+            Term::Const(ref v) => Json::object(vec![("const", codec.encode_const(v))]),
+            Term::Add(..) => Json::op("add", args),
+            Term::Sub(..) => Json::op("sub", args),
+            Term::Mul(..) => Json::op("mul", args),
+            Term::Div(..) => Json::op("div", args),
+            Term::Neg(..) => Json::op("neg", args),
+            Term::Powf(_, ref n) => Json::op("powf", args).with("n", codec.encode_const(n)),
+            Term::Apply(Func::Polygamma(n), _) => {
+                Json::op("polygamma", args).with("n", Json::Number(n as f64))
+            },
+            Term::Apply(Func::Powi(n), _) => Json::op("powi", args).with("n", Json::Number(n as f64)),
+            Term::Apply(f, _) => Json::op(f.name(), args),
+            Term::Apply2(f, _, _) => Json::op(f.name(), args),
+        }
+    }
+}
+
+/// Reads the `Variable` described by a JSON value
+pub fn decode_variable(json: &Json) -> Result<Variable, Error> {
+    json.as_u64().map(|idx| Variable(idx as usize))
+}
+
+/// Returns the name and arguments of a JSON operation with the expected arity
+pub fn decode_op(json: &Json, arity: usize) -> Result<(&str, &[Json]), Error> {
+    let name = json.field("op")?.as_str()?;
+    let args = json.field("args")?.as_array()?;
+    if args.len() != arity {
+        return Err(Error::new(&format!("Operation `{}` expects {} arguments but got {}",
+                                       name, arity, args.len())));
+    }
+    Ok((name, args))
+}
+
+/// Reads a `Term` from its JSON description
+pub fn decode<T, C: Codec<T>>(json: &Json, codec: &C) -> Result<Term<T>, Error> {
+    if let Some(v) = json.get("var") {
+        return decode_variable(v).map(Term::Var);
+    }
+    if let Some(v) = json.get("const") {
+        return codec.decode_const(v).map(Term::Const);
+    }
+
+    let name = json.field("op")?.as_str()?;
+    let arity = match name {
+        "add" | "sub" | "mul" | "div" => 2,
+        _ if Func2::from_name(name).is_some() => 2,
+        _ => 1,
+    };
+    let (_, args) = decode_op(json, arity)?;
+    let arg = |i: usize| decode(&args[i], codec).map(Box::new);
+
+    match name {
+        "add" => Ok(Term::Add(arg(0)?, arg(1)?)),
+        "sub" => Ok(Term::Sub(arg(0)?, arg(1)?)),
+        "mul" => Ok(Term::Mul(arg(0)?, arg(1)?)),
+        "div" => Ok(Term::Div(arg(0)?, arg(1)?)),
+        "neg" => Ok(Term::Neg(arg(0)?)),
+        "powf" => Ok(Term::Powf(arg(0)?, codec.decode_const(json.field("n")?)?)),
+        "polygamma" => match json.field("n")?.as_u64()? {
+            n if (2..=u32::MAX as u64).contains(&n) => Ok(Term::Apply(Func::Polygamma(n as u32), arg(0)?)),
+            n => Err(Error::new(&format!("Invalid polygamma order {}", n))),
+        },
+        "powi" => match *json.field("n")? {
+            Json::Number(n) if n.fract() == 0.0 && n.abs() <= i32::MAX as f64 => {
+                Ok(Term::Apply(Func::Powi(n as i32), arg(0)?))
+            },
+            _ => Err(Error::new("Expected an integer exponent")),
+        },
+        _ => match (Func::from_name(name), Func2::from_name(name)) {
+            (Some(f), _) => Ok(Term::Apply(f, arg(0)?)),
+            (_, Some(f)) => Ok(Term::Apply2(f, arg(0)?, arg(1)?)),
+            _ => Err(Error::new(&format!("Unknown operation `{}`", name))),
+        },
+    }
+}
+
+/// Wraps an encoded expression in a versioned document
+pub fn document(expr: Json) -> Json {
+    Json::object(vec![("format", Json::String("rugrads".to_string())),
+                      ("version", Json::Number(VERSION as f64)),
+                      ("expr", expr)])
+}
+
+/// Returns the encoded expression held by a versioned document
+pub fn read_document(doc: &Json) -> Result<&Json, Error> {
+    if doc.field("format")?.as_str()? != "rugrads" {
+        return Err(Error::new("Not a rugrads document"));
+    }
+    let version = doc.field("version")?.as_u64()?;
+    if version > VERSION {
+        return Err(Error::new(&format!("Unsupported format version {}", version)));
+    }
+    doc.field("expr")
+}
+
+/// Writes an expression over `Float` scalars as a string
+pub fn to_string<T, E>(expr: &Container<T, E>) -> String
+    where T: Float,
+          E: Expression<T> + Encode<Scalars>
+{
+    document(expr.encode(&Scalars)).to_string()
+}
+
+/// Reads an expression over `Float` scalars from a string
+///
+/// The variable indices are not checked, see `from_str_in`.
+pub fn from_str<T: Float>(s: &str) -> Result<Container<T, Term<T>>, Error> {
+    let doc = Json::parse(s)?;
+    decode(read_document(&doc)?, &Scalars).map(Container::new)
+}
+
+/// Reads an expression over `Float` scalars from a string, checking that
+/// each of its variables is in `context`
+pub fn from_str_in<T: Float>(s: &str, context: &Context<T>) -> Result<Container<T, Term<T>>, Error> {
+    let expr = from_str(s)?;
+    check_variables(expr.inner(), context.variables().len())?;
+    Ok(expr)
+}
+
+fn check_variables<T>(term: &Term<T>, count: usize) -> Result<(), Error> {
+    match *term {
+        Term::Var(v) if v.0 >= count => {
+            Err(Error::new(&format!("Variable {} is not in a context with {} variables", v.0, count)))
+        },
+        _ => term.args().into_iter().try_for_each(|a| check_variables(a, count)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::{Context, Expression, Gradient, LeafVar};
+    use ::functions::*;
+
+    #[test]
+    fn test_json_round_trip() {
+        let json = Json::object(vec![("a", Json::Array(vec![Json::Number(-1.5e-7), Json::Null])),
+                                     ("b \"quoted\"\n", Json::Bool(true)),
+                                     ("c", Json::float(f64::INFINITY))]);
+        let text = json.to_string();
+        assert_eq!(Json::parse(&text).unwrap(), json);
+        assert_eq!(json.get("c").unwrap().as_f64().unwrap(), f64::INFINITY);
+    }
+
+    #[test]
+    fn test_json_errors() {
+        assert!(Json::parse("{\"a\": }").is_err());
+        assert!(Json::parse("[1, 2").is_err());
+        assert!(Json::parse("[1] 2").is_err());
+        assert!(Json::parse("\"abc").is_err());
+
+        let nested = |n| format!("{}{}", "[".repeat(n), "]".repeat(n));
+        assert!(Json::parse(&nested(MAX_DEPTH)).is_ok());
+        assert!(Json::parse(&nested(MAX_DEPTH + 1)).is_err());
+        assert!(Json::parse(&"[".repeat(1_000_000)).is_err());
+    }
+
+    #[test]
+    fn test_expression_round_trip() {
+        let mut c = Context::new();
+        let x = c.create_variable(0.5);
+        let y = c.create_variable(0.3);
+        let f = y * sin(x) + cos(y) / exp(x) - powf(ln(y), 3.0) * Container::new(LeafVar(0.1))
+            + atan2(y, tanh(x)) * acosh(exp(x)) - hypot(x, log(y, exp2(x))) + pow(x, y) * powi(y, -2);
+
+        let text = to_string(&f);
+        let g = from_str::<f64>(&text).unwrap();
+        assert_eq!(to_string(&g), text);
+        assert!((g.eval(&mut c).value - f.eval(&mut c).value).abs() < 1e-12);
+
+        let mut c2 = Context::new();
+        c2.create_variable(0.5);
+        c2.create_variable(0.3);
+        let mut grad_f = Gradient::of(f, c);
+        let mut grad_g = Gradient::of(g, c2);
+        assert!((grad_f.grad(&x) - grad_g.grad(&x)).abs() < 1e-12);
+        assert!((grad_f.grad(&y) - grad_g.grad(&y)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_parameterised_func_round_trip() {
+        let mut c = Context::new();
+        let x = c.create_variable(2.5);
+        let f = Container::new(Term::Apply(Func::Polygamma(3), Box::new(Term::Var(*x.inner()))));
+
+        let text = to_string(&f);
+        let g = from_str::<f64>(&text).unwrap();
+        assert_eq!(g.inner(), f.inner());
+        assert!((g.eval(&mut c).value - ::special::polygamma(3, 2.5)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_polygamma_order_errors() {
+        let doc = |n: &str| {
+            format!("{{\"format\":\"rugrads\",\"version\":1,\"expr\":{{\"op\":\"polygamma\",\"args\":[{{\"var\":0}}],\"n\":{}}}}}", n)
+        };
+        assert!(from_str::<f64>(&doc("2")).is_ok());
+        assert!(from_str::<f64>(&doc("4294967295")).is_ok());
+
+        // Orders below two are written as digamma and trigamma
+        assert!(from_str::<f64>(&doc("0")).is_err());
+        assert!(from_str::<f64>(&doc("1")).is_err());
+        // Orders which do not fit in a u32 must not wrap
+        assert!(from_str::<f64>(&doc("4294967296")).is_err());
+        assert!(from_str::<f64>(&doc("4294967298")).is_err());
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let unknown = "{\"format\":\"rugrads\",\"version\":1,\"expr\":{\"op\":\"foo\",\"args\":[]}}";
+        assert!(from_str::<f64>(unknown).is_err());
+
+        let arity = "{\"format\":\"rugrads\",\"version\":1,\"expr\":{\"op\":\"add\",\"args\":[{\"var\":0}]}}";
+        assert!(from_str::<f64>(arity).is_err());
+
+        let version = "{\"format\":\"rugrads\",\"version\":99,\"expr\":{\"var\":0}}";
+        assert!(from_str::<f64>(version).is_err());
+
+        let mut c = Context::new();
+        let x = c.create_variable(0.5);
+        let text = to_string(&(x * sin(x)));
+        assert!(from_str_in::<f64>(&text, &c).is_ok());
+        assert!(from_str_in::<f64>(&text, &Context::new()).is_err());
+    }
+}
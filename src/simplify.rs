@@ -0,0 +1,327 @@
+//! Simplify module
+//!
+//! Rewrites `Term`s using algebraic identities and constant folding
+//! so that they produce fewer nodes when evaluated.
+//!
+//! # Example
+//!
+//! ```
+//! use rugrads::{Container, Context, LeafVar};
+//! use rugrads::functions::*;
+//! use rugrads::simplify::simplify;
+//!
+//! let mut context = Context::new();
+//! let x = context.create_variable(0.5);
+//! let one = Container::new(LeafVar(1.0));
+//!
+//! // ln(exp(x)) * (2 - 1) simplifies to x
+//! let f = ln(exp(x)) * (one + one - one);
+//! let s = simplify(&f);
+//! ```
+
+use num::Float;
+
+use ::{Container, Context, Expression};
+use ::term::{Func, Term, ToTerm};
+
+/// A rewrite rule for terms
+///
+/// Rules only need to consider the top level of the term they are given,
+/// the `Simplifier` takes care of applying them throughout the tree.
+///
+/// Any function `Fn(&Term<T>) -> Option<Term<T>>` is a `Rule`.
+pub trait Rule<T> {
+    /// Returns the rewritten term, or `None` if the rule does not apply
+    fn rewrite(&self, term: &Term<T>) -> Option<Term<T>>;
+}
+
+impl<T, F> Rule<T> for F
+    where F: Fn(&Term<T>) -> Option<Term<T>>
+{
+    fn rewrite(&self, term: &Term<T>) -> Option<Term<T>> {
+        self(term)
+    }
+}
+
+/// Applies a set of rewrite rules to a term until it stops changing
+pub struct Simplifier<T> {
+    rules: Vec<Box<dyn Rule<T>>>,
+    max_passes: usize,
+}
+
+impl<T: Float + 'static> Simplifier<T> {
+    /// Creates a `Simplifier` using the default rules
+    ///
+    /// These are `fold_constants` and `identities`.
+    pub fn new() -> Self {
+        Simplifier::empty()
+            .with_rule(fold_constants::<T>)
+            .with_rule(identities::<T>)
+    }
+}
+
+impl<T: Float + 'static> Default for Simplifier<T> {
+    fn default() -> Self {
+        Simplifier::new()
+    }
+}
+
+impl<T> Simplifier<T> {
+    /// Creates a `Simplifier` with no rules
+    pub fn empty() -> Self {
+        Simplifier {
+            rules: vec![],
+            max_passes: 32,
+        }
+    }
+
+    /// Adds a rule to the simplifier
+    ///
+    /// Rules are tried in the order they were added.
+    pub fn with_rule<R: Rule<T> + 'static>(mut self, rule: R) -> Self {
+        self.rules.push(Box::new(rule));
+        self
+    }
+
+    /// Sets the maximum number of passes made over the term
+    pub fn max_passes(mut self, max_passes: usize) -> Self {
+        self.max_passes = max_passes;
+        self
+    }
+
+    /// Simplifies the term
+    ///
+    /// Each pass rewrites the arguments of a term before the term itself.
+    /// Passes are repeated until no rule applies or `max_passes` is reached.
+    pub fn simplify(&self, term: Term<T>) -> Term<T> {
+        let mut term = term;
+        for _ in 0..self.max_passes {
+            let mut changed = false;
+            term = self.pass(term, &mut changed);
+            if !changed {
+                break;
+            }
+        }
+        term
+    }
+
+    fn pass(&self, term: Term<T>, changed: &mut bool) -> Term<T> {
+        let term = term.map_args(|a| self.pass(a, changed));
+        for rule in self.rules.iter() {
+            if let Some(t) = rule.rewrite(&term) {
+                *changed = true;
+                return t;
+            }
+        }
+        term
+    }
+}
+
+/// Simplifies an expression using the default rules
+///
+/// The returned expression computes the same value and gradient as
+/// the input, but will typically create fewer nodes when evaluated.
+pub fn simplify<T, E>(expr: &Container<T, E>) -> Container<T, Term<T>>
+    where T: Float + 'static,
+          E: Expression<T> + ToTerm<T>
+{
+    Container::new(Simplifier::new().simplify(expr.to_term()))
+}
+
+/// Replaces a term which does not depend on any variables by its value
+pub fn fold_constants<T: Float>(term: &Term<T>) -> Option<Term<T>> {
+    if term.is_const() || !term.is_constant() {
+        return None;
+    }
+
+    Some(Term::Const(term.value(&Context::new())))
+}
+
+fn is_value<T: Float>(term: &Term<T>, value: T) -> bool {
+    match *term {
+        Term::Const(v) => v == value,
+        _ => false,
+    }
+}
+
+fn is_finite_const<T: Float>(term: &Term<T>) -> bool {
+    match *term {
+        Term::Const(v) => v.is_finite(),
+        _ => false,
+    }
+}
+
+/// Applies algebraic identities
+///
+/// These include `x + 0 = x`, `x * 1 = x`, `-(-x) = x`, `powf(x, 1) = x`
+/// and `ln(exp(x)) = x`. `x * 0 = 0` and `x - x = 0` are only applied when
+/// `x` is a finite constant, as they are NaN rather than zero for infinite
+/// or NaN `x`. `exp(ln(x))` is left alone as it is NaN rather than `x` for
+/// `x <= 0`.
+pub fn identities<T: Float>(term: &Term<T>) -> Option<Term<T>> {
+    let zero = T::zero();
+    let one = T::one();
+
+    match *term {
+        Term::Add(ref x, ref y) => {
+            if is_value(y, zero) {
+                Some((**x).clone())
+            } else if is_value(x, zero) {
+                Some((**y).clone())
+            } else if let Term::Neg(ref y) = **y {
+                Some(Term::Sub(x.clone(), y.clone()))
+            } else {
+                None
+            }
+        },
+        Term::Sub(ref x, ref y) => {
+            if is_value(y, zero) {
+                Some((**x).clone())
+            } else if is_value(x, zero) {
+                Some(Term::Neg(y.clone()))
+            } else if x == y && is_finite_const(x) {
+                Some(Term::Const(zero))
+            } else if let Term::Neg(ref y) = **y {
+                Some(Term::Add(x.clone(), y.clone()))
+            } else {
+                None
+            }
+        },
+        Term::Mul(ref x, ref y) => {
+            if (is_value(x, zero) && is_finite_const(y)) || (is_value(y, zero) && is_finite_const(x)) {
+                Some(Term::Const(zero))
+            } else if is_value(y, one) {
+                Some((**x).clone())
+            } else if is_value(x, one) {
+                Some((**y).clone())
+            } else if is_value(x, -one) {
+                Some(Term::Neg(y.clone()))
+            } else if is_value(y, -one) {
+                Some(Term::Neg(x.clone()))
+            } else {
+                None
+            }
+        },
+        Term::Div(ref x, ref y) => {
+            if is_value(y, one) {
+                Some((**x).clone())
+            } else {
+                None
+            }
+        },
+        Term::Neg(ref x) => {
+            match **x {
+                Term::Neg(ref x) => Some((**x).clone()),
+                _ => None,
+            }
+        },
+        Term::Powf(ref x, n) => {
+            if n == one {
+                Some((**x).clone())
+            } else if n == zero {
+                Some(Term::Const(one))
+            } else {
+                None
+            }
+        },
+        Term::Apply(Func::Ln, ref x) => {
+            match **x {
+                Term::Apply(Func::Exp, ref x) => Some((**x).clone()),
+                _ => None,
+            }
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::{Context, Expression, Gradient, LeafVar};
+    use ::term::Term;
+    use ::functions::*;
+
+    #[test]
+    fn test_identities() {
+        let mut c = Context::new();
+        let x = c.create_variable(0.5);
+        let one = Container::new(LeafVar(1.0));
+        let zero = Container::new(LeafVar(0.0));
+
+        let f = -(-(ln(exp(x)) * one)) + zero;
+        assert_eq!(simplify(&f).into_inner(), Term::Var(*x));
+
+        // exp(ln(x)) is NaN for non-positive x so must be kept
+        let y = c.create_variable(-1.0);
+        let s = simplify(&exp(ln(y)));
+        assert!(s.eval(&mut c).value.is_nan());
+    }
+
+    #[test]
+    fn test_non_finite_kept() {
+        let mut c = Context::new();
+        let x = c.create_variable(f64::INFINITY);
+        let y = c.create_variable(0.0);
+        let zero = Container::new(LeafVar(0.0));
+
+        // ln(0) * 0 and inf - inf are NaN, not zero
+        let s = simplify(&(ln(y) * zero.clone()));
+        assert!(s.eval(&mut c).value.is_nan());
+        let s = simplify(&(zero * ln(y)));
+        assert!(s.eval(&mut c).value.is_nan());
+        let s = simplify(&(x - x));
+        assert!(s.eval(&mut c).value.is_nan());
+
+        // Finite constants are still folded
+        let two = Container::new(LeafVar(2.0));
+        assert_eq!(simplify(&(two.clone() - two.clone())).into_inner(), Term::Const(0.0));
+        assert_eq!(simplify(&((two.clone() - two) * ln(y))).into_inner(),
+                   Term::Mul(Box::new(Term::Const(0.0)), Box::new(Term::Apply(Func::Ln, Box::new(Term::Var(*y))))));
+    }
+
+    #[test]
+    fn test_fold_constants() {
+        let mut c = Context::new();
+        let x = c.create_variable(0.5);
+        let two = Container::new(LeafVar(2.0));
+        let three = Container::new(LeafVar(3.0));
+
+        let f = x * (two + three * sin(two));
+        let s = simplify(&f);
+        match *s.inner() {
+            Term::Mul(_, ref y) => assert!(y.is_const()),
+            _ => panic!("Expected a product"),
+        }
+        assert!((s.eval(&mut c).value - f.eval(&mut c).value).abs() < 1e-10);
+    }
+
+    #[test]
+    fn test_simplified_gradient() {
+        let mut c = Context::new();
+        let x = c.create_variable(0.5);
+        let y = c.create_variable(2.0);
+
+        // The y dependence is removed by simplification
+        let f = x * sin(x) + powf(y, 0.0);
+        let s = simplify(&f);
+        assert!(!s.inner().contains(&y));
+
+        let mut g = Gradient::of(s, c);
+        assert!((g.grad(&x) - (f64::sin(0.5) + 0.5 * f64::cos(0.5))).abs() < 1e-10);
+        assert_eq!(g.grad(&y), 0.0);
+    }
+
+    #[test]
+    fn test_custom_rule() {
+        let mut c = Context::new();
+        let x = c.create_variable(0.5);
+
+        // Rewrite x * x as powf(x, 2)
+        let square = |t: &Term<f64>| match *t {
+            Term::Mul(ref a, ref b) if a == b => Some(Term::Powf(a.clone(), 2.0)),
+            _ => None,
+        };
+        let s = Simplifier::new().with_rule(square).simplify((x * x).to_term());
+        assert_eq!(s, Term::Powf(Box::new(Term::Var(*x)), 2.0));
+    }
+}
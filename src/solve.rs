@@ -0,0 +1,400 @@
+//! Solve module
+//!
+//! Finds a root of a scalar expression in one of its variables. The
+//! expression is given as a `Gradient`, which supplies the derivatives,
+//! and the variable is moved in the gradient's context. When a solver
+//! returns the variable holds the best point it found.
+//!
+//! # Example
+//!
+//! ```
+//! use rugrads::{Context, Gradient};
+//! use rugrads::functions::*;
+//! use rugrads::solve::RootFinder;
+//!
+//! let mut context = Context::new();
+//! let x = context.create_variable(1.0f64);
+//! let mut grad = Gradient::of(cos(x) - x, context);
+//!
+//! let solution = RootFinder::new().newton(&mut grad, &x);
+//! assert!(solution.converged());
+//! assert!((solution.root - 0.7390851332151607).abs() < 1e-12);
+//! assert_eq!(*grad.get(&x), solution.root);
+//! ```
+
+use num::Float;
+
+use ::{Expression, Gradient, Variable};
+use ::term::ToTerm;
+
+/// The reason a solver stopped
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Termination {
+    /// The last step was within the step tolerance
+    StepTolerance,
+    /// The value was within the value tolerance
+    ValueTolerance,
+    /// The maximum number of iterations was reached
+    MaxIterations,
+    /// The derivative was zero or not finite
+    ZeroDerivative,
+    /// No step along the Newton direction reduced the value
+    NoProgress,
+    /// The values at the ends of the bracket have the same sign
+    NotBracketed,
+}
+
+/// The result of a root finding
+#[derive(Clone, Copy, Debug)]
+pub struct Solution<T> {
+    /// The best point found
+    pub root: T,
+    /// The value of the expression at `root`
+    pub value: T,
+    /// The number of iterations taken
+    pub iterations: usize,
+    /// The number of times the expression was evaluated
+    pub evaluations: usize,
+    /// Why the solver stopped
+    pub termination: Termination,
+}
+
+impl<T> Solution<T> {
+    /// Returns true if the solver stopped because it met a tolerance
+    pub fn converged(&self) -> bool {
+        self.termination == Termination::StepTolerance || self.termination == Termination::ValueTolerance
+    }
+}
+
+/// Settings for the scalar root finders
+///
+/// A solver stops when `|f(x)| <= ftol` or when its last step was no
+/// larger than `xtol * (1 + |x|)`.
+#[derive(Clone, Copy, Debug)]
+pub struct RootFinder<T> {
+    xtol: T,
+    ftol: T,
+    max_iter: usize,
+    max_halvings: usize,
+}
+
+// Evaluates an expression and its derivatives at points of a variable
+struct Objective<'a, T: 'a, E: 'a + Expression<T>> {
+    grad: &'a mut Gradient<T, E>,
+    x: Variable,
+    evaluations: usize,
+}
+
+impl<'a, T: Float, E: Expression<T>> Objective<'a, T, E> {
+    // Returns f(x) and f'(x) from one forward and backward pass
+    fn eval(&mut self, x: T) -> (T, T) {
+        *self.grad.get_mut(&self.x) = x;
+        self.evaluations += 1;
+        let mut value = T::nan();
+        let deriv = self.grad.accumulate(&self.x, |v| {
+            value = *v;
+            T::one()
+        });
+        (value, deriv.unwrap_or(T::zero()))
+    }
+
+    fn finish(self, root: T, value: T, iterations: usize, termination: Termination) -> Solution<T> {
+        *self.grad.get_mut(&self.x) = root;
+        Solution {
+            root: root,
+            value: value,
+            iterations: iterations,
+            evaluations: self.evaluations,
+            termination: termination,
+        }
+    }
+}
+
+impl<T: Float> RootFinder<T> {
+    /// Creates a root finder
+    ///
+    /// The default tolerances are `xtol = 1e-12` and `ftol = 0`, with
+    /// at most 100 iterations.
+    pub fn new() -> Self {
+        RootFinder {
+            xtol: T::from(1e-12).unwrap(),
+            ftol: T::zero(),
+            max_iter: 100,
+            max_halvings: 30,
+        }
+    }
+
+    /// Sets the step tolerance
+    pub fn xtol(mut self, xtol: T) -> Self {
+        self.xtol = xtol;
+        self
+    }
+
+    /// Sets the value tolerance
+    pub fn ftol(mut self, ftol: T) -> Self {
+        self.ftol = ftol;
+        self
+    }
+
+    /// Sets the maximum number of iterations
+    pub fn max_iter(mut self, max_iter: usize) -> Self {
+        self.max_iter = max_iter;
+        self
+    }
+
+    fn small_step(&self, step: T, x: T) -> bool {
+        step.abs() <= self.xtol * (T::one() + x.abs())
+    }
+
+    // Safeguarded iteration from the current value of `x`, where `step`
+    // gives the full step from the value and derivative at the current
+    // point, which is set in the context
+    fn iterate<E, S>(&self, grad: &mut Gradient<T, E>, x: &Variable, mut step: S) -> Solution<T>
+        where E: Expression<T>, S: FnMut(&mut Gradient<T, E>, T, T) -> T
+    {
+        let start = *grad.get(x);
+        let mut obj = Objective { grad: grad, x: *x, evaluations: 0 };
+        let mut x = start;
+        let (mut f, mut df) = obj.eval(x);
+
+        for iter in 0..self.max_iter {
+            if f.abs() <= self.ftol {
+                return obj.finish(x, f, iter, Termination::ValueTolerance);
+            }
+            if df == T::zero() || !df.is_finite() {
+                return obj.finish(x, f, iter, Termination::ZeroDerivative);
+            }
+
+            let full = step(obj.grad, f, df);
+
+            // Halve the step until |f| decreases
+            let mut t = T::one();
+            let mut accepted = None;
+            for _ in 0..self.max_halvings {
+                let next = x + t * full;
+                let (f_next, df_next) = obj.eval(next);
+                if f_next.abs() < f.abs() || (f_next.abs() == f.abs() && self.small_step(t * full, x)) {
+                    accepted = Some((next, f_next, df_next));
+                    break;
+                }
+                t = t / (T::one() + T::one());
+            }
+
+            match accepted {
+                Some((next, f_next, df_next)) => {
+                    let taken = next - x;
+                    x = next;
+                    f = f_next;
+                    df = df_next;
+                    if self.small_step(taken, x) {
+                        return obj.finish(x, f, iter + 1, Termination::StepTolerance);
+                    }
+                }
+                None => return obj.finish(x, f, iter + 1, Termination::NoProgress),
+            }
+        }
+
+        let termination = if f.abs() <= self.ftol { Termination::ValueTolerance } else { Termination::MaxIterations };
+        obj.finish(x, f, self.max_iter, termination)
+    }
+
+    /// Newton's method from the current value of `x`
+    ///
+    /// Each step is halved until it reduces `|f|`, so the iteration
+    /// cannot diverge, though it can stop at a local minimum of `|f|`
+    /// which is not a root.
+    pub fn newton<E: Expression<T>>(&self, grad: &mut Gradient<T, E>, x: &Variable) -> Solution<T> {
+        self.iterate(grad, x, |_, f, df| -f / df)
+    }
+
+    /// Halley's method from the current value of `x`
+    ///
+    /// This converges cubically near a simple root. The second derivative
+    /// is found by differentiating the expression symbolically, so the
+    /// expression must be convertible to a `Term`. The steps are
+    /// safeguarded as in `newton`.
+    pub fn halley<E>(&self, grad: &mut Gradient<T, E>, x: &Variable) -> Solution<T>
+        where E: Expression<T> + ToTerm<T>
+    {
+        let second = grad.expr().to_term().diff(x).diff(x);
+        self.iterate(grad, x, |grad, f, df| {
+            let d2f = second.value(grad.context());
+            let denom = df * df - f * d2f / (T::one() + T::one());
+            if denom == T::zero() || !denom.is_finite() {
+                -f / df
+            } else {
+                -f * df / denom
+            }
+        })
+    }
+
+    /// Finds a root in the bracket `[a, b]`
+    ///
+    /// This is Brent's method with Newton steps in place of the
+    /// interpolation steps wherever the Newton step stays well inside the
+    /// bracket. It falls back on inverse quadratic interpolation and then
+    /// on bisection, so it always converges to a root of a continuous
+    /// expression when `f(a)` and `f(b)` have opposite signs.
+    pub fn bracketed<E: Expression<T>>(&self, grad: &mut Gradient<T, E>, x: &Variable, (a, b): (T, T))
+        -> Solution<T>
+    {
+        let two = T::one() + T::one();
+        let mut obj = Objective { grad: grad, x: *x, evaluations: 0 };
+        let (mut a, mut b) = (a, b);
+        let (mut fa, _) = obj.eval(a);
+        let (mut fb, mut dfb) = obj.eval(b);
+        if fa == T::zero() {
+            return obj.finish(a, fa, 0, Termination::ValueTolerance);
+        }
+        if fb == T::zero() {
+            return obj.finish(b, fb, 0, Termination::ValueTolerance);
+        }
+        if (fa > T::zero()) == (fb > T::zero()) {
+            let (root, value) = if fa.abs() < fb.abs() { (a, fa) } else { (b, fb) };
+            return obj.finish(root, value, 0, Termination::NotBracketed);
+        }
+
+        // b is the best point, c is on the other side of the root and a
+        // is the previous b
+        let (mut c, mut fc) = (a, fa);
+        let mut d = b - a;
+        let mut e = d;
+        for iter in 0..self.max_iter {
+            if (fb > T::zero()) == (fc > T::zero()) {
+                c = a;
+                fc = fa;
+                d = b - a;
+                e = d;
+            }
+            if fc.abs() < fb.abs() {
+                // The derivative is only known at the latest point, so a
+                // swap leaves b without one
+                a = b;
+                b = c;
+                c = a;
+                fa = fb;
+                fb = fc;
+                fc = fa;
+                dfb = T::nan();
+            }
+
+            let tol = two * T::epsilon() * b.abs() + self.xtol / two;
+            let m = (c - b) / two;
+            if fb.abs() <= self.ftol {
+                return obj.finish(b, fb, iter, Termination::ValueTolerance);
+            }
+            if m.abs() <= tol {
+                return obj.finish(b, fb, iter, Termination::StepTolerance);
+            }
+
+            let mut step = m;
+            if e.abs() >= tol && fa.abs() > fb.abs() {
+                let newton = -fb / dfb;
+                let interpolated = if dfb.is_finite() && dfb != T::zero() {
+                    newton
+                } else if a == c {
+                    // Secant
+                    -fb * (b - a) / (fb - fa)
+                } else {
+                    // Inverse quadratic interpolation
+                    let q = fa / fc;
+                    let r = fb / fc;
+                    let s = fb / fa;
+                    let p = s * (two * m * q * (q - r) - (b - a) * (r - T::one()));
+                    p / ((q - T::one()) * (r - T::one()) * (s - T::one()))
+                };
+                let limit = (T::from(1.5).unwrap() * m.abs() - tol / two).min(e.abs() / two);
+                if interpolated * m > T::zero() && interpolated.abs() < limit {
+                    e = d;
+                    step = interpolated;
+                } else {
+                    e = m;
+                }
+            } else {
+                e = m;
+            }
+            d = step;
+
+            a = b;
+            fa = fb;
+            b = if d.abs() > tol { b + d } else if m > T::zero() { b + tol } else { b - tol };
+            let (f_next, df_next) = obj.eval(b);
+            fb = f_next;
+            dfb = df_next;
+        }
+
+        obj.finish(b, fb, self.max_iter, Termination::MaxIterations)
+    }
+}
+
+impl<T: Float> Default for RootFinder<T> {
+    fn default() -> Self {
+        RootFinder::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::{Container, Context, LeafVar};
+    use ::functions::*;
+
+    #[test]
+    fn test_newton_and_halley() {
+        let mut c = Context::new();
+        let x = c.create_variable(3.0);
+        let mut g = Gradient::of(x * x * x - x - x - Container::new(LeafVar(5.0)), c);
+
+        let newton = RootFinder::new().newton(&mut g, &x);
+        *g.get_mut(&x) = 3.0;
+        let halley = RootFinder::new().halley(&mut g, &x);
+
+        let root = 2.0945514815423265;
+        for s in &[newton, halley] {
+            assert!(s.converged(), "{:?}", s);
+            assert!((s.root - root).abs() < 1e-12);
+        }
+        assert!(halley.iterations < newton.iterations);
+    }
+
+    #[test]
+    fn test_safeguards() {
+        // Plain Newton on atan diverges from |x| > 1.39
+        let mut c = Context::new();
+        let x = c.create_variable(3.0);
+        let mut g = Gradient::of(atan(x), c);
+        let s = RootFinder::new().newton(&mut g, &x);
+        assert!(s.converged() && s.root.abs() < 1e-12, "{:?}", s);
+
+        // x^2 + 1 has no root, and the derivative vanishes at its minimum
+        let mut c = Context::new();
+        let x = c.create_variable(0.0);
+        let mut g = Gradient::of(x * x + Container::new(LeafVar(1.0)), c);
+        let s = RootFinder::new().newton(&mut g, &x);
+        assert_eq!(s.termination, Termination::ZeroDerivative);
+        assert!(!s.converged());
+
+        let s = RootFinder::new().bracketed(&mut g, &x, (-1.0, 2.0));
+        assert_eq!(s.termination, Termination::NotBracketed);
+    }
+
+    #[test]
+    fn test_bracketed() {
+        // Newton from 0 would jump far outside the bracket
+        let mut c = Context::new();
+        let x = c.create_variable(0.0);
+        let mut g = Gradient::of(tanh(Container::new(LeafVar(4.0)) * (x - Container::new(LeafVar(0.3)))), c);
+        let s = RootFinder::new().bracketed(&mut g, &x, (-2.0, 3.0));
+        assert!(s.converged(), "{:?}", s);
+        assert!((s.root - 0.3).abs() < 1e-12);
+        assert_eq!(*g.get(&x), s.root);
+
+        // A root without a usable derivative
+        let mut c = Context::new();
+        let x = c.create_variable(0.0);
+        let mut g = Gradient::of(cbrt(x - Container::new(LeafVar(1.0))), c);
+        let s = RootFinder::new().max_iter(200).bracketed(&mut g, &x, (0.0, 5.0));
+        assert!(s.converged(), "{:?}", s);
+        assert!((s.root - 1.0).abs() < 1e-10);
+    }
+}
@@ -0,0 +1,263 @@
+//! Symbolic module
+//!
+//! Computes derivatives as new expressions rather than as values.
+//!
+//! Where `Gradient` returns the value of a derivative at the current
+//! point in a `Context`, `derivative` returns an expression for the
+//! derivative itself. This can be simplified, printed, evaluated
+//! cheaply with `Term::value` or differentiated again.
+//!
+//! # Example
+//!
+//! ```
+//! use rugrads::{Context, Gradient};
+//! use rugrads::functions::*;
+//! use rugrads::symbolic::derivative;
+//!
+//! let mut context = Context::new();
+//! let x = context.create_variable(0.5);
+//! let f = x * sin(x);
+//!
+//! // sin(x) + x * cos(x)
+//! let df = derivative(&f, &x);
+//! println!("{}", df.inner());
+//!
+//! // The second derivative
+//! let d2f = derivative(&df, &x);
+//! let mut grad = Gradient::of(d2f, context);
+//! ```
+
+use num::Float;
+
+use ::{Container, Expression, Variable};
+use ::simplify::Simplifier;
+use ::term::{Term, ToTerm};
+
+fn boxed<T>(t: Term<T>) -> Box<Term<T>> {
+    Box::new(t)
+}
+
+impl<T: Float> Term<T> {
+    /// Returns the unsimplified derivative of the term
+    ///
+    /// Like back propagation, this leaves out the terms of the chain rule
+    /// for arguments which do not contain `wrt`, rather than multiplying
+    /// their partial derivative by zero. This keeps an infinite partial
+    /// from turning the result into NaN.
+    pub fn diff(&self, wrt: &Variable) -> Term<T> {
+        if !self.contains(wrt) {
+            return Term::Const(T::zero());
+        }
+
+        match *self {
+            Term::Var(_) => Term::Const(T::one()),
+            Term::Const(_) => Term::Const(T::zero()),
+            Term::Add(ref x, ref y) => Term::Add(boxed(x.diff(wrt)), boxed(y.diff(wrt))),
+            Term::Sub(ref x, ref y) => Term::Sub(boxed(x.diff(wrt)), boxed(y.diff(wrt))),
+            Term::Mul(ref x, ref y) => {
+                let dx = || Term::Mul(boxed(x.diff(wrt)), y.clone());
+                let dy = || Term::Mul(x.clone(), boxed(y.diff(wrt)));
+                match (x.contains(wrt), y.contains(wrt)) {
+                    (true, false) => dx(),
+                    (false, true) => dy(),
+                    _ => Term::Add(boxed(dx()), boxed(dy())),
+                }
+            },
+            Term::Div(ref x, ref y) => {
+                let dx = || Term::Div(boxed(x.diff(wrt)), y.clone());
+                let dy = || {
+                    let y_sq = Term::Mul(y.clone(), y.clone());
+                    Term::Div(boxed(Term::Mul(x.clone(), boxed(y.diff(wrt)))), boxed(y_sq))
+                };
+                match (x.contains(wrt), y.contains(wrt)) {
+                    (true, false) => dx(),
+                    (false, true) => Term::Neg(boxed(dy())),
+                    _ => Term::Sub(boxed(dx()), boxed(dy())),
+                }
+            },
+            Term::Neg(ref x) => Term::Neg(boxed(x.diff(wrt))),
+            Term::Powf(ref x, n) => {
+                let outer = Term::Mul(boxed(Term::Const(n)), boxed(Term::Powf(x.clone(), n - T::one())));
+                Term::Mul(boxed(outer), boxed(x.diff(wrt)))
+            },
+            Term::Apply(f, ref x) => {
+                Term::Mul(boxed(f.deriv_term((**x).clone())), boxed(x.diff(wrt)))
+            },
+            Term::Apply2(f, ref x, ref y) => {
+                let (dfx, dfy) = f.deriv_term((**x).clone(), (**y).clone());
+                let dx = || Term::Mul(boxed(dfx.clone()), boxed(x.diff(wrt)));
+                let dy = || Term::Mul(boxed(dfy.clone()), boxed(y.diff(wrt)));
+                match (x.contains(wrt), y.contains(wrt)) {
+                    (true, false) => dx(),
+                    (false, true) => dy(),
+                    _ => Term::Add(boxed(dx()), boxed(dy())),
+                }
+            },
+        }
+    }
+}
+
+impl<T: Clone> Term<T> {
+    /// Replaces every occurrence of `var` with `replacement`
+    pub fn substitute(&self, var: &Variable, replacement: &Term<T>) -> Term<T> {
+        match *self {
+            Term::Var(v) if v == *var => replacement.clone(),
+            _ => self.clone().map_args(|a| a.substitute(var, replacement)),
+        }
+    }
+}
+
+/// Returns the derivative of an expression with respect to a `Variable`
+///
+/// The derivative is simplified using the default `Simplifier` rules.
+pub fn derivative<T, E>(expr: &Container<T, E>, wrt: &Variable) -> Container<T, Term<T>>
+    where T: Float + 'static,
+          E: Expression<T> + ToTerm<T>
+{
+    Container::new(Simplifier::new().simplify(expr.to_term().diff(wrt)))
+}
+
+/// Replaces every occurrence of `var` in an expression with another expression
+pub fn substitute<T, E, S>(expr: &Container<T, E>, var: &Variable, replacement: &Container<T, S>)
+    -> Container<T, Term<T>>
+    where T: Float,
+          E: Expression<T> + ToTerm<T>,
+          S: Expression<T> + ToTerm<T>
+{
+    Container::new(expr.to_term().substitute(var, &replacement.to_term()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::{Context, Expression, Gradient, LeafVar};
+    use ::functions::*;
+
+    #[test]
+    fn test_derivative_matches_grad() {
+        let mut c = Context::new();
+        let x = c.create_variable(0.5);
+        let y = c.create_variable(0.3);
+        let f = y * sin(x) + cos(y) / exp(x) - powf(ln(y), 3.0);
+
+        let dx = derivative(&f, &x);
+        let dy = derivative(&f, &y);
+
+        let mut g = Gradient::of(f, c);
+        let (gx, gy) = (g.grad(&x), g.grad(&y));
+        assert!((dx.eval(g.context()).value - gx).abs() < 1e-10);
+        assert!((dy.inner().value(g.context()) - gy).abs() < 1e-10);
+    }
+
+    #[test]
+    fn test_elementary_functions() {
+        let mut c = Context::new();
+        let x = c.create_variable(0.5);
+        let y = c.create_variable(-0.3);
+        let f = atan2(y, x) * asin(y) + tan(x) / cosh(y) - atanh(y) * asinh(x)
+            + hypot(x, y) * log(x, exp2(y)) - cbrt(y) * sqrt(x)
+            + max(x, y) * abs(y) - min(x, y) * relu(x) + floor(x) * y
+            + pow(x, y) * powi(y, 3);
+
+        let dx = derivative(&f, &x);
+        let dy = derivative(&f, &y);
+
+        let mut g = Gradient::of(f, c);
+        let (gx, gy) = (g.grad(&x), g.grad(&y));
+        assert!((dx.inner().value(g.context()) - gx).abs() < 1e-10);
+        assert!((dy.inner().value(g.context()) - gy).abs() < 1e-10);
+    }
+
+    #[test]
+    fn test_unused_infinite_partial() {
+        // d/dy of exp(x) * ln(y) has no `d/dy exp(x) * ln(y)` term, which
+        // would be `0 * -inf` at y = 0
+        let mut c = Context::new();
+        let x = c.create_variable(0.5);
+        let y = c.create_variable(0.0);
+        let f = exp(x) * ln(y);
+
+        let dy = derivative(&f, &y);
+        let mut g = Gradient::of(f, c);
+        assert_eq!(g.grad(&y), f64::INFINITY);
+        assert_eq!(dy.inner().value(g.context()), g.grad(&y));
+    }
+
+    #[test]
+    fn test_pow_at_zero() {
+        // The partials of pow are zero where the textbook formulas give
+        // `0 * inf`
+        for &(x0, y0) in &[(0.0, 2.0), (0.0, 0.0), (0.0, 0.5), (2.0, 0.0), (1.5, -2.0)] {
+            let mut c = Context::new();
+            let x = c.create_variable(x0);
+            let y = c.create_variable(y0);
+            let f = pow(x, y);
+
+            let dx = derivative(&f, &x);
+            let dy = derivative(&f, &y);
+
+            let mut g = Gradient::of(f, c);
+            let (gx, gy) = (g.grad(&x), g.grad(&y));
+            assert_eq!(dx.inner().value(g.context()), gx, "x = {}, y = {}", x0, y0);
+            assert_eq!(dy.inner().value(g.context()), gy, "x = {}, y = {}", x0, y0);
+        }
+    }
+
+    #[test]
+    fn test_derivative_simplified() {
+        let mut c = Context::new();
+        let x = c.create_variable(0.5);
+        let f = sin(x);
+
+        let df = derivative(&f, &x);
+        assert_eq!(format!("{}", df.inner()), "cos(x0)");
+    }
+
+    #[test]
+    fn test_gamma_derivatives() {
+        let mut c = Context::new();
+        let x = c.create_variable(2.5);
+        let f = lgamma(x);
+
+        let df = derivative(&f, &x);
+        let d3f = derivative(&derivative(&df, &x), &x);
+        assert_eq!(format!("{}", df.inner()), "digamma(x0)");
+        assert_eq!(format!("{}", d3f.inner()), "polygamma(2, x0)");
+
+        let mut g = Gradient::of(derivative(&d3f, &x), c);
+        assert!((g.value() - ::special::polygamma(3, 2.5)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_second_derivative() {
+        let mut c = Context::new();
+        let x = c.create_variable(0.5);
+        let f = x * sin(x);
+
+        let df = derivative(&f, &x);
+        let d2f = derivative(&df, &x);
+        let expected = 2.0 * f64::cos(0.5) - 0.5 * f64::sin(0.5);
+        assert!((d2f.inner().value(&c) - expected).abs() < 1e-10);
+
+        // Taking the gradient of the derivative gives the same value
+        let mut g = Gradient::of(df, c);
+        assert!((g.grad(&x) - expected).abs() < 1e-10);
+    }
+
+    #[test]
+    fn test_substitute() {
+        let mut c = Context::new();
+        let x = c.create_variable(0.5);
+        let y = c.create_variable(2.0);
+        let f = sin(x) * x;
+
+        // Replace x by 2y
+        let s = substitute(&f, &x, &(Container::new(LeafVar(2.0)) * y));
+        assert!(!s.inner().contains(&x));
+        assert!((s.eval(&mut c).value - 4.0 * f64::sin(4.0)).abs() < 1e-10);
+
+        let mut g = Gradient::of(s, c);
+        let expected = 2.0 * (f64::sin(4.0) + 4.0 * f64::cos(4.0));
+        assert!((g.grad(&y) - expected).abs() < 1e-10);
+    }
+}
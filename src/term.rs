@@ -0,0 +1,790 @@
+//! Term module
+//!
+//! A `Term` is an expression whose structure is decided at runtime
+//! instead of being encoded in the type of a `Container`. Any expression
+//! built from the `functions` module can be converted into a `Term` using
+//! the `ToTerm` trait, after which it can be inspected and rewritten.
+
+use std::fmt;
+
+use num::Float;
+
+use ::{Node, Context, Expression, VecJacProduct, Variable, LeafVar, Container};
+use ::special;
+
+/// A univariate function which can be applied to a `Term`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Func {
+    /// Sine
+    Sin,
+    /// Cosine
+    Cos,
+    /// Exponential
+    Exp,
+    /// Natural Logarithm
+    Ln,
+    /// Tangent
+    Tan,
+    /// Inverse Sine
+    Asin,
+    /// Inverse Cosine
+    Acos,
+    /// Inverse Tangent
+    Atan,
+    /// Hyperbolic Sine
+    Sinh,
+    /// Hyperbolic Cosine
+    Cosh,
+    /// Hyperbolic Tangent
+    Tanh,
+    /// Inverse Hyperbolic Sine
+    Asinh,
+    /// Inverse Hyperbolic Cosine
+    Acosh,
+    /// Inverse Hyperbolic Tangent
+    Atanh,
+    /// Square Root
+    Sqrt,
+    /// Cube Root
+    Cbrt,
+    /// Base 2 Logarithm
+    Log2,
+    /// Base 10 Logarithm
+    Log10,
+    /// Base 2 Exponential
+    Exp2,
+    /// `exp(x) - 1`
+    ExpM1,
+    /// `ln(1 + x)`
+    Ln1p,
+    /// Error Function
+    Erf,
+    /// Complementary Error Function
+    Erfc,
+    /// Gamma Function
+    Gamma,
+    /// Logarithm of the absolute value of the Gamma Function
+    Lgamma,
+    /// Digamma Function
+    Digamma,
+    /// Trigamma Function
+    Trigamma,
+    /// Polygamma Function of the given order, which is at least two
+    ///
+    /// This appears in derivatives of `Trigamma`. It is written with its
+    /// order and so is not found by `Func::from_name`.
+    Polygamma(u32),
+    /// Absolute Value
+    Abs,
+    /// Sign, which is zero at zero
+    Sign,
+    /// Largest integer not greater than the argument
+    Floor,
+    /// Smallest integer not less than the argument
+    Ceil,
+    /// Nearest integer, rounding half way cases away from zero
+    Round,
+    /// Rectified Linear Unit, `max(x, 0)`
+    Relu,
+    /// Integer power
+    ///
+    /// This is written with its exponent and so is not found by
+    /// `Func::from_name`.
+    Powi(i32),
+}
+
+// The sign with `sign(0) = 0`, unlike `Float::signum`
+fn sign<T: Float>(x: T) -> T {
+    if x > T::zero() {
+        T::one()
+    } else if x < T::zero() {
+        -T::one()
+    } else {
+        x
+    }
+}
+
+// The derivative of `max(x, 0)`, split evenly at the tie
+fn step<T: Float>(x: T) -> T {
+    (sign(x) + T::one()) / (T::one() + T::one())
+}
+
+impl Func {
+    /// The name of the function
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Func::Sin => "sin",
+            Func::Cos => "cos",
+            Func::Exp => "exp",
+            Func::Ln => "ln",
+            Func::Tan => "tan",
+            Func::Asin => "asin",
+            Func::Acos => "acos",
+            Func::Atan => "atan",
+            Func::Sinh => "sinh",
+            Func::Cosh => "cosh",
+            Func::Tanh => "tanh",
+            Func::Asinh => "asinh",
+            Func::Acosh => "acosh",
+            Func::Atanh => "atanh",
+            Func::Sqrt => "sqrt",
+            Func::Cbrt => "cbrt",
+            Func::Log2 => "log2",
+            Func::Log10 => "log10",
+            Func::Exp2 => "exp2",
+            Func::ExpM1 => "exp_m1",
+            Func::Ln1p => "ln_1p",
+            Func::Erf => "erf",
+            Func::Erfc => "erfc",
+            Func::Gamma => "gamma",
+            Func::Lgamma => "lgamma",
+            Func::Digamma => "digamma",
+            Func::Trigamma => "trigamma",
+            Func::Polygamma(_) => "polygamma",
+            Func::Abs => "abs",
+            Func::Sign => "sign",
+            Func::Floor => "floor",
+            Func::Ceil => "ceil",
+            Func::Round => "round",
+            Func::Relu => "relu",
+            Func::Powi(_) => "powi",
+        }
+    }
+
+    /// Looks up a function by its name
+    pub fn from_name(name: &str) -> Option<Func> {
+        match name {
+            "sin" => Some(Func::Sin),
+            "cos" => Some(Func::Cos),
+            "exp" => Some(Func::Exp),
+            "ln" => Some(Func::Ln),
+            "tan" => Some(Func::Tan),
+            "asin" => Some(Func::Asin),
+            "acos" => Some(Func::Acos),
+            "atan" => Some(Func::Atan),
+            "sinh" => Some(Func::Sinh),
+            "cosh" => Some(Func::Cosh),
+            "tanh" => Some(Func::Tanh),
+            "asinh" => Some(Func::Asinh),
+            "acosh" => Some(Func::Acosh),
+            "atanh" => Some(Func::Atanh),
+            "sqrt" => Some(Func::Sqrt),
+            "cbrt" => Some(Func::Cbrt),
+            "log2" => Some(Func::Log2),
+            "log10" => Some(Func::Log10),
+            "exp2" => Some(Func::Exp2),
+            "exp_m1" => Some(Func::ExpM1),
+            "ln_1p" => Some(Func::Ln1p),
+            "erf" => Some(Func::Erf),
+            "erfc" => Some(Func::Erfc),
+            "gamma" => Some(Func::Gamma),
+            "lgamma" => Some(Func::Lgamma),
+            "digamma" => Some(Func::Digamma),
+            "trigamma" => Some(Func::Trigamma),
+            "abs" => Some(Func::Abs),
+            "sign" => Some(Func::Sign),
+            "floor" => Some(Func::Floor),
+            "ceil" => Some(Func::Ceil),
+            "round" => Some(Func::Round),
+            "relu" => Some(Func::Relu),
+            _ => None,
+        }
+    }
+
+    /// Applies the function to a value
+    pub fn apply<T: Float>(&self, x: T) -> T {
+        match *self {
+            Func::Sin => x.sin(),
+            Func::Cos => x.cos(),
+            Func::Exp => x.exp(),
+            Func::Ln => x.ln(),
+            Func::Tan => x.tan(),
+            Func::Asin => x.asin(),
+            Func::Acos => x.acos(),
+            Func::Atan => x.atan(),
+            Func::Sinh => x.sinh(),
+            Func::Cosh => x.cosh(),
+            Func::Tanh => x.tanh(),
+            Func::Asinh => x.asinh(),
+            Func::Acosh => x.acosh(),
+            Func::Atanh => x.atanh(),
+            Func::Sqrt => x.sqrt(),
+            Func::Cbrt => x.cbrt(),
+            Func::Log2 => x.log2(),
+            Func::Log10 => x.log10(),
+            Func::Exp2 => x.exp2(),
+            Func::ExpM1 => x.exp_m1(),
+            Func::Ln1p => x.ln_1p(),
+            Func::Erf => special::erf(x),
+            Func::Erfc => special::erfc(x),
+            Func::Gamma => special::gamma(x),
+            Func::Lgamma => special::lgamma(x),
+            Func::Digamma => special::digamma(x),
+            Func::Trigamma => special::trigamma(x),
+            Func::Polygamma(n) => special::polygamma(n, x),
+            Func::Abs => x.abs(),
+            Func::Sign => sign(x),
+            Func::Floor => x.floor(),
+            Func::Ceil => x.ceil(),
+            Func::Round => x.round(),
+            Func::Relu => if x > T::zero() { x } else { T::zero() },
+            Func::Powi(n) => x.powi(n),
+        }
+    }
+
+    /// The derivative of the function at a value
+    pub fn deriv<T: Float>(&self, x: T) -> T {
+        match *self {
+            Func::Sin => x.cos(),
+            Func::Cos => -x.sin(),
+            Func::Exp => x.exp(),
+            Func::Ln => x.recip(),
+            Func::Tan => x.cos().powi(2).recip(),
+            Func::Asin => (T::one() - x * x).sqrt().recip(),
+            Func::Acos => -(T::one() - x * x).sqrt().recip(),
+            Func::Atan => (T::one() + x * x).recip(),
+            Func::Sinh => x.cosh(),
+            Func::Cosh => x.sinh(),
+            Func::Tanh => T::one() - x.tanh().powi(2),
+            Func::Asinh => (x * x + T::one()).sqrt().recip(),
+            Func::Acosh => (x * x - T::one()).sqrt().recip(),
+            Func::Atanh => (T::one() - x * x).recip(),
+            Func::Sqrt => (x.sqrt() + x.sqrt()).recip(),
+            Func::Cbrt => (T::from(3).unwrap() * x.cbrt().powi(2)).recip(),
+            Func::Log2 => (x * T::from(::std::f64::consts::LN_2).unwrap()).recip(),
+            Func::Log10 => (x * T::from(::std::f64::consts::LN_10).unwrap()).recip(),
+            Func::Exp2 => x.exp2() * T::from(::std::f64::consts::LN_2).unwrap(),
+            Func::ExpM1 => x.exp(),
+            Func::Ln1p => (T::one() + x).recip(),
+            Func::Erf => T::from(2.0 / ::std::f64::consts::PI.sqrt()).unwrap() * (-x * x).exp(),
+            Func::Erfc => -T::from(2.0 / ::std::f64::consts::PI.sqrt()).unwrap() * (-x * x).exp(),
+            Func::Gamma => special::gamma(x) * special::digamma(x),
+            Func::Lgamma => special::digamma(x),
+            Func::Digamma => special::trigamma(x),
+            Func::Trigamma => special::polygamma(2, x),
+            Func::Polygamma(n) => special::polygamma(n + 1, x),
+            Func::Abs => sign(x),
+            Func::Sign | Func::Floor | Func::Ceil | Func::Round => T::zero(),
+            Func::Relu => step(x),
+            Func::Powi(0) => T::zero(),
+            Func::Powi(n) => match n.checked_sub(1) {
+                Some(m) => T::from(n).unwrap() * x.powi(m),
+                // `i32::MIN - 1` does not fit in an `i32`
+                None => T::from(n).unwrap() * x.powf(T::from(n).unwrap() - T::one()),
+            },
+        }
+    }
+
+    /// The derivative of the function as a `Term` in its argument
+    pub fn deriv_term<T: Float>(&self, x: Term<T>) -> Term<T> {
+        let one = || Box::new(Term::Const(T::one()));
+        let neg_half = T::from(-0.5).unwrap();
+        let ln_2 = T::from(::std::f64::consts::LN_2).unwrap();
+        let ln_10 = T::from(::std::f64::consts::LN_10).unwrap();
+        let x_sq = Box::new(Term::Mul(Box::new(x.clone()), Box::new(x.clone())));
+        let x = Box::new(x);
+        match *self {
+            Func::Sin => Term::Apply(Func::Cos, x),
+            Func::Cos => Term::Neg(Box::new(Term::Apply(Func::Sin, x))),
+            Func::Exp => Term::Apply(Func::Exp, x),
+            Func::Ln => Term::Div(one(), x),
+            Func::Tan => {
+                let cos_sq = Term::Powf(Box::new(Term::Apply(Func::Cos, x)), T::one() + T::one());
+                Term::Div(one(), Box::new(cos_sq))
+            },
+            Func::Asin => Term::Powf(Box::new(Term::Sub(one(), x_sq)), neg_half),
+            Func::Acos => Term::Neg(Box::new(Term::Powf(Box::new(Term::Sub(one(), x_sq)), neg_half))),
+            Func::Atan => Term::Div(one(), Box::new(Term::Add(one(), x_sq))),
+            Func::Sinh => Term::Apply(Func::Cosh, x),
+            Func::Cosh => Term::Apply(Func::Sinh, x),
+            Func::Tanh => {
+                let tanh_sq = Term::Powf(Box::new(Term::Apply(Func::Tanh, x)), T::one() + T::one());
+                Term::Sub(one(), Box::new(tanh_sq))
+            },
+            Func::Asinh => Term::Powf(Box::new(Term::Add(x_sq, one())), neg_half),
+            Func::Acosh => Term::Powf(Box::new(Term::Sub(x_sq, one())), neg_half),
+            Func::Atanh => Term::Div(one(), Box::new(Term::Sub(one(), x_sq))),
+            Func::Sqrt => Term::Div(Box::new(Term::Const(-neg_half)), Box::new(Term::Apply(Func::Sqrt, x))),
+            Func::Cbrt => {
+                let cbrt_sq = Term::Powf(Box::new(Term::Apply(Func::Cbrt, x)), T::one() + T::one());
+                Term::Div(Box::new(Term::Const(T::from(3).unwrap().recip())), Box::new(cbrt_sq))
+            },
+            Func::Log2 => Term::Div(Box::new(Term::Const(ln_2.recip())), x),
+            Func::Log10 => Term::Div(Box::new(Term::Const(ln_10.recip())), x),
+            Func::Exp2 => Term::Mul(Box::new(Term::Const(ln_2)), Box::new(Term::Apply(Func::Exp2, x))),
+            Func::ExpM1 => Term::Apply(Func::Exp, x),
+            Func::Ln1p => Term::Div(one(), Box::new(Term::Add(one(), x))),
+            Func::Erf | Func::Erfc => {
+                let scale = T::from(2.0 / ::std::f64::consts::PI.sqrt()).unwrap();
+                let scale = if *self == Func::Erf { scale } else { -scale };
+                let gauss = Term::Apply(Func::Exp, Box::new(Term::Neg(x_sq)));
+                Term::Mul(Box::new(Term::Const(scale)), Box::new(gauss))
+            },
+            Func::Gamma => Term::Mul(Box::new(Term::Apply(Func::Gamma, x.clone())),
+                                     Box::new(Term::Apply(Func::Digamma, x))),
+            Func::Lgamma => Term::Apply(Func::Digamma, x),
+            Func::Digamma => Term::Apply(Func::Trigamma, x),
+            Func::Trigamma => Term::Apply(Func::Polygamma(2), x),
+            Func::Polygamma(n) => Term::Apply(Func::Polygamma(n + 1), x),
+            Func::Abs => Term::Apply(Func::Sign, x),
+            Func::Sign | Func::Floor | Func::Ceil | Func::Round => Term::Const(T::zero()),
+            Func::Relu => {
+                let half = Box::new(Term::Const(T::from(0.5).unwrap()));
+                Term::Mul(half, Box::new(Term::Add(Box::new(Term::Apply(Func::Sign, x)), one())))
+            },
+            Func::Powi(0) => Term::Const(T::zero()),
+            Func::Powi(n) => {
+                let pow = match n.checked_sub(1) {
+                    Some(m) => Term::Apply(Func::Powi(m), x),
+                    None => Term::Powf(x, T::from(n).unwrap() - T::one()),
+                };
+                Term::Mul(Box::new(Term::Const(T::from(n).unwrap())), Box::new(pow))
+            },
+        }
+    }
+}
+
+/// A bivariate function which can be applied to two `Term`s
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Func2 {
+    /// Four quadrant inverse tangent, `atan2(y, x)`
+    Atan2,
+    /// Length of the hypotenuse, `hypot(x, y)`
+    Hypot,
+    /// Logarithm to an arbitrary base, `log(x, base)`
+    Log,
+    /// Logarithm of the Beta Function, `lbeta(a, b)`
+    Lbeta,
+    /// The smaller argument
+    Min,
+    /// The larger argument
+    Max,
+    /// Power with an expression exponent, `pow(base, exponent)`
+    Pow,
+}
+
+impl Func2 {
+    /// The name of the function
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Func2::Atan2 => "atan2",
+            Func2::Hypot => "hypot",
+            Func2::Log => "log",
+            Func2::Lbeta => "lbeta",
+            Func2::Min => "min",
+            Func2::Max => "max",
+            Func2::Pow => "pow",
+        }
+    }
+
+    /// Looks up a function by its name
+    pub fn from_name(name: &str) -> Option<Func2> {
+        match name {
+            "atan2" => Some(Func2::Atan2),
+            "hypot" => Some(Func2::Hypot),
+            "log" => Some(Func2::Log),
+            "lbeta" => Some(Func2::Lbeta),
+            "min" => Some(Func2::Min),
+            "max" => Some(Func2::Max),
+            "pow" => Some(Func2::Pow),
+            _ => None,
+        }
+    }
+
+    /// Applies the function to two values
+    pub fn apply<T: Float>(&self, x: T, y: T) -> T {
+        match *self {
+            Func2::Atan2 => x.atan2(y),
+            Func2::Hypot => x.hypot(y),
+            Func2::Log => x.log(y),
+            Func2::Lbeta => special::lbeta(x, y),
+            Func2::Min => x.min(y),
+            Func2::Max => x.max(y),
+            Func2::Pow => x.powf(y),
+        }
+    }
+
+    /// The partial derivatives of the function with respect to each argument
+    pub fn deriv<T: Float>(&self, x: T, y: T) -> (T, T) {
+        match *self {
+            Func2::Atan2 => {
+                let r_sq = x * x + y * y;
+                (y / r_sq, -x / r_sq)
+            },
+            Func2::Hypot => {
+                // Use the zero subgradient at the origin
+                let r = x.hypot(y);
+                if r == T::zero() {
+                    (T::zero(), T::zero())
+                } else {
+                    (x / r, y / r)
+                }
+            },
+            Func2::Log => {
+                let ln_base = y.ln();
+                ((x * ln_base).recip(), -x.log(y) / (y * ln_base))
+            },
+            Func2::Lbeta => {
+                let digamma_sum = special::digamma(x + y);
+                (special::digamma(x) - digamma_sum, special::digamma(y) - digamma_sum)
+            },
+            Func2::Min => (step(y - x), step(x - y)),
+            Func2::Max => (step(x - y), step(y - x)),
+            Func2::Pow => {
+                // Avoid `0 * inf` for a zero exponent or a zero result
+                let dx = if y == T::zero() { T::zero() } else { y * x.powf(y - T::one()) };
+                let value = x.powf(y);
+                let dy = if value == T::zero() { T::zero() } else { value * x.ln() };
+                (dx, dy)
+            },
+        }
+    }
+
+    /// The partial derivatives of the function as `Term`s in its arguments
+    pub fn deriv_term<T: Float>(&self, x: Term<T>, y: Term<T>) -> (Term<T>, Term<T>) {
+        match *self {
+            Func2::Atan2 => {
+                let x_sq = Term::Mul(Box::new(x.clone()), Box::new(x.clone()));
+                let y_sq = Term::Mul(Box::new(y.clone()), Box::new(y.clone()));
+                let r_sq = Box::new(Term::Add(Box::new(x_sq), Box::new(y_sq)));
+                (Term::Div(Box::new(y), r_sq.clone()),
+                 Term::Neg(Box::new(Term::Div(Box::new(x), r_sq))))
+            },
+            Func2::Hypot => {
+                // Divide by r + 1 - sign(r) so the origin gets the zero
+                // subgradient rather than 0 / 0
+                let r = Box::new(Term::Apply2(Func2::Hypot, Box::new(x.clone()), Box::new(y.clone())));
+                let sign = Box::new(Term::Apply(Func::Sign, r.clone()));
+                let one = Box::new(Term::Const(T::one()));
+                let r = Box::new(Term::Add(r, Box::new(Term::Sub(one, sign))));
+                (Term::Div(Box::new(x), r.clone()), Term::Div(Box::new(y), r))
+            },
+            Func2::Log => {
+                let log = Term::Apply2(Func2::Log, Box::new(x.clone()), Box::new(y.clone()));
+                let ln_base = Box::new(Term::Apply(Func::Ln, Box::new(y.clone())));
+                let dx = Term::Div(Box::new(Term::Const(T::one())),
+                                   Box::new(Term::Mul(Box::new(x), ln_base.clone())));
+                let dy = Term::Div(Box::new(log), Box::new(Term::Mul(Box::new(y), ln_base)));
+                (dx, Term::Neg(Box::new(dy)))
+            },
+            Func2::Lbeta => {
+                let digamma_sum = Box::new(Term::Apply(Func::Digamma, Box::new(
+                    Term::Add(Box::new(x.clone()), Box::new(y.clone())))));
+                (Term::Sub(Box::new(Term::Apply(Func::Digamma, Box::new(x))), digamma_sum.clone()),
+                 Term::Sub(Box::new(Term::Apply(Func::Digamma, Box::new(y))), digamma_sum))
+            },
+            Func2::Min | Func2::Max => {
+                // step(x - y) = (sign(x - y) + 1) / 2
+                let half = Box::new(Term::Const(T::from(0.5).unwrap()));
+                let sign = Box::new(Term::Apply(Func::Sign, Box::new(Term::Sub(Box::new(x), Box::new(y)))));
+                let one = Box::new(Term::Const(T::one()));
+                let x_larger = Term::Mul(half.clone(), Box::new(Term::Add(one.clone(), sign.clone())));
+                let y_larger = Term::Mul(half, Box::new(Term::Sub(one, sign)));
+                if *self == Func2::Max { (x_larger, y_larger) } else { (y_larger, x_larger) }
+            },
+            Func2::Pow => {
+                // As in `deriv`, avoid `0 * inf` for a zero exponent or a zero
+                // result. `1 - |sign(t)|` is one when `t` is zero and zero
+                // otherwise, so adding it moves the exponent to 0 or the
+                // logarithm's argument to 1 in those cases only.
+                let one = || Box::new(Term::Const(T::one()));
+                let is_zero = |t: Term<T>| {
+                    let abs_sign = Term::Apply(Func::Abs, Box::new(Term::Apply(Func::Sign, Box::new(t))));
+                    Box::new(Term::Sub(one(), Box::new(abs_sign)))
+                };
+                let pow = Term::Apply2(Func2::Pow, Box::new(x.clone()), Box::new(y.clone()));
+                let exponent = Term::Add(Box::new(Term::Sub(Box::new(y.clone()), one())), is_zero(y.clone()));
+                let dx = Term::Mul(Box::new(y),
+                                   Box::new(Term::Apply2(Func2::Pow, Box::new(x.clone()), Box::new(exponent))));
+                let ln_arg = Term::Add(Box::new(x), is_zero(pow.clone()));
+                let dy = Term::Mul(Box::new(pow), Box::new(Term::Apply(Func::Ln, Box::new(ln_arg))));
+                (dx, dy)
+            },
+        }
+    }
+}
+
+/// A dynamically structured expression
+#[derive(Clone, Debug, PartialEq)]
+pub enum Term<T> {
+    /// A variable in the context
+    Var(Variable),
+    /// A constant value
+    Const(T),
+    /// Addition of two terms
+    Add(Box<Term<T>>, Box<Term<T>>),
+    /// Subtraction of two terms
+    Sub(Box<Term<T>>, Box<Term<T>>),
+    /// Multiplication of two terms
+    Mul(Box<Term<T>>, Box<Term<T>>),
+    /// Division of two terms
+    Div(Box<Term<T>>, Box<Term<T>>),
+    /// Negation of a term
+    Neg(Box<Term<T>>),
+    /// A term raised to a constant power
+    Powf(Box<Term<T>>, T),
+    /// A univariate function applied to a term
+    Apply(Func, Box<Term<T>>),
+    /// A bivariate function applied to two terms
+    Apply2(Func2, Box<Term<T>>, Box<Term<T>>),
+}
+
+impl<T> Term<T> {
+    /// Returns true if the term is a constant
+    pub fn is_const(&self) -> bool {
+        match *self {
+            Term::Const(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Returns true if the term does not depend on any variables
+    pub fn is_constant(&self) -> bool {
+        match *self {
+            Term::Var(_) => false,
+            Term::Const(_) => true,
+            _ => self.args().iter().all(|a| a.is_constant()),
+        }
+    }
+
+    /// Returns true if the given variable appears in the term
+    pub fn contains(&self, var: &Variable) -> bool {
+        match *self {
+            Term::Var(v) => v == *var,
+            _ => self.args().iter().any(|a| a.contains(var)),
+        }
+    }
+
+    /// Returns references to the arguments of this term
+    pub fn args(&self) -> Vec<&Term<T>> {
+        match *self {
+            Term::Var(_) | Term::Const(_) => vec![],
+            Term::Add(ref x, ref y) | Term::Sub(ref x, ref y) |
+            Term::Mul(ref x, ref y) | Term::Div(ref x, ref y) |
+            Term::Apply2(_, ref x, ref y) => vec![x, y],
+            Term::Neg(ref x) | Term::Powf(ref x, _) | Term::Apply(_, ref x) => vec![x],
+        }
+    }
+
+    /// Consumes the term and rebuilds it with each argument mapped by `f`
+    pub fn map_args<F: FnMut(Term<T>) -> Term<T>>(self, mut f: F) -> Term<T> {
+        match self {
+            Term::Var(v) => Term::Var(v),
+            Term::Const(v) => Term::Const(v),
+            Term::Add(x, y) => Term::Add(Box::new(f(*x)), Box::new(f(*y))),
+            Term::Sub(x, y) => Term::Sub(Box::new(f(*x)), Box::new(f(*y))),
+            Term::Mul(x, y) => Term::Mul(Box::new(f(*x)), Box::new(f(*y))),
+            Term::Div(x, y) => Term::Div(Box::new(f(*x)), Box::new(f(*y))),
+            Term::Neg(x) => Term::Neg(Box::new(f(*x))),
+            Term::Powf(x, n) => Term::Powf(Box::new(f(*x)), n),
+            Term::Apply(func, x) => Term::Apply(func, Box::new(f(*x))),
+            Term::Apply2(func, x, y) => Term::Apply2(func, Box::new(f(*x)), Box::new(f(*y))),
+        }
+    }
+}
+
+impl<T: Float> Term<T> {
+    /// Computes the value of the term in the given context
+    ///
+    /// Unlike `Expression::eval` this does not build a computational
+    /// graph and so cannot be used to compute gradients.
+    pub fn value(&self, c: &Context<T>) -> T {
+        match *self {
+            Term::Var(ref v) => *v.value(c),
+            Term::Const(v) => v,
+            Term::Add(ref x, ref y) => x.value(c) + y.value(c),
+            Term::Sub(ref x, ref y) => x.value(c) - y.value(c),
+            Term::Mul(ref x, ref y) => x.value(c) * y.value(c),
+            Term::Div(ref x, ref y) => x.value(c) / y.value(c),
+            Term::Neg(ref x) => -x.value(c),
+            Term::Powf(ref x, n) => x.value(c).powf(n),
+            Term::Apply(f, ref x) => f.apply(x.value(c)),
+            Term::Apply2(f, ref x, ref y) => f.apply(x.value(c), y.value(c)),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum TermVJP<T> {
+    Identity,
+    Sub,
+    Mul(T, T),
+    Div(T, T),
+    Neg,
+    Powf(T),
+    Apply(Func),
+    Apply2(Func2, T, T),
+}
+
+impl<T: Float> VecJacProduct<T> for TermVJP<T> {
+    fn vjp(&self, g: T, _: &Node<T>, x: &Node<T>, argnum: usize) -> T {
+        match (*self, argnum) {
+            (TermVJP::Identity, _) => g,
+            (TermVJP::Sub, 0) => g,
+            (TermVJP::Sub, 1) => -g,
+            (TermVJP::Mul(_, v2), 0) => g * v2,
+            (TermVJP::Mul(v1, _), 1) => g * v1,
+            (TermVJP::Div(_, v2), 0) => g / v2,
+            (TermVJP::Div(v1, v2), 1) => - g * v1 / (v2 * v2),
+            (TermVJP::Neg, _) => -g,
+            (TermVJP::Powf(n), _) => g * n * x.value.powf(n - T::one()),
+            (TermVJP::Apply(f), _) => g * f.deriv(x.value),
+            (TermVJP::Apply2(f, v1, v2), 0) => g * f.deriv(v1, v2).0,
+            (TermVJP::Apply2(f, v1, v2), 1) => g * f.deriv(v1, v2).1,
+            _ => panic!("Invalid argnum fed to Term VJP"),
+        }
+    }
+}
+
+impl<T: Float> Expression<T> for Term<T> {
+    fn eval(&self, c: &mut Context<T>) -> Node<T> {
+        let (parents, value, vjp) = match *self {
+            Term::Var(ref v) => return v.eval(c),
+            Term::Const(v) => (vec![], v, TermVJP::Identity),
+            Term::Add(ref x, ref y) => {
+                let parents = vec![x.eval(c), y.eval(c)];
+                let value = parents[0].value + parents[1].value;
+                (parents, value, TermVJP::Identity)
+            },
+            Term::Sub(ref x, ref y) => {
+                let parents = vec![x.eval(c), y.eval(c)];
+                let value = parents[0].value - parents[1].value;
+                (parents, value, TermVJP::Sub)
+            },
+            Term::Mul(ref x, ref y) => {
+                let parents = vec![x.eval(c), y.eval(c)];
+                let (v1, v2) = (parents[0].value, parents[1].value);
+                (parents, v1 * v2, TermVJP::Mul(v1, v2))
+            },
+            Term::Div(ref x, ref y) => {
+                let parents = vec![x.eval(c), y.eval(c)];
+                let (v1, v2) = (parents[0].value, parents[1].value);
+                (parents, v1 / v2, TermVJP::Div(v1, v2))
+            },
+            Term::Neg(ref x) => {
+                let parents = vec![x.eval(c)];
+                let value = -parents[0].value;
+                (parents, value, TermVJP::Neg)
+            },
+            Term::Powf(ref x, n) => {
+                let parents = vec![x.eval(c)];
+                let value = parents[0].value.powf(n);
+                (parents, value, TermVJP::Powf(n))
+            },
+            Term::Apply(f, ref x) => {
+                let parents = vec![x.eval(c)];
+                let value = f.apply(parents[0].value);
+                (parents, value, TermVJP::Apply(f))
+            },
+            Term::Apply2(f, ref x, ref y) => {
+                let parents = vec![x.eval(c), y.eval(c)];
+                let (v1, v2) = (parents[0].value, parents[1].value);
+                (parents, f.apply(v1, v2), TermVJP::Apply2(f, v1, v2))
+            },
+        };
+
+        let progenitors = Node::get_progenitors(&parents);
+        Node::new(c, value, parents, progenitors, Box::new(vjp))
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for Term<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Term::Var(v) => write!(f, "x{}", v.0),
+            Term::Const(ref v) => write!(f, "{}", v),
+            Term::Add(ref x, ref y) => write!(f, "({} + {})", x, y),
+            Term::Sub(ref x, ref y) => write!(f, "({} - {})", x, y),
+            Term::Mul(ref x, ref y) => write!(f, "({} * {})", x, y),
+            Term::Div(ref x, ref y) => write!(f, "({} / {})", x, y),
+            Term::Neg(ref x) => write!(f, "-{}", x),
+            Term::Powf(ref x, ref n) => write!(f, "powf({}, {})", x, n),
+            Term::Apply(Func::Polygamma(n), ref x) => write!(f, "polygamma({}, {})", n, x),
+            Term::Apply(Func::Powi(n), ref x) => write!(f, "powi({}, {})", x, n),
+            Term::Apply(func, ref x) => write!(f, "{}({})", func.name(), x),
+            Term::Apply2(func, ref x, ref y) => write!(f, "{}({}, {})", func.name(), x, y),
+        }
+    }
+}
+
+/// Conversion of an expression into a `Term`
+pub trait ToTerm<T> {
+    /// Returns the `Term` describing this expression
+    fn to_term(&self) -> Term<T>;
+}
+
+impl<T: Clone> ToTerm<T> for Term<T> {
+    fn to_term(&self) -> Term<T> {
+        self.clone()
+    }
+}
+
+impl<T> ToTerm<T> for Variable {
+    fn to_term(&self) -> Term<T> {
+        Term::Var(*self)
+    }
+}
+
+impl<T: Clone> ToTerm<T> for LeafVar<T> {
+    fn to_term(&self) -> Term<T> {
+        Term::Const(self.0.clone())
+    }
+}
+
+impl<T, E: Expression<T> + ToTerm<T>> ToTerm<T> for Container<T, E> {
+    fn to_term(&self) -> Term<T> {
+        self.inner.to_term()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::{Context, Expression, Gradient};
+    use ::functions::*;
+
+    #[test]
+    fn test_to_term() {
+        let mut c = Context::new();
+        let x = c.create_variable(0.5);
+        let f = sin(x) * x;
+
+        let t = f.to_term();
+        assert_eq!(t, Term::Mul(Box::new(Term::Apply(Func::Sin, Box::new(Term::Var(*x)))),
+                                Box::new(Term::Var(*x))));
+        assert_eq!(format!("{}", t), "(sin(x0) * x0)");
+    }
+
+    #[test]
+    fn test_term_eval_matches_expr() {
+        let mut c = Context::new();
+        let x = c.create_variable(0.5);
+        let y = c.create_variable(1.5);
+        let f = powf(y, 2.0) * exp(x) - cos(x) / ln(y);
+
+        let t = Container::new(f.to_term());
+        let expected = f.eval(&mut c).value;
+        assert!((t.eval(&mut c).value - expected).abs() < 1e-10);
+        assert!((t.inner().value(&c) - expected).abs() < 1e-10);
+
+        let mut c2 = Context::new();
+        c2.create_variable(0.5);
+        c2.create_variable(1.5);
+
+        let mut g1 = Gradient::of(f, c);
+        let mut g2 = Gradient::of(t, c2);
+        assert!((g1.grad(&x) - g2.grad(&x)).abs() < 1e-10);
+        assert!((g1.grad(&y) - g2.grad(&y)).abs() < 1e-10);
+    }
+
+    #[test]
+    fn test_hypot_deriv_term() {
+        let c = Context::new();
+        for &(x, y) in &[(3.0f64, 4.0), (0.0, 0.0)] {
+            let (dx, dy) = Func2::Hypot.deriv_term(Term::Const(x), Term::Const(y));
+            let (ex, ey) = Func2::Hypot.deriv(x, y);
+            assert_eq!(dx.value(&c), ex);
+            assert_eq!(dy.value(&c), ey);
+        }
+    }
+}
@@ -1,3 +1,5 @@
+//! Module providing test support functions
+
 use ::{Expression, Context, Variable, Gradient, Container};
 use std::ops::{Add, Sub, Div};
 use num;